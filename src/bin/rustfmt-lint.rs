@@ -0,0 +1,97 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![cfg(not(test))]
+#![cfg_attr(not(feature = "syntex"), feature(rustc_private))]
+
+// Prints a compiler style warning for each part of a crate which isn't
+// formatted, without changing anything. Unlike `rustfmt --check`, it succeeds
+// unless asked to deny unformatted code, so it can run alongside the build.
+
+extern crate rustfmt;
+extern crate getopts;
+
+use rustfmt::Input;
+use rustfmt::config::Config;
+use rustfmt::lint::lint;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use getopts::Options;
+
+const EXIT_SUCCESS: i32 = 0;
+// --deny was given and some code is not formatted.
+const EXIT_UNFORMATTED: i32 = 1;
+const EXIT_ERROR: i32 = 3;
+
+fn main() {
+    let args: Vec<_> = env::args().collect();
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "show this message");
+    opts.optflag("", "deny", "fail if any code is not formatted");
+    opts.optflag("",
+                 "skip-children",
+                 "don't check the out of line modules of the crate roots given");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            println!("{}", f);
+            std::process::exit(EXIT_ERROR);
+        }
+    };
+    if matches.opt_present("h") || matches.free.is_empty() {
+        println!("{}", opts.usage("Usage: rustfmt-lint [options] crate_root.rs..."));
+        std::process::exit(EXIT_SUCCESS);
+    }
+
+    let mut config = match read_default_config() {
+        Ok(config) => config,
+        Err(msg) => {
+            println!("{}", msg);
+            std::process::exit(EXIT_ERROR);
+        }
+    };
+    if matches.opt_present("skip-children") {
+        config.skip_children = true;
+    }
+
+    let mut unformatted = false;
+    for file in &matches.free {
+        match lint(Input::File(PathBuf::from(file)), &config) {
+            Ok(warnings) => {
+                for warning in &warnings {
+                    let _ = writeln!(io::stderr(), "{}", warning);
+                }
+                unformatted |= !warnings.is_empty();
+            }
+            Err(e) => {
+                let _ = writeln!(io::stderr(), "Error checking {}: {}", file, e);
+                std::process::exit(EXIT_ERROR);
+            }
+        }
+    }
+
+    if unformatted && matches.opt_present("deny") {
+        std::process::exit(EXIT_UNFORMATTED);
+    }
+    std::process::exit(EXIT_SUCCESS);
+}
+
+fn read_default_config() -> Result<Config, String> {
+    let mut def_config = String::new();
+    let mut def_config_file = try!(File::open("default.toml")
+                                       .map_err(|e| format!("Could not open default.toml: {}", e)));
+    try!(def_config_file.read_to_string(&mut def_config)
+                        .map_err(|e| format!("Could not read default.toml: {}", e)));
+    Config::try_from_toml(&def_config)
+}
@@ -9,21 +9,627 @@
 // except according to those terms.
 
 #![cfg(not(test))]
+#![cfg_attr(not(feature = "syntex"), feature(rustc_private))]
 
 extern crate rustfmt;
+extern crate getopts;
+extern crate time;
 
-use rustfmt::{WriteMode, run};
+use rustfmt::{Error, Input, WriteMode, format_input, is_formatted, run_from_stdin};
+use rustfmt::config::{self, Color, Config, Verbosity};
+use rustfmt::walk::list_rust_files;
+use rustfmt::parallel::run_files;
+use rustfmt::git::{self, changed_rust_files};
+use rustfmt::walk::is_ignored;
+use rustfmt::summary::Summary;
+use rustfmt::daemon;
+use rustfmt::markdown;
 
-use std::fs::File;
-use std::io::Read;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 
-fn main() {
-    let args: Vec<_> = std::env::args().collect();
-    let mut def_config_file = File::open("default.toml").unwrap();
+use getopts::{Matches, Options};
+
+const DEFAULT_THREADS: usize = 4;
+
+// Exit codes, see the README.
+const EXIT_SUCCESS: i32 = 0;
+// A checking mode found code whose formatting would change.
+const EXIT_DIFF: i32 = 1;
+// Some input could not be parsed.
+const EXIT_PARSE_ERROR: i32 = 2;
+// Bad options or config, or reading or writing files failed.
+const EXIT_OPERATIONAL_ERROR: i32 = 3;
+// Rustfmt crashed.
+const EXIT_INTERNAL_ERROR: i32 = 4;
+
+fn make_opts() -> Options {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "show this message");
+    opts.optflag("v", "verbose", "print each file as it is processed or skipped, with timings");
+    opts.optflag("q", "quiet", "print nothing but errors");
+    opts.optflag("", "backup", "keep a .bk copy of every file rustfmt overwrites");
+    opts.optflag("", "no-backup", "don't keep backups, even if the config asks for them");
+    opts.optopt("",
+                "backup-dir",
+                "put backup files in DIR instead of next to the originals",
+                "DIR");
+    opts.optopt("",
+                "emit",
+                "what to do with the formatted code: overwrite the files (the default), print \
+                 them to stdout, describe the changes as a diff, json or checkstyle xml, list \
+                 the files which would change, print them with code rustfmt left unformatted \
+                 replaced by Xs, or write the changes as one patch to stdout or as a FILE.patch \
+                 for each file",
+                "files|stdout|diff|json|checkstyle|list|coverage|patch|patch-files");
+    opts.optflag("",
+                 "check",
+                 "print a diff of the changes formatting would make and exit with status 1 if \
+                  there are any, same as --emit diff. With --quiet, print nothing and stop at \
+                  the first file which would change");
+    opts.optflag("l",
+                 "files-with-diff",
+                 "only print the names of files whose formatting differs, same as --emit list");
+    opts.optflag("",
+                 "skip-children",
+                 "only format the files given, don't follow `mod foo;` declarations into other \
+                  files");
+    opts.optflag("",
+                 "no-default-ignores",
+                 "when searching directories, don't skip hidden files, target directories and \
+                  files matched by .gitignore");
+    opts.optflagopt("",
+                    "changed",
+                    "only format the .rs files under the current directory git says have \
+                     changed since the merge base of REF and HEAD, or since HEAD if REF is \
+                     omitted",
+                    "REF");
+    opts.optflag("",
+                 "lines-from-diff",
+                 "read a unified diff from stdin and only format the lines it adds to .rs \
+                  files, e.g. with --check to allow no new formatting problems in a codebase \
+                  which is not yet formatted");
+    opts.optflag("",
+                 "staged",
+                 "for pre-commit hooks: format only the lines of .rs files staged for commit, \
+                  updating the index, and the working tree if it has no unstaged changes to \
+                  those files. With --check, list the files which would change instead");
+    opts.optopt("j",
+                "threads",
+                "format up to N crates at once (default 4)",
+                "N");
+    opts.optmulti("",
+                  "lines",
+                  "only format items and statements overlapping these lines, FILE may be \
+                   omitted to apply the range to all files",
+                  "[FILE:]START-END");
+    opts.optopt("",
+                "print-config",
+                "print a JSON Schema describing every config option, with its type, default \
+                 and allowed values, and exit",
+                "schema");
+    opts.optopt("",
+                "color",
+                "whether to color diffs: only when printing to a terminal (the default), \
+                 always, or never",
+                "auto|always|never");
+    opts.optflagopt("",
+                    "summary",
+                    "print statistics for the run to stderr when done, as text (the default) or \
+                     json",
+                    "text|json");
+    opts.optflag("",
+                 "verify",
+                 "before writing each file, check the formatted code parses to the same AST as \
+                  the original, and don't write it if not");
+    opts.optflag("",
+                 "check-idempotence",
+                 "format the formatted code again and report an internal error, with a diff, if \
+                  that changes anything");
+    opts.optflag("",
+                 "self-test",
+                 "format the files given without writing them, checking the result means the \
+                  same as the original and formats to itself again, and report any problems. \
+                  Implies --verify, --check-idempotence and --summary");
+    opts.optflag("",
+                 "daemon",
+                 "stay running and format code for editors, answering JSON-RPC requests read \
+                  from stdin one per line on stdout");
+    opts.optflag("",
+                 "profile",
+                 "print the time spent parsing, in each kind of rewrite, and writing to stderr");
+    opts.optflag("",
+                 "stats",
+                 "print to stderr how many of each kind of code, e.g., structs, imports and \
+                  method call chains, formatting changed, and how many rewrites failed and \
+                  why");
+    opts.optopt("",
+                "cache",
+                "remember which crates are already formatted in FILE, and skip them while they \
+                 and the config are unchanged",
+                "FILE");
+    opts.optopt("",
+                "failure-report",
+                "write a JSON list of the code rustfmt could not format and left as it was to \
+                 FILE",
+                "FILE");
+    opts
+}
+
+fn print_usage(opts: &Options) {
+    let brief = "Usage: rustfmt [options] [file.rs | file.md | dir]...\n\nFormats stdin to \
+                 stdout if no file is given. Directories are searched recursively for .rs \
+                 files. Only the ```rust code blocks of Markdown files are formatted.";
+    println!("{}", opts.usage(brief));
+}
+
+// Apply any configuration overrides from the command line.
+fn update_config(config: &mut Config, matches: &Matches) {
+    if matches.opt_present("v") {
+        config.verbosity = Verbosity::Verbose;
+    }
+    if matches.opt_present("q") {
+        config.verbosity = Verbosity::Quiet;
+    }
+    if matches.opt_present("backup") {
+        config.write_backup = true;
+    }
+    if matches.opt_present("no-backup") {
+        config.write_backup = false;
+    }
+    if let Some(dir) = matches.opt_str("backup-dir") {
+        config.backup_dir = dir;
+    }
+    if matches.opt_str("emit").map_or(false, |emit| emit == "coverage") {
+        config.coverage = true;
+    }
+    if matches.opt_present("verify") {
+        config.verify_ast = true;
+    }
+    if matches.opt_present("check-idempotence") {
+        config.check_idempotence = true;
+    }
+    if matches.opt_present("profile") {
+        config.profile = true;
+    }
+    if matches.opt_present("stats") {
+        config.stats = true;
+    }
+    if let Some(path) = matches.opt_str("cache") {
+        config.cache_file = path;
+    }
+    if matches.opt_present("self-test") {
+        config.verify_ast = true;
+        config.check_idempotence = true;
+        // Every file should be checked, including unchanged ones.
+        config.cache_file = String::new();
+    }
+    if let Some(path) = matches.opt_str("failure-report") {
+        config.failure_report = path;
+    }
+    if let Some(color) = matches.opt_str("color") {
+        config.color = match &*color {
+            "auto" => Color::Auto,
+            "always" => Color::Always,
+            "never" => Color::Never,
+            _ => {
+                println!("Unknown color setting: {}", color);
+                std::process::exit(EXIT_OPERATIONAL_ERROR);
+            }
+        };
+    }
+    if matches.opt_present("skip-children") {
+        config.skip_children = true;
+    }
+    if matches.opt_present("no-default-ignores") {
+        config.default_ignores = false;
+    }
+    for spec in matches.opt_strs("lines") {
+        if let Err(msg) = config.file_lines.add_spec(&spec) {
+            println!("{}", msg);
+            std::process::exit(EXIT_OPERATIONAL_ERROR);
+        }
+    }
+}
+
+fn determine_write_mode(matches: &Matches) -> WriteMode {
+    // The self-test leaves the files alone, but listing the ones which would
+    // change is a useful part of its report.
+    if matches.opt_present("l") || matches.opt_present("self-test") {
+        return WriteMode::List;
+    }
+    if matches.opt_present("check") {
+        return WriteMode::Diff;
+    }
+
+    match matches.opt_str("emit") {
+        None => WriteMode::Overwrite,
+        Some(ref emit) => {
+            match &**emit {
+                "files" => WriteMode::Overwrite,
+                "stdout" | "coverage" => WriteMode::Display,
+                "diff" => WriteMode::Diff,
+                "json" => WriteMode::Json,
+                "checkstyle" => WriteMode::Checkstyle,
+                "list" => WriteMode::List,
+                "patch" => WriteMode::Patch,
+                "patch-files" => WriteMode::PatchFiles,
+                _ => {
+                    println!("Unknown emit mode: {}", emit);
+                    std::process::exit(EXIT_OPERATIONAL_ERROR);
+                }
+            }
+        }
+    }
+}
+
+// Whether and how to print statistics at the end of the run: None for not at
+// all, Some(true) for JSON.
+fn determine_summary_style(matches: &Matches) -> Option<bool> {
+    if !matches.opt_present("summary") {
+        if matches.opt_present("self-test") {
+            return Some(false);
+        }
+        return None;
+    }
+
+    match matches.opt_str("summary") {
+        None => Some(false),
+        Some(ref style) => {
+            match &**style {
+                "text" => Some(false),
+                "json" => Some(true),
+                _ => {
+                    println!("Unknown summary style: {}", style);
+                    std::process::exit(EXIT_OPERATIONAL_ERROR);
+                }
+            }
+        }
+    }
+}
+
+// Print the statistics if asked for, then exit with the right code.
+fn finish(summary: Summary, summary_style: Option<bool>, start: u64) -> ! {
+    if let Some(as_json) = summary_style {
+        let elapsed_ms = (time::precise_time_ns() - start) / 1_000_000;
+        let _ = summary.write_stats(io::stderr(), elapsed_ms, as_json);
+    }
+    std::process::exit(exit_code(&summary));
+}
+
+// The self-test only fails for bugs in rustfmt, not for files which would
+// change.
+fn finish_self_test(mut summary: Summary, summary_style: Option<bool>, start: u64) -> ! {
+    if summary.has_internal_errors() {
+        println!("Self-test failed: formatting changed the meaning of some code or was not \
+                  idempotent, see the errors above");
+    } else if summary.has_operational_errors() || summary.has_parsing_errors() {
+        println!("Self-test incomplete: some files could not be read or parsed");
+    } else {
+        println!("Self-test passed");
+    }
+
+    summary.clear_diff();
+    finish(summary, summary_style, start);
+}
+
+// Formats the staged lines of each staged file, so that a partially staged
+// file has only the part about to be committed formatted. The working tree is
+// only written when it matches the index, so unstaged work is never lost.
+fn run_staged(config: &Config, check: bool) -> ! {
+    let files = match git::staged_rust_files() {
+        Ok(files) => files,
+        Err(msg) => {
+            println!("{}", msg);
+            std::process::exit(EXIT_OPERATIONAL_ERROR);
+        }
+    };
+
+    let mut code = EXIT_SUCCESS;
+    for file in files {
+        if is_ignored(Path::new(&file.name), config) {
+            continue;
+        }
+        let staged = match git::staged_text(&file.name) {
+            Ok(staged) => staged,
+            Err(msg) => {
+                println!("{}", msg);
+                std::process::exit(EXIT_OPERATIONAL_ERROR);
+            }
+        };
+
+        let mut file_config = config.clone();
+        file_config.skip_children = true;
+        for range in &file.lines {
+            file_config.file_lines.add_spec(&format!("{}-{}", range.lo, range.hi)).unwrap();
+        }
+        let formatted = match format_input(Input::Text(staged.clone()), &file_config) {
+            Ok((_, file_map, _)) => {
+                match file_map.values().next() {
+                    Some(text) => text.to_string(),
+                    None => continue,
+                }
+            }
+            Err(Error::Parse) => {
+                println!("Could not parse the staged version of {}", file.name);
+                code = EXIT_PARSE_ERROR;
+                continue;
+            }
+            Err(e) => {
+                println!("Error formatting the staged version of {}: {}", file.name, e);
+                std::process::exit(EXIT_INTERNAL_ERROR);
+            }
+        };
+        if formatted == staged {
+            continue;
+        }
+
+        if check {
+            println!("{}", file.name);
+            if code == EXIT_SUCCESS {
+                code = EXIT_DIFF;
+            }
+            continue;
+        }
+        if let Err(msg) = git::update_staged(&file.name, &formatted) {
+            println!("{}", msg);
+            std::process::exit(EXIT_OPERATIONAL_ERROR);
+        }
+        if read_file(&file.path).ok().map_or(false, |working| working == staged) {
+            if let Err(e) = File::create(&file.path)
+                                .and_then(|mut f| f.write_all(formatted.as_bytes())) {
+                println!("Error writing {}: {}", file.name, e);
+                std::process::exit(EXIT_OPERATIONAL_ERROR);
+            }
+        } else if config.verbosity != Verbosity::Quiet {
+            println!("Formatted the staged version of {}, which has unstaged changes left \
+                      unformatted",
+                     file.name);
+        }
+    }
+    std::process::exit(code);
+}
+
+fn read_file(path: &Path) -> io::Result<String> {
+    let mut text = String::new();
+    try!(try!(File::open(path)).read_to_string(&mut text));
+    Ok(text)
+}
+
+// file as the formatter takes it. None, after saying so, if the path isn't
+// UTF-8.
+fn job_path(file: &Path) -> Option<String> {
+    match file.to_str() {
+        Some(path) => Some(path.to_owned()),
+        None => {
+            println!("Skipping {}: the path is not valid UTF-8", file.display());
+            None
+        }
+    }
+}
+
+fn exit_formatted(formatted: bool) -> ! {
+    let code = if formatted {
+        EXIT_SUCCESS
+    } else {
+        EXIT_DIFF
+    };
+    std::process::exit(code);
+}
+
+fn exit_code(summary: &Summary) -> i32 {
+    if summary.has_internal_errors() {
+        EXIT_INTERNAL_ERROR
+    } else if summary.has_operational_errors() {
+        EXIT_OPERATIONAL_ERROR
+    } else if summary.has_parsing_errors() {
+        EXIT_PARSE_ERROR
+    } else if summary.has_diff() {
+        EXIT_DIFF
+    } else {
+        EXIT_SUCCESS
+    }
+}
+
+fn read_default_config() -> Result<Config, String> {
     let mut def_config = String::new();
-    def_config_file.read_to_string(&mut def_config).unwrap();
+    let mut def_config_file = try!(File::open("default.toml")
+                                       .map_err(|e| format!("Could not open default.toml: {}", e)));
+    try!(def_config_file.read_to_string(&mut def_config)
+                        .map_err(|e| format!("Could not read default.toml: {}", e)));
+    Config::try_from_toml(&def_config)
+}
+
+fn main() {
+    let start = time::precise_time_ns();
+    let args: Vec<_> = env::args().collect();
+    let opts = make_opts();
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            println!("{}", f);
+            print_usage(&opts);
+            std::process::exit(EXIT_OPERATIONAL_ERROR);
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(&opts);
+        std::process::exit(EXIT_SUCCESS);
+    }
+    if let Some(what) = matches.opt_str("print-config") {
+        if what != "schema" {
+            println!("Unknown config to print: {}", what);
+            std::process::exit(EXIT_OPERATIONAL_ERROR);
+        }
+        println!("{}", config::schema().pretty());
+        std::process::exit(EXIT_SUCCESS);
+    }
+
+    let mut config = match read_default_config() {
+        Ok(config) => config,
+        Err(msg) => {
+            println!("{}", msg);
+            std::process::exit(EXIT_OPERATIONAL_ERROR);
+        }
+    };
+    update_config(&mut config, &matches);
+    let write_mode = determine_write_mode(&matches);
+    let summary_style = determine_summary_style(&matches);
+    // Only a yes or no answer is wanted, so stop at the first difference.
+    let check_quietly = matches.opt_present("check") && config.verbosity == Verbosity::Quiet;
+
+    if matches.opt_present("daemon") {
+        let stdin = io::stdin();
+        if let Err(e) = daemon::serve(stdin.lock(), io::stdout(), &config) {
+            println!("Error in daemon: {}", e);
+            std::process::exit(EXIT_OPERATIONAL_ERROR);
+        }
+        std::process::exit(EXIT_SUCCESS);
+    }
+
+    if matches.opt_present("staged") {
+        run_staged(&config, matches.opt_present("check"));
+    }
+
+    let self_test = matches.opt_present("self-test");
+    let from_diff = matches.opt_present("lines-from-diff");
+    if self_test && matches.free.is_empty() && !matches.opt_present("changed") && !from_diff {
+        println!("--self-test needs the files or directories to check");
+        std::process::exit(EXIT_OPERATIONAL_ERROR);
+    }
+
+    // With no input file, format stdin and write the result to stdout.
+    if matches.free.is_empty() && !matches.opt_present("changed") && !from_diff {
+        let mut input = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut input) {
+            println!("Error reading stdin: {}", e);
+            std::process::exit(EXIT_OPERATIONAL_ERROR);
+        }
+        if check_quietly {
+            exit_formatted(is_formatted(Input::Text(input), &config));
+        }
+        // The other modes compare with the original file, which there isn't.
+        match write_mode {
+            WriteMode::Overwrite | WriteMode::Display => {}
+            _ => {
+                println!("Formatted stdin can only be written to stdout");
+                std::process::exit(EXIT_OPERATIONAL_ERROR);
+            }
+        }
+        let summary = run_from_stdin(input, WriteMode::Plain, &config);
+        finish(summary, summary_style, start);
+    } else {
+        let threads = match matches.opt_str("threads").map(|n| n.parse::<usize>()) {
+            None => DEFAULT_THREADS,
+            Some(Ok(n)) if n > 0 => n,
+            Some(_) => {
+                println!("--threads expects a positive number");
+                std::process::exit(EXIT_OPERATIONAL_ERROR);
+            }
+        };
 
-    run(args, WriteMode::Overwrite, &def_config);
+        let mut jobs = Vec::new();
+        // Files skipped because their paths can't be passed to the formatter.
+        let mut bad_paths = false;
+        if matches.opt_present("changed") {
+            let revision = matches.opt_str("changed");
+            let files = match changed_rust_files(revision.as_ref().map(|r| &**r)) {
+                Ok(files) => files,
+                Err(msg) => {
+                    println!("{}", msg);
+                    std::process::exit(EXIT_OPERATIONAL_ERROR);
+                }
+            };
+            // Only the changed files should be touched, not their modules.
+            let mut changed_config = config.clone();
+            changed_config.skip_children = true;
+            for file in files {
+                if !is_ignored(&file, &changed_config) {
+                    match job_path(&file) {
+                        Some(path) => jobs.push((path, changed_config.clone())),
+                        None => bad_paths = true,
+                    }
+                }
+            }
+        }
+        if from_diff {
+            let mut diff = String::new();
+            if let Err(e) = io::stdin().read_to_string(&mut diff) {
+                println!("Error reading stdin: {}", e);
+                std::process::exit(EXIT_OPERATIONAL_ERROR);
+            }
+            // Files with no added lines must be left out, since an empty set
+            // of ranges would allow all lines to be formatted.
+            let files: Vec<_> = git::changed_lines(&diff)
+                                    .into_iter()
+                                    .filter(|&(ref name, ref lines)| {
+                                        name.ends_with(".rs") && !lines.is_empty()
+                                    })
+                                    .collect();
+            let mut diff_config = config.clone();
+            diff_config.skip_children = true;
+            for &(ref name, ref lines) in &files {
+                for range in lines {
+                    let spec = format!("{}:{}-{}", name, range.lo, range.hi);
+                    diff_config.file_lines.add_spec(&spec).unwrap();
+                }
+            }
+            for (name, _) in files {
+                if !is_ignored(Path::new(&name), &diff_config) {
+                    jobs.push((name, diff_config.clone()));
+                }
+            }
+        }
+        for input in matches.free {
+            let is_dir = match fs::metadata(&input) {
+                Ok(metadata) => metadata.is_dir(),
+                Err(e) => {
+                    println!("Error reading {}: {}", input, e);
+                    std::process::exit(EXIT_OPERATIONAL_ERROR);
+                }
+            };
+            if is_dir {
+                // Every file is found by the walk, so don't format modules
+                // twice by also following them.
+                let mut dir_config = config.clone();
+                dir_config.skip_children = true;
+                let files = match list_rust_files(Path::new(&input), &dir_config) {
+                    Ok(files) => files,
+                    Err(e) => {
+                        println!("Error reading directory {}: {}", input, e);
+                        std::process::exit(EXIT_OPERATIONAL_ERROR);
+                    }
+                };
+                for file in files {
+                    match job_path(&file) {
+                        Some(path) => jobs.push((path, dir_config.clone())),
+                        None => bad_paths = true,
+                    }
+                }
+            } else {
+                jobs.push((input, config.clone()));
+            }
+        }
 
-    std::process::exit(0);
+        if check_quietly {
+            exit_formatted(jobs.into_iter().all(|(file, config)| {
+                if markdown::is_markdown(&file) {
+                    markdown::is_formatted(&file, &config)
+                } else {
+                    is_formatted(Input::File(PathBuf::from(file)), &config)
+                }
+            }));
+        }
+        let mut summary = run_files(jobs, write_mode, &config, threads);
+        if bad_paths {
+            summary.add_operational_error();
+        }
+        if self_test {
+            finish_self_test(summary, summary_style, start);
+        }
+        finish(summary, summary_style, start);
+    }
 }
@@ -0,0 +1,161 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Lining up the `=` of neighbouring let statements and consts in a block, e.g.,
+//
+//     let a     = 1;
+//     let abcde = 2;
+
+use std::cmp;
+
+use syntax::ast;
+use syntax::codemap::{Span, mk_sp};
+use syntax::ptr;
+use syntax::visit::Visitor;
+
+use budget::{saturating_reserve, saturating_reserve_width};
+use rewrite::Rewrite;
+use utils::{make_indent, str_width};
+use visitor::FmtVisitor;
+
+// A statement `lhs = value;` which fits on one line.
+struct Binding {
+    lhs: String,
+    value: String,
+    // From the start of the statement to the end of the value.
+    span: Span,
+}
+
+impl<'a> FmtVisitor<'a> {
+    pub fn visit_stmts(&mut self, stmts: &[ptr::P<ast::Stmt>]) {
+        if !self.config.align_bindings {
+            for stmt in stmts {
+                self.visit_stmt(stmt);
+            }
+            return;
+        }
+
+        let mut i = 0;
+        while i < stmts.len() {
+            let group = self.binding_group(&stmts[i..]);
+            if group.len() > 1 {
+                self.push_bindings(&group);
+                i += group.len();
+            } else {
+                self.visit_stmt(&stmts[i]);
+                i += 1;
+            }
+        }
+    }
+
+    // The bindings on consecutive lines at the start of stmts which can be
+    // lined up without any of them running past max_width.
+    fn binding_group(&self, stmts: &[ptr::P<ast::Stmt>]) -> Vec<Binding> {
+        let mut group = Vec::new();
+        let mut lhs_width = 0;
+        let mut value_width = 0;
+
+        for (i, stmt) in stmts.iter().enumerate() {
+            if i > 0 && !self.on_next_line(stmts[i - 1].span, stmt.span) {
+                break;
+            }
+            let binding = match self.binding(stmt) {
+                Some(binding) => binding,
+                None => break,
+            };
+
+            let tab_width = self.config.tab_width;
+            let new_lhs_width = cmp::max(lhs_width, str_width(&binding.lhs, tab_width));
+            let new_value_width = cmp::max(value_width, str_width(&binding.value, tab_width));
+            if self.block_indent + new_lhs_width + new_value_width + " = ;".len() >
+               self.config.max_width {
+                break;
+            }
+            lhs_width = new_lhs_width;
+            value_width = new_value_width;
+            group.push(binding);
+        }
+
+        group
+    }
+
+    // Whether next starts on the line after prev ends, with nothing but the
+    // semicolon in between.
+    fn on_next_line(&self, prev: Span, next: Span) -> bool {
+        let between = self.snippet(mk_sp(prev.hi, next.lo));
+        between.chars().all(|c| c == ';' || c.is_whitespace()) &&
+        between.matches('\n').count() == 1
+    }
+
+    // stmt as a binding, if it is a let statement or const with a value, no
+    // comments or attributes and fits on a line.
+    fn binding(&self, stmt: &ast::Stmt) -> Option<Binding> {
+        if self.is_out_of_order(stmt.span) || !self.in_file_lines(stmt.span) {
+            return None;
+        }
+        let (decl, keyword, value) = match stmt.node {
+            ast::Stmt_::StmtDecl(ref decl, _) => {
+                match decl.node {
+                    ast::Decl_::DeclLocal(ref local) => {
+                        match local.init {
+                            Some(ref init) => (decl, "let ", init),
+                            None => return None,
+                        }
+                    }
+                    ast::Decl_::DeclItem(ref item) if item.attrs.is_empty() => {
+                        match item.node {
+                            ast::Item_::ItemConst(_, ref expr) => (decl, "const ", expr),
+                            _ => return None,
+                        }
+                    }
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+
+        let lhs = self.snippet(mk_sp(decl.span.lo, value.span.lo));
+        let lhs = lhs.trim_right();
+        if !lhs.starts_with(keyword) || !lhs.ends_with('=') || lhs.contains('\n') ||
+           lhs.contains("//") || lhs.contains("/*") {
+            return None;
+        }
+        let lhs = lhs[..lhs.len() - 1].trim_right();
+
+        let offset = self.block_indent + str_width(lhs, self.config.tab_width) + " = ".len();
+        let width = saturating_reserve(saturating_reserve_width(self.config.max_width, offset),
+                                       ";");
+        let value_str = match value.rewrite(&self.get_context(), width, offset) {
+            Ok(ref value_str) if !value_str.contains('\n') => value_str.clone(),
+            _ => return None,
+        };
+
+        Some(Binding {
+            lhs: lhs.to_owned(),
+            value: value_str,
+            span: mk_sp(decl.span.lo, value.span.hi),
+        })
+    }
+
+    fn push_bindings(&mut self, group: &[Binding]) {
+        let tab_width = self.config.tab_width;
+        let lhs_width = group.iter().map(|b| str_width(&b.lhs, tab_width)).max().unwrap_or(0);
+
+        for binding in group {
+            self.format_missing_with_indent(binding.span.lo);
+            let offset = self.buffer.cur_offset();
+            let padding = lhs_width - str_width(&binding.lhs, tab_width);
+            let line = format!("{}{} = {}", binding.lhs, make_indent(padding), binding.value);
+            // Anything after the value, usually just the semicolon, is copied
+            // as missing code.
+            self.push_rewrite("binding", binding.span, Ok(line), offset);
+        }
+    }
+}
@@ -0,0 +1,50 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Working out how much width is left for part of a rewrite. Rather than
+// `width - 3` and a comment saying what the 3 is, reserve room for the text
+// itself, e.g., `try!(reserve(width, ">::"))`. Running out of room is a
+// WidthExhausted error rather than an underflow.
+
+use rewrite::RewriteError;
+
+// The width left after setting aside room for text.
+pub fn reserve(width: usize, text: &str) -> Result<usize, RewriteError> {
+    reserve_width(width, text.len())
+}
+
+// The width left after setting aside used columns, e.g., for the rewrite of
+// an earlier part of the code.
+pub fn reserve_width(width: usize, used: usize) -> Result<usize, RewriteError> {
+    width.checked_sub(used).ok_or(RewriteError::WidthExhausted)
+}
+
+// As reserve, but runs out at zero rather than failing. For widths which are
+// only a preference, such as how much room to try a horizontal layout in.
+pub fn saturating_reserve(width: usize, text: &str) -> usize {
+    saturating_reserve_width(width, text.len())
+}
+
+pub fn saturating_reserve_width(width: usize, used: usize) -> usize {
+    width.checked_sub(used).unwrap_or(0)
+}
+
+#[test]
+fn reserve_test() {
+    assert_eq!(reserve(10, ">::"), Ok(7));
+    assert_eq!(reserve(3, ">::"), Ok(0));
+    assert_eq!(reserve(2, ">::"), Err(RewriteError::WidthExhausted));
+    assert_eq!(reserve(0, ""), Ok(0));
+    assert_eq!(reserve_width(5, 6), Err(RewriteError::WidthExhausted));
+
+    assert_eq!(saturating_reserve(2, "()"), 0);
+    assert_eq!(saturating_reserve(1, "()"), 0);
+    assert_eq!(saturating_reserve_width(7, 2), 5);
+}
@@ -0,0 +1,135 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Remembering, between runs, which crates are already formatted so that they
+// needn't be parsed or formatted again while they and the config stay the
+// same.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{Hash, Hasher, SipHasher};
+use std::io::{self, Read, Write};
+
+use rustc_serialize::json;
+
+use config::{Color, Config, Verbosity};
+
+// Crate roots which formatting left unchanged, with the hash of the config and
+// of the contents of each file in the crate at the time.
+pub struct FormatCache {
+    path: String,
+    entries: HashMap<String, Entry>,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct Entry {
+    config: String,
+    files: Vec<(String, String)>,
+}
+
+impl FormatCache {
+    // A missing or unreadable cache file gives an empty cache.
+    pub fn load(path: &str) -> FormatCache {
+        let mut text = String::new();
+        let entries = File::open(path)
+                          .and_then(|mut file| file.read_to_string(&mut text))
+                          .ok()
+                          .and_then(|_| json::decode(&text).ok())
+                          .unwrap_or(HashMap::new());
+        FormatCache {
+            path: path.to_owned(),
+            entries: entries,
+        }
+    }
+
+    // True if root was formatted with an equivalent config, and neither it
+    // nor any of its modules have changed since.
+    pub fn is_formatted(&self, root: &str, config: &Config) -> bool {
+        match self.entries.get(root) {
+            Some(entry) => {
+                entry.config == hash_config(config) &&
+                entry.files.iter().all(|&(ref file, ref hash)| {
+                    hash_file(file).map_or(false, |h| h == *hash)
+                })
+            }
+            None => false,
+        }
+    }
+
+    // Records that formatting the crate at root, made up of files, changed
+    // nothing.
+    pub fn insert(&mut self, root: &str, config: &Config, files: &[String]) {
+        let mut hashes = Vec::new();
+        for file in files {
+            match hash_file(file) {
+                Some(hash) => hashes.push((file.clone(), hash)),
+                None => return,
+            }
+        }
+        self.entries.insert(root.to_owned(),
+                            Entry {
+                                config: hash_config(config),
+                                files: hashes,
+                            });
+    }
+
+    // Forgets root, e.g., because formatting changed it.
+    pub fn remove(&mut self, root: &str) {
+        self.entries.remove(root);
+    }
+
+    pub fn save(&self) -> Result<(), io::Error> {
+        let text = json::encode(&self.entries).unwrap();
+        let mut file = try!(File::create(&self.path));
+        file.write_all(text.as_bytes())
+    }
+}
+
+fn hash_file(path: &str) -> Option<String> {
+    let mut text = String::new();
+    match File::open(path).and_then(|mut file| file.read_to_string(&mut text)) {
+        Ok(_) => Some(hash_str(&text)),
+        Err(_) => None,
+    }
+}
+
+// Options which don't change the formatted code are left out, so that e.g.
+// `--verbose` doesn't invalidate the cache.
+fn hash_config(config: &Config) -> String {
+    let mut config = config.clone();
+    config.write_backup = false;
+    config.backup_dir = String::new();
+    config.verbosity = Verbosity::Normal;
+    config.failure_report = String::new();
+    config.color = Color::Auto;
+    config.profile = false;
+    config.stats = false;
+    config.cache_file = String::new();
+    hash_str(&format!("{:?}", config))
+}
+
+// SipHasher::new uses fixed keys, so hashes are the same from run to run.
+fn hash_str(s: &str) -> String {
+    let mut hasher = SipHasher::new();
+    s.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+#[test]
+fn hash_config_test() {
+    let config = Config::from_toml(include_str!("default.toml"));
+    let mut verbose = config.clone();
+    verbose.verbosity = Verbosity::Verbose;
+    let mut narrow = config.clone();
+    narrow.max_width = 80;
+
+    assert_eq!(hash_config(&config), hash_config(&verbose));
+    assert!(hash_config(&config) != hash_config(&narrow));
+}
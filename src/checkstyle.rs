@@ -0,0 +1,81 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Checkstyle compatible XML output, for CI tools.
+
+use std::cmp;
+use std::io::{self, Write};
+
+use rustfmt_diff::Mismatch;
+
+pub fn header<T: Write>(out: &mut T) -> Result<(), io::Error> {
+    try!(write!(out, "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n"));
+    write!(out, "<checkstyle version=\"4.3\">\n")
+}
+
+pub fn footer<T: Write>(out: &mut T) -> Result<(), io::Error> {
+    write!(out, "</checkstyle>\n")
+}
+
+// Writes one error per line which would be changed by formatting.
+pub fn write_file<T: Write>(out: &mut T,
+                            filename: &str,
+                            mismatches: &[Mismatch])
+                            -> Result<(), io::Error> {
+    try!(write!(out, "<file name=\"{}\">\n", escape(filename)));
+    for mismatch in mismatches {
+        let count = cmp::max(mismatch.original_lines.len(), mismatch.expected_lines.len());
+        for i in 0..count {
+            // Extra expected lines are reported against the last original
+            // line of the mismatch.
+            let line = mismatch.original_start_line +
+                       cmp::min(i, mismatch.original_lines.len().saturating_sub(1));
+            let message = match mismatch.expected_lines.get(i) {
+                Some(expected) => format!("Should be `{}`", expected),
+                None => "Should be removed".to_owned(),
+            };
+            try!(write!(out,
+                        "<error line=\"{}\" severity=\"warning\" message=\"{}\" />\n",
+                        line,
+                        escape(&message)));
+        }
+    }
+    write!(out, "</file>\n")
+}
+
+fn escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            '&' => result.push_str("&amp;"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+#[test]
+fn checkstyle_output() {
+    let mismatches = vec![Mismatch {
+                              original_start_line: 3,
+                              original_lines: vec!["fn  foo<T>() {}".to_owned()],
+                              expected_lines: vec!["fn foo<T>() {}".to_owned()],
+                          }];
+    let mut out = Vec::new();
+    write_file(&mut out, "a.rs", &mismatches).unwrap();
+
+    assert_eq!("<file name=\"a.rs\">\n<error line=\"3\" severity=\"warning\" message=\"Should \
+                be `fn foo&lt;T&gt;() {}`\" />\n</file>\n",
+               String::from_utf8(out).unwrap());
+}
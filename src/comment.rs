@@ -11,9 +11,15 @@
 // Format comments.
 
 use string::{StringFormat, rewrite_string};
-use utils::make_indent;
-
-pub fn rewrite_comment(orig: &str, block_style: bool, width: usize, offset: usize) -> String {
+use utils::{make_indent, str_width};
+
+// Widths are in columns, see utils::str_width.
+pub fn rewrite_comment(orig: &str,
+                       block_style: bool,
+                       width: usize,
+                       offset: usize,
+                       tab_width: usize)
+                       -> String {
     let s = orig.trim();
 
     // Edge case: block comments. Let's not trim their lines (for now).
@@ -34,6 +40,7 @@ pub fn rewrite_comment(orig: &str, block_style: bool, width: usize, offset: usiz
         width: max_chars,
         offset: offset + opener.len() - line_start.len(),
         trim_end: true,
+        tab_width: tab_width,
     };
 
     let indent_str = make_indent(offset);
@@ -65,7 +72,7 @@ pub fn rewrite_comment(orig: &str, block_style: bool, width: usize, offset: usiz
                 acc.push_str(line_start);
             }
 
-            if line.len() > max_chars {
+            if str_width(line, tab_width) > max_chars {
                 acc.push_str(&rewrite_string(line, &fmt));
             } else {
                 acc.push_str(line);
@@ -93,24 +100,27 @@ fn left_trim_comment_line<'a>(line: &'a str) -> &'a str {
 
 #[test]
 fn format_comments() {
-    assert_eq!("/* test */", rewrite_comment(" //test", true, 100, 100));
-    assert_eq!("// comment\n// on a", rewrite_comment("// comment on a", false, 10, 0));
+    assert_eq!("/* test */", rewrite_comment(" //test", true, 100, 100, 4));
+    assert_eq!("// comment\n// on a", rewrite_comment("// comment on a", false, 10, 0, 4));
 
     assert_eq!("//  A multi line comment\n            // between args.",
                rewrite_comment("//  A multi line comment\n             // between args.",
                                false,
                                60,
-                               12));
+                               12,
+                               4));
 
     let input = "// comment";
     let expected = "/* com\n                                                                      \
                     * men\n                                                                      * \
                     t */";
-    assert_eq!(expected, rewrite_comment(input, true, 9, 69));
+    assert_eq!(expected, rewrite_comment(input, true, 9, 69, 4));
 
-    assert_eq!("/* trimmed */", rewrite_comment("/*   trimmed    */", true, 100, 100));
-}
+    assert_eq!("/* trimmed */", rewrite_comment("/*   trimmed    */", true, 100, 100, 4));
 
+    // Wide characters take two columns each.
+    assert_eq!("// 名前\n// 名前", rewrite_comment("// 名前 名前", false, 9, 0, 4));
+}
 
 pub trait FindUncommented {
     fn find_uncommented(&self, pat: &str) -> Option<usize>;
@@ -127,8 +137,8 @@ impl FindUncommented for str {
                     if b != c {
                         needle_iter = pat.chars();
                     }
-                },
-                None => return Some(i - pat.len())
+                }
+                None => return Some(i - pat.len()),
             }
 
             if possible_comment && (b == '/' || b == '*') {
@@ -145,7 +155,7 @@ impl FindUncommented for str {
         // Handle case where the pattern is a suffix of the search string
         match needle_iter.next() {
             Some(_) => None,
-            None => Some(self.len() - pat.len())
+            None => Some(self.len() - pat.len()),
         }
     }
 }
@@ -10,9 +10,15 @@
 
 extern crate toml;
 
+use std::collections::BTreeMap;
+
+use rustc_serialize::json::{Json, ToJson};
+
 use {NewlineStyle, BraceStyle, ReturnIndent, StructLitStyle};
-use lists::SeparatorTactic;
+use lists::{SeparatorPlace, SeparatorTactic};
 use issues::ReportTactic;
+use file_lines::FileLines;
+use utils;
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum BlockIndentStyle {
@@ -26,12 +32,101 @@ pub enum BlockIndentStyle {
 
 impl_enum_decodable!(BlockIndentStyle, Inherit, Tabbed, Visual);
 
-#[derive(RustcDecodable, Clone)]
+// Where the continuation lines of a wrapped `if`, `while` or `for` condition,
+// or `match` scrutinee, go.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ConditionIndentStyle {
+    // Aligned with the start of the condition.
+    Visual,
+    // Two levels deeper than the keyword, so one deeper than the body.
+    Double,
+}
+
+impl_enum_decodable!(ConditionIndentStyle, Visual, Double);
+
+// How the body of a closure is indented.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ClosureIndentStyle {
+    // As written, the body isn't formatted.
+    Preserve,
+    // One level deeper than the start of the closure, so a closure nested in
+    // another's body steps right by where it starts on its line.
+    Visual,
+    // One level deeper than the statement the closure is in, so a closure
+    // nested in another's body is indented one more level, however long the
+    // line it starts on. A closure with a block body which is the last
+    // argument of a call stays on the line with the call.
+    Block,
+}
+
+impl_enum_decodable!(ClosureIndentStyle, Preserve, Visual, Block);
+
+// The case of the digits of hexadecimal literals.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum HexLiteralCase {
+    // As written.
+    Preserve,
+    Upper,
+    Lower,
+}
+
+impl_enum_decodable!(HexLiteralCase, Preserve, Upper, Lower);
+
+// How a numeric literal's type suffix is attached.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum LiteralSuffixStyle {
+    // As written.
+    Preserve,
+    // After an underscore, e.g., `1_u32`.
+    Separated,
+    // Straight after the digits, e.g., `1u32`.
+    Joined,
+}
+
+impl_enum_decodable!(LiteralSuffixStyle, Preserve, Separated, Joined);
+
+// How much rustfmt tells the user about what it is doing.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Verbosity {
+    // Errors only.
+    Quiet,
+    // Errors and formatting warnings.
+    Normal,
+    // Also report every file processed and skipped, with timings.
+    Verbose,
+}
+
+impl_enum_decodable!(Verbosity, Quiet, Normal, Verbose);
+
+// Whether to color diffs printed to stdout.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Color {
+    // Only when stdout is a terminal.
+    Auto,
+    Always,
+    Never,
+}
+
+impl_enum_decodable!(Color, Auto, Always, Never);
+
+impl Color {
+    pub fn use_color(self) -> bool {
+        match self {
+            Color::Auto => utils::stdout_isatty(),
+            Color::Always => true,
+            Color::Never => false,
+        }
+    }
+}
+
+#[derive(RustcDecodable, Clone, Debug)]
 pub struct Config {
     pub max_width: usize,
     pub ideal_width: usize,
     pub leeway: usize,
     pub tab_spaces: usize,
+    // How many columns a tab in the source, e.g., in a comment, takes up.
+    pub tab_width: usize,
     pub newline_style: NewlineStyle,
     pub fn_brace_style: BraceStyle,
     pub fn_return_indent: ReturnIndent,
@@ -39,14 +134,131 @@ pub struct Config {
     pub struct_trailing_comma: SeparatorTactic,
     pub struct_lit_trailing_comma: SeparatorTactic,
     pub struct_lit_style: StructLitStyle,
+    // When struct literal fields are one per line, line up the values of
+    // neighbouring fields, padding names by at most this many columns. Zero
+    // turns alignment off.
+    pub struct_lit_align_threshold: usize,
+    // Whether struct literals may go on one line at all, and if so, how many
+    // columns their fields may take up there. Zero means as many as fit.
+    pub struct_lit_single_line: bool,
+    pub struct_lit_width: usize,
     pub enum_trailing_comma: bool,
+    // Line up the `=` of the explicit discriminants of neighbouring enum
+    // variants whose names are at most this long. Zero turns alignment off.
+    pub enum_discrim_align_threshold: usize,
+    // Trailing commas for the other comma separated lists: arguments,
+    // parameters, tuples, generics, where clauses and imports. Arrays and the
+    // arguments of macros never get one.
+    pub trailing_comma: SeparatorTactic,
+    // Where to put the commas between where clause predicates, one per line.
+    pub where_separator_place: SeparatorPlace,
     pub report_todo: ReportTactic,
     pub report_fixme: ReportTactic,
     pub reorder_imports: bool, // Alphabetically, case sensitive.
     pub expr_indent_style: BlockIndentStyle,
+    pub condition_indent_style: ConditionIndentStyle,
+    pub closure_indent_style: ClosureIndentStyle,
+    pub hex_literal_case: HexLiteralCase,
+    // Write the exponent of float literals as `e` rather than `E`.
+    pub lowercase_float_exponent: bool,
+    pub literal_suffix_style: LiteralSuffixStyle,
+    // Group the digits of decimal literals longer than four digits in
+    // threes, e.g., `1_000_000`. Literals which already have underscores in
+    // them are left as they are.
+    pub group_decimal_digits: bool,
+    // Drop parentheses which make no difference to how the code parses, e.g.,
+    // around an if condition or a returned value. Parentheses with comments
+    // in them are kept.
+    pub remove_redundant_parens: bool,
+    // Line up the `=` of let statements and consts on consecutive lines of a
+    // block, as long as none of them runs past max_width.
+    pub align_bindings: bool,
+    // Put a comma after match arms with a block body. Arms with any other
+    // body always get one.
+    pub match_block_trailing_comma: bool,
+    // Runs of more blank lines than this inside blocks are cut down to this
+    // many. Blank lines in comments and string literals are left alone.
+    pub max_blank_lines: usize,
+    // Blank lines between fns, impls or mods and the items next to them, the
+    // most any of the two asks for. There are none between an opening brace
+    // and the first of them. Neighbouring one line items of the same kind,
+    // e.g., `mod foo;`, are left as they are. Zero leaves the blank lines
+    // around that kind of item as written.
+    pub fn_blank_lines: usize,
+    pub impl_blank_lines: usize,
+    pub mod_blank_lines: usize,
+    // Drop the semicolons after blocks, loops, ifs and matches in statement
+    // position where they make no difference, i.e., the expression can only
+    // be `()`.
+    pub remove_redundant_semicolons: bool,
+    // Put the items of trait impls in the order the trait declares them, for
+    // traits in the crate being formatted.
+    pub reorder_impl_items: bool,
+    // Move extern crates, then mods, then uses to the top of each module.
+    pub reorder_declarations: bool,
+    // Format the code examples in `///` doc comments, except those marked
+    // `ignore` or `text`.
+    pub format_code_in_doc_comments: bool,
+    // Keep a .bk copy of each file we overwrite.
+    pub write_backup: bool,
+    // Directory to put backups in, relative paths are resolved against the
+    // current directory. Empty means next to the original file.
+    pub backup_dir: String,
+    // Only format the given files, don't follow `mod foo;` into other files.
+    pub skip_children: bool,
+    // Files and directories to skip when walking a directory. An entry
+    // matches a path which starts with it, or any file or directory with
+    // exactly that name.
+    pub ignore: Vec<String>,
+    // When walking a directory, also skip hidden files and directories,
+    // `target` directories and anything matched by a .gitignore.
+    pub default_ignores: bool,
+    // Only reformat items and statements overlapping these line ranges, see
+    // FileLines. Everything else is left untouched.
+    pub file_lines: FileLines,
+    pub verbosity: Verbosity,
+    // Write a JSON list of the code rustfmt could not format to this file.
+    // Empty means no report.
+    pub failure_report: String,
+    pub color: Color,
+    // Replace code which is copied through without being formatted with Xs,
+    // to show how much of a file rustfmt can handle.
+    pub coverage: bool,
+    // Print the time spent parsing, in each kind of rewrite and writing.
+    pub profile: bool,
+    // Print how many of each kind of code formatting changed, and why
+    // rewrites failed.
+    pub stats: bool,
+    // Remember crates which are already formatted in this file, and skip
+    // them while they and the config are unchanged. Empty means no cache.
+    pub cache_file: String,
+    // Format the result of formatting again, and report a bug if that
+    // changes anything.
+    pub check_idempotence: bool,
+    // Before writing a file, check that the formatted code parses to the
+    // same AST as the original, and don't write it if not.
+    pub verify_ast: bool,
+    // Write files which started with a byte order mark back out with one.
+    pub keep_bom: bool,
+    // End each file with a single newline, otherwise with none.
+    pub newline_at_eof: bool,
 }
 
 impl Config {
+    // Like from_toml, but reports problems rather than panicking.
+    pub fn try_from_toml(toml: &str) -> Result<Config, String> {
+        let mut parser = toml::Parser::new(toml);
+        let parsed = match parser.parse() {
+            Some(parsed) => parsed,
+            None => {
+                let msgs: Vec<_> = parser.errors.iter().map(|e| e.desc.clone()).collect();
+                return Err(format!("Could not parse config: {}", msgs.join(", ")));
+            }
+        };
+        toml::decode(toml::Value::Table(parsed))
+            .ok_or("Could not decode config, an option is missing or has a bad value".to_owned())
+    }
+
     pub fn from_toml(toml: &str) -> Config {
         let parsed = toml.parse().unwrap();
         match toml::decode(parsed) {
@@ -60,3 +272,204 @@ impl Config {
         }
     }
 }
+
+// The kind of value a config option takes.
+#[derive(Copy, Clone, Debug)]
+enum OptionType {
+    Bool,
+    Usize,
+    Str,
+    // A list of strings.
+    List,
+    // One of these names.
+    Enum(&'static [&'static str]),
+}
+
+// Each option's name, type and description, in the order of Config's fields.
+fn options() -> Vec<(&'static str, OptionType, &'static str)> {
+    use self::OptionType::*;
+
+    vec![("max_width", Usize, "Maximum width of each line"),
+         ("ideal_width", Usize, "Width lines are kept to where possible"),
+         ("leeway", Usize, "How far past ideal_width a line may go to avoid a worse layout"),
+         ("tab_spaces", Usize, "Number of spaces per indentation level"),
+         ("tab_width", Usize, "How many columns a tab in the source takes up"),
+         ("newline_style", Enum(NewlineStyle::variants()), "Line endings to write"),
+         ("fn_brace_style",
+          Enum(BraceStyle::variants()),
+          "Where to put a function's opening brace"),
+         ("fn_return_indent",
+          Enum(ReturnIndent::variants()),
+          "Where to indent a function's return type when it goes on its own line"),
+         ("fn_args_paren_newline", Bool, "Break the line before the parenthesis of long arguments"),
+         ("struct_trailing_comma",
+          Enum(SeparatorTactic::variants()),
+          "When to put a comma after the last field of a struct definition"),
+         ("struct_lit_trailing_comma",
+          Enum(SeparatorTactic::variants()),
+          "When to put a comma after the last field of a struct literal"),
+         ("struct_lit_style", Enum(StructLitStyle::variants()), "How to indent struct literals"),
+         ("struct_lit_align_threshold",
+          Usize,
+          "Line up the values of struct literal fields, padding names by at most this many \
+           columns, zero for no alignment"),
+         ("struct_lit_single_line", Bool, "Allow struct literals on one line when they fit"),
+         ("struct_lit_width",
+          Usize,
+          "The most columns the fields of a struct literal on one line may take up, zero for \
+           no limit"),
+         ("enum_trailing_comma", Bool, "Put a comma after the last variant of an enum"),
+         ("enum_discrim_align_threshold",
+          Usize,
+          "Line up the discriminants of enum variants with names at most this long, zero for \
+           no alignment"),
+         ("trailing_comma",
+          Enum(SeparatorTactic::variants()),
+          "When to put a comma after the last item of other comma separated lists, except \
+           arrays and macro arguments"),
+         ("where_separator_place",
+          Enum(SeparatorPlace::variants()),
+          "Where to put the commas of a where clause with one predicate per line"),
+         ("report_todo", Enum(ReportTactic::variants()), "Report TODO comments"),
+         ("report_fixme", Enum(ReportTactic::variants()), "Report FIXME comments"),
+         ("reorder_imports", Bool, "Sort imports alphabetically, case sensitively"),
+         ("expr_indent_style", Enum(BlockIndentStyle::variants()), "How to indent expressions"),
+         ("condition_indent_style",
+          Enum(ConditionIndentStyle::variants()),
+          "How to indent wrapped if, while and for conditions and match scrutinees"),
+         ("closure_indent_style",
+          Enum(ClosureIndentStyle::variants()),
+          "How to indent the bodies of closures"),
+         ("hex_literal_case", Enum(HexLiteralCase::variants()), "The case of hex literal digits"),
+         ("lowercase_float_exponent", Bool, "Write float exponents as `e` rather than `E`"),
+         ("literal_suffix_style",
+          Enum(LiteralSuffixStyle::variants()),
+          "Whether to separate literal type suffixes with an underscore"),
+         ("group_decimal_digits",
+          Bool,
+          "Group the digits of long decimal literals in threes with underscores"),
+         ("remove_redundant_parens", Bool, "Remove parentheses which don't affect parsing"),
+         ("align_bindings",
+          Bool,
+          "Line up the `=` of let statements and consts on consecutive lines"),
+         ("match_block_trailing_comma", Bool, "Put a comma after match arms with a block body"),
+         ("max_blank_lines", Usize, "Maximum number of blank lines in a row inside blocks"),
+         ("fn_blank_lines",
+          Usize,
+          "Blank lines between a fn and the items next to it, zero to leave them as written"),
+         ("impl_blank_lines",
+          Usize,
+          "Blank lines between an impl and the items next to it, zero to leave them as written"),
+         ("mod_blank_lines",
+          Usize,
+          "Blank lines between a mod and the items next to it, zero to leave them as written"),
+         ("remove_redundant_semicolons",
+          Bool,
+          "Remove semicolons after blocks, loops, ifs and matches which don't need them"),
+         ("reorder_impl_items", Bool, "Order the items of trait impls as the trait declares them"),
+         ("reorder_declarations",
+          Bool,
+          "Move extern crates, mods and uses, in that order, to the top of each module"),
+         ("format_code_in_doc_comments", Bool, "Format the code examples in doc comments"),
+         ("write_backup", Bool, "Keep a .bk copy of each file overwritten"),
+         ("backup_dir", Str, "Directory to put backups in, empty for next to the originals"),
+         ("skip_children", Bool, "Only format the given files, don't follow `mod foo;`"),
+         ("ignore", List, "Files and directories to skip when walking a directory"),
+         ("default_ignores",
+          Bool,
+          "Also skip hidden files, target directories and files matched by .gitignore"),
+         ("file_lines", List, "Only format these line ranges, each `[FILE:]LO-HI`"),
+         ("verbosity", Enum(Verbosity::variants()), "How much to report"),
+         ("failure_report", Str, "File to list the code which could not be formatted in"),
+         ("color", Enum(Color::variants()), "Whether to color diffs"),
+         ("coverage", Bool, "Replace code which is not formatted with Xs"),
+         ("profile", Bool, "Print the time spent in each phase of formatting"),
+         ("stats", Bool, "Print how much of each kind of code changed and why rewrites failed"),
+         ("cache_file", Str, "File to remember already formatted crates in"),
+         ("check_idempotence", Bool, "Check that formatting the result again changes nothing"),
+         ("verify_ast", Bool, "Check that formatting does not change the meaning of the code"),
+         ("keep_bom", Bool, "Keep the byte order mark of files which start with one"),
+         ("newline_at_eof", Bool, "End each file with a single newline")]
+}
+
+// A JSON Schema for rustfmt.toml, describing each option's type, default and
+// allowed values, for editors to complete and check configs with.
+pub fn schema() -> Json {
+    let defaults = match toml::Parser::new(include_str!("default.toml")).parse() {
+        Some(defaults) => defaults,
+        None => panic!("default.toml does not parse"),
+    };
+
+    let mut properties = BTreeMap::new();
+    for (name, option_type, description) in options() {
+        let mut property = BTreeMap::new();
+        let type_name = match option_type {
+            OptionType::Bool => "boolean",
+            OptionType::Usize => "integer",
+            OptionType::Str | OptionType::Enum(_) => "string",
+            OptionType::List => "array",
+        };
+        property.insert("type".to_owned(), type_name.to_json());
+        match option_type {
+            OptionType::Usize => {
+                property.insert("minimum".to_owned(), 0.to_json());
+            }
+            OptionType::List => {
+                let mut items = BTreeMap::new();
+                items.insert("type".to_owned(), "string".to_json());
+                property.insert("items".to_owned(), Json::Object(items));
+            }
+            OptionType::Enum(variants) => {
+                let variants = variants.iter().map(|v| v.to_json()).collect();
+                property.insert("enum".to_owned(), Json::Array(variants));
+            }
+            OptionType::Bool | OptionType::Str => {}
+        }
+        if let Some(default) = defaults.get(name) {
+            property.insert("default".to_owned(), toml_to_json(default));
+        }
+        property.insert("description".to_owned(), description.to_json());
+        properties.insert(name.to_owned(), Json::Object(property));
+    }
+
+    let mut result = BTreeMap::new();
+    result.insert("$schema".to_owned(),
+                  "http://json-schema.org/draft-04/schema#".to_json());
+    result.insert("title".to_owned(), "rustfmt.toml".to_json());
+    result.insert("type".to_owned(), "object".to_json());
+    result.insert("properties".to_owned(), Json::Object(properties));
+    result.insert("additionalProperties".to_owned(), false.to_json());
+    Json::Object(result)
+}
+
+fn toml_to_json(value: &toml::Value) -> Json {
+    match *value {
+        toml::Value::String(ref s) => Json::String(s.clone()),
+        toml::Value::Integer(i) => Json::I64(i),
+        toml::Value::Float(f) => Json::F64(f),
+        toml::Value::Boolean(b) => Json::Boolean(b),
+        toml::Value::Datetime(ref s) => Json::String(s.clone()),
+        toml::Value::Array(ref values) => Json::Array(values.iter().map(toml_to_json).collect()),
+        toml::Value::Table(ref table) => {
+            Json::Object(table.iter().map(|(k, v)| (k.clone(), toml_to_json(v))).collect())
+        }
+    }
+}
+
+#[test]
+fn schema_test() {
+    // Every option in the default config must be described, and nothing else.
+    let defaults = toml::Parser::new(include_str!("default.toml")).parse().unwrap();
+    let mut described: Vec<_> = options().into_iter().map(|(name, _, _)| name).collect();
+    described.sort();
+    let mut names: Vec<_> = defaults.keys().map(|name| &**name).collect();
+    names.sort();
+    assert_eq!(described, names);
+
+    let schema = schema();
+    let color = schema.find_path(&["properties", "color"]).unwrap();
+    assert_eq!(color.find("default"), Some(&Json::String("Auto".to_owned())));
+    assert_eq!(color.find("enum").unwrap().as_array().unwrap().len(), 3);
+    let max_width = schema.find_path(&["properties", "max_width"]).unwrap();
+    assert_eq!(max_width.find("type"), Some(&Json::String("integer".to_owned())));
+}
@@ -0,0 +1,182 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// A resident formatting server for editors, speaking JSON-RPC 2.0 with one
+// message per line. A `format` request takes either the `text` of a buffer or
+// the `path` of a file, and optionally `ranges` of byte offsets to restrict
+// formatting to, and answers with the edits to make, see edits::TextEdit.
+// A `shutdown` request stops the server.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+
+use rustc_serialize::json::{Json, ToJson};
+
+use config::Config;
+use edits::{TextEdit, format_ranges};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+// The request was fine, but its input could not be read or formatted.
+const FORMAT_FAILED: i64 = -32000;
+
+impl ToJson for TextEdit {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        object.insert("lo".to_owned(), self.lo.to_json());
+        object.insert("hi".to_owned(), self.hi.to_json());
+        object.insert("replacement".to_owned(), self.replacement.to_json());
+        Json::Object(object)
+    }
+}
+
+// Answers requests from input on output until a shutdown request or the end
+// of input.
+pub fn serve<R, W>(input: R, mut output: W, config: &Config) -> io::Result<()>
+    where R: BufRead,
+          W: Write
+{
+    for line in input.lines() {
+        let line = try!(line);
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (id, response, shutdown) = match Json::from_str(&line) {
+            Ok(request) => {
+                let id = request.find("id").cloned().unwrap_or(Json::Null);
+                let method = request.find("method").and_then(|m| m.as_string());
+                match method {
+                    Some("shutdown") => (id, Ok(Json::Null), true),
+                    Some(method) => (id, handle(method, request.find("params"), config), false),
+                    None => (id, Err((INVALID_REQUEST, "missing method".to_owned())), false),
+                }
+            }
+            Err(e) => (Json::Null, Err((PARSE_ERROR, e.to_string())), false),
+        };
+
+        try!(writeln!(output, "{}", make_response(id, response)));
+        try!(output.flush());
+        if shutdown {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn handle(method: &str, params: Option<&Json>, config: &Config) -> Result<Json, (i64, String)> {
+    match method {
+        "format" => format(params, config),
+        _ => Err((METHOD_NOT_FOUND, format!("unknown method: {}", method))),
+    }
+}
+
+fn format(params: Option<&Json>, config: &Config) -> Result<Json, (i64, String)> {
+    let params = match params {
+        Some(params) if params.is_object() => params,
+        _ => return Err((INVALID_PARAMS, "expected an object of params".to_owned())),
+    };
+
+    let text = match (params.find("text").and_then(|t| t.as_string()),
+                      params.find("path").and_then(|p| p.as_string())) {
+        (Some(text), _) => text.to_owned(),
+        (None, Some(path)) => try!(read_file(path).map_err(|e| (FORMAT_FAILED, e))),
+        (None, None) => return Err((INVALID_PARAMS, "expected text or path".to_owned())),
+    };
+    let ranges = match params.find("ranges") {
+        Some(ranges) => try!(parse_ranges(ranges)),
+        None => vec![(0, text.len())],
+    };
+
+    let edits: Vec<TextEdit> = try!(format_ranges(&text, &ranges, config)
+                                        .map_err(|e| (FORMAT_FAILED, e.to_string())));
+    let mut result = BTreeMap::new();
+    result.insert("edits".to_owned(), edits.to_json());
+    Ok(Json::Object(result))
+}
+
+fn read_file(path: &str) -> Result<String, String> {
+    let mut text = String::new();
+    try!(File::open(path)
+             .and_then(|mut file| file.read_to_string(&mut text))
+             .map_err(|e| format!("could not read {}: {}", path, e)));
+    Ok(text)
+}
+
+// Ranges are given as [lo, hi] pairs.
+fn parse_ranges(ranges: &Json) -> Result<Vec<(usize, usize)>, (i64, String)> {
+    let error = || (INVALID_PARAMS, "expected ranges as a list of [lo, hi] pairs".to_owned());
+    let ranges = try!(ranges.as_array().ok_or_else(&error));
+    ranges.iter()
+          .map(|range| {
+              let pair = match range.as_array() {
+                  Some(pair) if pair.len() == 2 => pair,
+                  _ => return Err(error()),
+              };
+              match (pair[0].as_u64(), pair[1].as_u64()) {
+                  (Some(lo), Some(hi)) if lo <= hi => Ok((lo as usize, hi as usize)),
+                  _ => Err(error()),
+              }
+          })
+          .collect()
+}
+
+fn make_response(id: Json, response: Result<Json, (i64, String)>) -> Json {
+    let mut object = BTreeMap::new();
+    object.insert("jsonrpc".to_owned(), "2.0".to_json());
+    object.insert("id".to_owned(), id);
+    match response {
+        Ok(result) => {
+            object.insert("result".to_owned(), result);
+        }
+        Err((code, message)) => {
+            let mut error = BTreeMap::new();
+            error.insert("code".to_owned(), code.to_json());
+            error.insert("message".to_owned(), message.to_json());
+            object.insert("error".to_owned(), Json::Object(error));
+        }
+    }
+    Json::Object(object)
+}
+
+#[test]
+fn serve_test() {
+    let config = Config::from_toml(include_str!("default.toml"));
+    let input = concat!(r#"{"jsonrpc": "2.0", "id": 1, "method": "format", "#,
+                        r#""params": {"text": "fn main() {\n    foo(1,2);\n}\n"}}"#,
+                        "\n",
+                        r#"{"jsonrpc": "2.0", "id": 2, "method": "frobnicate"}"#,
+                        "\n",
+                        "not json\n",
+                        r#"{"jsonrpc": "2.0", "id": 3, "method": "shutdown"}"#,
+                        "\n",
+                        r#"{"jsonrpc": "2.0", "id": 4, "method": "format"}"#,
+                        "\n");
+    let mut output = Vec::new();
+    serve(input.as_bytes(), &mut output, &config).unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let responses: Vec<_> = output.lines().map(|line| Json::from_str(line).unwrap()).collect();
+    assert_eq!(responses.len(), 4);
+
+    let edits = responses[0].find_path(&["result", "edits"]).unwrap().as_array().unwrap();
+    assert_eq!(edits.len(), 1);
+    assert_eq!(edits[0].find("lo").unwrap().as_u64(), Some(12));
+    assert_eq!(edits[0].find("replacement").unwrap().as_string(), Some("    foo(1, 2);\n"));
+
+    let error_code = |response: &Json| response.find_path(&["error", "code"]).unwrap().as_i64();
+    assert_eq!(error_code(&responses[1]), Some(METHOD_NOT_FOUND));
+    assert_eq!(error_code(&responses[2]), Some(PARSE_ERROR));
+    assert_eq!(responses[3].find("result"), Some(&Json::Null));
+}
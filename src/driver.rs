@@ -0,0 +1,168 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Parsing the input and handing the crate to format_crate. By default this
+// goes through the compiler's driver, which needs a matching nightly. With the
+// `syntex` feature we use syntex_syntax, a copy of the parser which builds on
+// stable Rust.
+
+use config::Config;
+use {FormatResult, Plugins};
+
+#[cfg(not(feature = "syntex"))]
+pub use self::rustc_driver_impl::format_inner;
+#[cfg(feature = "syntex")]
+pub use self::syntex_impl::format_inner;
+
+#[cfg(not(feature = "syntex"))]
+mod rustc_driver_impl {
+    use rustc::session::Session;
+    use rustc::session::config as rustc_config;
+    use rustc::session::config::Input as CompilerInput;
+    use rustc_driver::{self, driver, CompilerCalls, Compilation};
+    use syntax::diagnostics;
+    use getopts;
+    use time;
+
+    use std::cell::RefCell;
+    use std::mem::swap;
+    use std::path::PathBuf;
+    use std::rc::Rc;
+
+    use super::{Config, FormatResult, Plugins};
+    use format_crate;
+
+    struct RustFmtCalls {
+        config: Option<Box<Config>>,
+        // Source text to format, if we are not reading from a file.
+        input_str: Option<String>,
+        // Filled in once the crate has been formatted.
+        result: Rc<RefCell<Option<FormatResult>>>,
+        plugins: Plugins,
+        // When we started, parsing is the time until the crate is ready.
+        start: u64,
+    }
+
+    impl<'a> CompilerCalls<'a> for RustFmtCalls {
+        fn no_input(&mut self,
+                    _: &getopts::Matches,
+                    _: &rustc_config::Options,
+                    _: &Option<PathBuf>,
+                    _: &Option<PathBuf>,
+                    _: &diagnostics::registry::Registry)
+                    -> Option<(CompilerInput, Option<PathBuf>)> {
+            match self.input_str.take() {
+                Some(input) => Some((CompilerInput::Str(input), None)),
+                None => panic!("No input supplied to RustFmt"),
+            }
+        }
+
+        fn build_controller(&mut self, _: &Session) -> driver::CompileController<'a> {
+            let mut config_option = None;
+            swap(&mut self.config, &mut config_option);
+            let config = config_option.unwrap();
+
+            // Input from stdin has no file to resolve out of line modules against.
+            let follow_mods = self.input_str.is_none() && !config.skip_children;
+            let result = self.result.clone();
+            let plugins = self.plugins.clone();
+            let start = self.start;
+
+            let mut control = driver::CompileController::basic();
+            control.after_parse.stop = Compilation::Stop;
+            control.after_parse.callback = Box::new(move |state| {
+                let parse_ns = time::precise_time_ns() - start;
+                let krate = state.krate.unwrap();
+                let codemap = state.session.codemap();
+                let (file_map, mut report) = format_crate(krate,
+                                                          codemap,
+                                                          &*config,
+                                                          follow_mods,
+                                                          &plugins);
+                if config.profile {
+                    report.profile.add("parse", parse_ns);
+                }
+                *result.borrow_mut() = Some((file_map, report));
+            });
+
+            control
+        }
+    }
+
+    pub fn format_inner(args: Vec<String>,
+                        config: &Config,
+                        input_str: Option<String>,
+                        plugins: Plugins)
+                        -> Option<FormatResult> {
+        let result = Rc::new(RefCell::new(None));
+        let mut call_ctxt = RustFmtCalls {
+            config: Some(Box::new(config.clone())),
+            input_str: input_str,
+            result: result.clone(),
+            plugins: plugins,
+            start: time::precise_time_ns(),
+        };
+        rustc_driver::run_compiler(&args, &mut call_ctxt);
+
+        let formatted = result.borrow_mut().take();
+        formatted
+    }
+}
+
+#[cfg(feature = "syntex")]
+mod syntex_impl {
+    use syntax::parse::{self, ParseSess};
+    use time;
+
+    use std::path::Path;
+
+    use super::{Config, FormatResult, Plugins};
+    use format_crate;
+
+    // args are as for the compiler, only the last, the root file, is used.
+    // Like the compiler, the parser panics on fatal errors.
+    pub fn format_inner(args: Vec<String>,
+                        config: &Config,
+                        input_str: Option<String>,
+                        plugins: Plugins)
+                        -> Option<FormatResult> {
+        let start = time::precise_time_ns();
+        let sess = ParseSess::new();
+        // Input from stdin has no file to resolve out of line modules against.
+        let follow_mods = input_str.is_none() && !config.skip_children;
+        let krate = match input_str {
+            Some(input) => {
+                parse::parse_crate_from_source_str("<stdin>".to_owned(),
+                                                   input,
+                                                   Vec::new(),
+                                                   &sess)
+            }
+            None => {
+                let root = match args.last() {
+                    Some(root) => root,
+                    None => return None,
+                };
+                parse::parse_crate_from_file(Path::new(root), Vec::new(), &sess)
+            }
+        };
+
+        let parse_ns = time::precise_time_ns() - start;
+
+        let (file_map, mut report) = format_crate(&krate,
+                                                  sess.codemap(),
+                                                  config,
+                                                  follow_mods,
+                                                  &plugins);
+        if config.profile {
+            report.profile.add("parse", parse_ns);
+        }
+        Some((file_map, report))
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Formatting the parts of a file an editor has changed, and describing the
+// result as small edits rather than a whole new file.
+
+use std::cmp;
+
+use {Input, Error, format_input};
+use config::Config;
+use file_lines::FileLines;
+use rustfmt_diff::make_diff;
+
+// Replace the text between byte offsets lo and hi of the original with
+// replacement.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct TextEdit {
+    pub lo: usize,
+    pub hi: usize,
+    pub replacement: String,
+}
+
+// Formats the code in text which overlaps ranges, which are pairs of byte
+// offsets (lo inclusive, hi exclusive). As with file_lines, whole items and
+// statements are formatted, so edits may reach outside the ranges. The edits
+// are in order and don't overlap, each applies to the original text.
+pub fn format_ranges(text: &str,
+                     ranges: &[(usize, usize)],
+                     config: &Config)
+                     -> Result<Vec<TextEdit>, Error> {
+    if ranges.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut config = config.clone();
+    config.file_lines = FileLines::all();
+    for &(lo, hi) in ranges {
+        let last = if hi > lo { hi - 1 } else { lo };
+        let spec = format!("{}-{}", line_at(text, lo), line_at(text, last));
+        config.file_lines.add_spec(&spec).unwrap();
+    }
+
+    let (_, file_map, _) = try!(format_input(Input::Text(text.to_owned()), &config));
+    let formatted = match file_map.values().next() {
+        Some(formatted) => formatted.to_string(),
+        None => return Ok(Vec::new()),
+    };
+
+    let line_starts = line_starts(text);
+    let offset_of = |line: usize| line_starts.get(line - 1).map_or(text.len(), |&i| i);
+    let edits = make_diff(text, &formatted)
+                    .into_iter()
+                    .map(|mismatch| {
+                        let first = mismatch.original_start_line;
+                        let mut replacement = String::new();
+                        for line in &mismatch.expected_lines {
+                            replacement.push_str(line);
+                            replacement.push('\n');
+                        }
+                        TextEdit {
+                            lo: offset_of(first),
+                            hi: offset_of(first + mismatch.original_lines.len()),
+                            replacement: replacement,
+                        }
+                    })
+                    .collect();
+
+    Ok(edits)
+}
+
+// The line containing byte offset i, numbered from 1.
+fn line_at(text: &str, i: usize) -> usize {
+    let i = cmp::min(i, text.len());
+    text.as_bytes()[..i].iter().filter(|&&b| b == b'\n').count() + 1
+}
+
+// The byte offset at which each line starts.
+fn line_starts(text: &str) -> Vec<usize> {
+    let mut result = vec![0];
+    result.extend(text.char_indices().filter(|&(_, c)| c == '\n').map(|(i, _)| i + 1));
+    result
+}
+
+#[test]
+fn line_offsets_test() {
+    let text = "a\nbc\n\nd";
+    assert_eq!(line_starts(text), vec![0, 2, 5, 6]);
+    assert_eq!(line_at(text, 0), 1);
+    assert_eq!(line_at(text, 2), 2);
+    assert_eq!(line_at(text, 4), 2);
+    assert_eq!(line_at(text, 6), 4);
+    assert_eq!(line_at(text, 100), 4);
+}
@@ -0,0 +1,82 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Checking that formatting didn't change what the code means, by comparing
+// the ASTs of the original and formatted code.
+
+use std::thread;
+
+use syntax::ast;
+use syntax::parse::{self, ParseSess};
+use syntax::print::pprust;
+use syntax::ptr::P;
+
+// Returns true if original and formatted parse to the same AST, ignoring
+// spans, comments and the order of runs of imports, which rustfmt may sort.
+// Both are parsed as the file at path, so that they find the same out of line
+// modules. Err if either doesn't parse.
+pub fn same_ast(path: &str, original: String, formatted: String) -> Result<bool, String> {
+    let path = path.to_owned();
+    // The parser panics on fatal errors, so keep it on its own thread.
+    let child = thread::spawn(move || {
+        print_crate(&path, original) == print_crate(&path, formatted)
+    });
+    child.join().map_err(|_| "could not parse code to compare".to_owned())
+}
+
+fn print_crate(path: &str, source: String) -> Vec<String> {
+    let sess = ParseSess::new();
+    let krate = parse::parse_crate_from_source_str(path.to_owned(), source, Vec::new(), &sess);
+
+    let mut result: Vec<_> = krate.attrs.iter().map(pprust::attribute_to_string).collect();
+    print_items(&krate.module.items, &mut result);
+    result
+}
+
+fn print_items(items: &[P<ast::Item>], out: &mut Vec<String>) {
+    let mut uses = Vec::new();
+    for item in items {
+        if let ast::Item_::ItemUse(..) = item.node {
+            uses.push(pprust::item_to_string(item));
+            continue;
+        }
+
+        flush_uses(&mut uses, out);
+        match item.node {
+            // Inline and out of line modules alike, so that imports inside
+            // them are compared in the same way.
+            ast::Item_::ItemMod(ref module) => {
+                out.extend(item.attrs.iter().map(pprust::attribute_to_string));
+                let vis = pprust::visibility_qualified(item.vis, "mod");
+                out.push(format!("{} {} {{", vis, item.ident));
+                print_items(&module.items, out);
+                out.push("}".to_owned());
+            }
+            _ => out.push(pprust::item_to_string(item)),
+        }
+    }
+    flush_uses(&mut uses, out);
+}
+
+fn flush_uses(uses: &mut Vec<String>, out: &mut Vec<String>) {
+    uses.sort();
+    out.extend(uses.iter().cloned());
+    uses.clear();
+}
+
+#[test]
+fn same_ast_test() {
+    let original = "use b;\nuse a;\nfn f() { foo(1,2) } // a comment\n";
+    let formatted = "use a;\nuse b;\n\nfn f() {\n    foo(1, 2)\n}\n";
+    let changed = "use a;\nuse b;\n\nfn f() {\n    foo(2, 1)\n}\n";
+
+    assert_eq!(same_ast("a.rs", original.to_owned(), formatted.to_owned()), Ok(true));
+    assert_eq!(same_ast("a.rs", original.to_owned(), changed.to_owned()), Ok(false));
+}
@@ -8,123 +8,309 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use rewrite::{Rewrite, RewriteContext};
-use lists::{write_list, itemize_list, ListFormatting, SeparatorTactic, ListTactic};
+use std::cmp;
+
+use rewrite::{Rewrite, RewriteContext, RewriteResult, RewriteError};
+use budget::{reserve, reserve_width, saturating_reserve, saturating_reserve_width};
+use lists::{write_list, itemize_list, definitive_tactic, ListFormatting, SeparatorTactic,
+            SeparatorPlace, ListTactic, ListItem};
 use string::{StringFormat, rewrite_string};
 use StructLitStyle;
-use utils::{span_after, make_indent, extra_offset};
+use utils::{span_after, make_indent, extra_offset, str_width};
 use visitor::FmtVisitor;
-use config::BlockIndentStyle;
+use config::{BlockIndentStyle, ClosureIndentStyle, ConditionIndentStyle};
 use comment::{FindUncommented, rewrite_comment};
 use types::rewrite_path;
+use macros;
+use literals::{normalize_number, string_lit_body};
+
+use syntax::{ast, ast_util, ptr};
+use syntax::parse::token;
+use syntax::print::pprust;
 
-use syntax::{ast, ptr};
+use time;
 use syntax::codemap::{Pos, Span, BytePos, mk_sp};
-use syntax::visit::Visitor;
+use syntax::visit::{self, Visitor};
 
 impl Rewrite for ast::Expr {
-    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> Option<String> {
-        match self.node {
-            ast::Expr_::ExprLit(ref l) => {
-                match l.node {
-                    ast::Lit_::LitStr(ref is, _) => {
-                        rewrite_string_lit(context, &is, l.span, width, offset)
-                    }
-                    _ => context.codemap.span_to_snippet(self.span).ok()
+    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> RewriteResult {
+        let key = (self.span.lo,
+                   self.span.hi,
+                   width,
+                   offset,
+                   context.block_indent,
+                   context.stmt_indent);
+        if let Some(result) = context.cache.borrow().get(&key) {
+            return result.clone();
+        }
+
+        let start = if context.config.profile {
+            time::precise_time_ns()
+        } else {
+            0
+        };
+        // Don't bother formatting children if we can't possibly fit.
+        let result = if min_width(self) > width {
+            Err(RewriteError::WidthExhausted)
+        } else {
+            rewrite_expr(self, context, width, offset)
+        };
+        if context.config.profile {
+            let phase = format!("rewrite {}", expr_kind(self));
+            context.profile.borrow_mut().add(&phase, time::precise_time_ns() - start);
+        }
+        context.cache.borrow_mut().insert(key, result.clone());
+        result
+    }
+}
+
+// The kind of expression, for profiles and statistics.
+pub fn expr_kind(expr: &ast::Expr) -> &'static str {
+    match expr.node {
+        ast::Expr_::ExprLit(..) => "literal",
+        ast::Expr_::ExprCall(..) => "call",
+        ast::Expr_::ExprMethodCall(..) => "method call chain",
+        ast::Expr_::ExprParen(..) => "parens",
+        ast::Expr_::ExprBinary(..) => "binary op",
+        ast::Expr_::ExprUnary(..) |
+        ast::Expr_::ExprBox(..) => "unary op",
+        ast::Expr_::ExprAssign(..) |
+        ast::Expr_::ExprAssignOp(..) => "assignment",
+        ast::Expr_::ExprStruct(..) => "struct literal",
+        ast::Expr_::ExprTup(..) => "tuple",
+        ast::Expr_::ExprVec(..) => "array",
+        ast::Expr_::ExprWhile(..) |
+        ast::Expr_::ExprWhileLet(..) |
+        ast::Expr_::ExprForLoop(..) |
+        ast::Expr_::ExprLoop(..) => "loop",
+        ast::Expr_::ExprBlock(..) => "block",
+        ast::Expr_::ExprIf(..) |
+        ast::Expr_::ExprIfLet(..) => "if",
+        ast::Expr_::ExprMatch(..) => "match",
+        ast::Expr_::ExprRange(..) => "range",
+        ast::Expr_::ExprPath(..) => "path",
+        ast::Expr_::ExprMac(..) => "macro call",
+        ast::Expr_::ExprRet(..) => "return",
+        ast::Expr_::ExprClosure(..) => "closure",
+        ast::Expr_::ExprBreak(..) |
+        ast::Expr_::ExprAgain(..) => "break",
+        _ => "other expression",
+    }
+}
+
+// Whether expr is a block, loop, if or match which can only be `()`, so that a
+// semicolon after it in statement position makes no difference.
+pub fn is_unit_block_like(expr: &ast::Expr) -> bool {
+    match expr.node {
+        ast::Expr_::ExprWhile(..) |
+        ast::Expr_::ExprWhileLet(..) |
+        ast::Expr_::ExprForLoop(..) |
+        ast::Expr_::ExprLoop(..) => true,
+        ast::Expr_::ExprBlock(ref block) => block.expr.is_none(),
+        ast::Expr_::ExprIf(_, ref block, ref else_block) |
+        ast::Expr_::ExprIfLet(_, _, ref block, ref else_block) => {
+            block.expr.is_none() && else_block.as_ref().map_or(true, |e| is_unit_block_like(e))
+        }
+        ast::Expr_::ExprMatch(_, ref arms) => {
+            arms.iter().all(|arm| {
+                match arm.body.node {
+                    ast::Expr_::ExprBlock(ref block) => block.expr.is_none(),
+                    _ => false,
                 }
+            })
+        }
+        _ => false,
+    }
+}
+
+// A cheap lower bound on the width any successful rewrite of expr needs: the
+// identifiers and punctuation which must share a line. Zero where we don't
+// know better.
+fn min_width(expr: &ast::Expr) -> usize {
+    match expr.node {
+        ast::Expr_::ExprPath(None, ref path) => {
+            // Each segment must fit in the width on its own.
+            let global = if path.global {
+                2
+            } else {
+                0
+            };
+            let longest = path.segments
+                              .iter()
+                              .map(|segment| token::get_ident(segment.identifier).len())
+                              .max()
+                              .unwrap_or(0);
+            global + longest
+        }
+        ast::Expr_::ExprCall(ref callee, _) => min_width(callee) + "()".len(),
+        // The parentheses might be removed.
+        ast::Expr_::ExprParen(ref subexpr) => min_width(subexpr),
+        ast::Expr_::ExprUnary(ref op, ref subexpr) => {
+            let operator_len = match *op {
+                ast::UnOp::UnUniq => "box ".len(),
+                _ => "!".len(),
+            };
+            min_width(subexpr) + operator_len
+        }
+        ast::Expr_::ExprBox(ref subexpr) => min_width(subexpr) + "box ".len(),
+        _ => 0,
+    }
+}
+
+fn rewrite_expr(expr: &ast::Expr,
+                context: &RewriteContext,
+                width: usize,
+                offset: usize)
+                -> RewriteResult {
+    match expr.node {
+        ast::Expr_::ExprLit(ref l) => {
+            match l.node {
+                ast::Lit_::LitStr(_, ast::StrStyle::CookedStr) => {
+                    rewrite_string_lit(context, l.span, width, offset)
+                }
+                ast::Lit_::LitInt(..) |
+                ast::Lit_::LitFloat(..) |
+                ast::Lit_::LitFloatUnsuffixed(..) => {
+                    context.snippet(l.span).map(|text| normalize_number(&text, context.config))
+                }
+                // Raw strings, byte strings, chars and bytes stay exactly as
+                // written however long they are, see literals.
+                _ => context.snippet(expr.span),
             }
-            ast::Expr_::ExprCall(ref callee, ref args) => {
-                rewrite_call(context, callee, args, self.span, width, offset)
-            }
-            ast::Expr_::ExprParen(ref subexpr) => {
-                rewrite_paren(context, subexpr, width, offset)
-            }
-            ast::Expr_::ExprBinary(ref op, ref lhs, ref rhs) => {
+        }
+        ast::Expr_::ExprCall(ref callee, ref args) => {
+            rewrite_call(context, callee, args, expr.span, width, offset)
+        }
+        ast::Expr_::ExprParen(ref subexpr) => {
+            rewrite_paren(context, subexpr, expr.span, width, offset)
+        }
+        ast::Expr_::ExprBinary(ref op, ref lhs, ref rhs) => {
+            let operands = add_operands(expr);
+            if op.node == ast::BinOp_::BiAdd && is_string_concat(context, &operands) {
+                rewrite_string_concat(context, &operands, width, offset)
+            } else {
                 rewrite_binary_op(context, op, lhs, rhs, width, offset)
             }
-            ast::Expr_::ExprUnary(ref op, ref subexpr) => {
-                rewrite_unary_op(context, op, subexpr, width, offset)
-            }
-            ast::Expr_::ExprStruct(ref path, ref fields, ref base) => {
-                rewrite_struct_lit(context,
-                                   path,
-                                   fields,
-                                   base.as_ref().map(|e| &**e),
-                                   self.span,
-                                   width,
-                                   offset)
-            }
-            ast::Expr_::ExprTup(ref items) => {
-                rewrite_tuple_lit(context, items, self.span, width, offset)
-            }
-            ast::Expr_::ExprWhile(ref cond, ref block, label) => {
-                Loop::new_while(None, cond, block, label).rewrite(context, width, offset)
-            }
-            ast::Expr_::ExprWhileLet(ref pat, ref cond, ref block, label) => {
-                Loop::new_while(Some(pat), cond, block, label).rewrite(context, width, offset)
-            }
-            ast::Expr_::ExprForLoop(ref pat, ref cond, ref block, label) => {
-                Loop::new_for(pat, cond, block, label).rewrite(context, width, offset)
-            }
-            ast::Expr_::ExprLoop(ref block, label) => {
-                Loop::new_loop(block, label).rewrite(context, width, offset)
-            }
-            ast::Expr_::ExprBlock(ref block) => {
-                block.rewrite(context, width, offset)
-            }
-            ast::Expr_::ExprIf(ref cond, ref if_block, ref else_block) => {
-                rewrite_if_else(context,
-                                cond,
-                                if_block,
-                                else_block.as_ref().map(|e| &**e),
-                                None,
-                                width,
-                                offset)
-            }
-            ast::Expr_::ExprIfLet(ref pat, ref cond, ref if_block, ref else_block) => {
-                rewrite_if_else(context,
-                                cond,
-                                if_block,
-                                else_block.as_ref().map(|e| &**e),
-                                Some(pat),
-                                width,
-                                offset)
-            }
-            // We reformat it ourselves because rustc gives us a bad span
-            // for ranges, see rust#27162
-            ast::Expr_::ExprRange(ref left, ref right) => {
-                rewrite_range(context,
-                              left.as_ref().map(|e| &**e),
-                              right.as_ref().map(|e| &**e),
-                              width,
-                              offset)
-            }
-            ast::Expr_::ExprPath(ref qself, ref path) => {
-                rewrite_path(context, qself.as_ref(), path, width, offset)
-            }
-            _ => context.codemap.span_to_snippet(self.span).ok()
         }
+        ast::Expr_::ExprUnary(ref op, ref subexpr) => {
+            rewrite_unary_op(context, op, subexpr, width, offset)
+        }
+        ast::Expr_::ExprBox(ref subexpr) => {
+            rewrite_box(context, &**subexpr, subexpr.span, expr.span, width, offset)
+        }
+        ast::Expr_::ExprAssign(ref lhs, ref rhs) => {
+            rewrite_assignment(context, None, lhs, rhs, width, offset)
+        }
+        ast::Expr_::ExprAssignOp(ref op, ref lhs, ref rhs) => {
+            rewrite_assignment(context, Some(op), lhs, rhs, width, offset)
+        }
+        ast::Expr_::ExprStruct(ref path, ref fields, ref base) => {
+            rewrite_struct_lit(context,
+                               path,
+                               fields,
+                               base.as_ref().map(|e| &**e),
+                               expr.span,
+                               width,
+                               offset)
+        }
+        ast::Expr_::ExprTup(ref items) => {
+            rewrite_tuple_lit(context, items, expr.span, width, offset)
+        }
+        ast::Expr_::ExprVec(ref items) => {
+            rewrite_array_lit(context, items, expr.span, width, offset)
+        }
+        ast::Expr_::ExprWhile(ref cond, ref block, label) => {
+            Loop::new_while(None, cond, block, label).rewrite(context, width, offset)
+        }
+        ast::Expr_::ExprWhileLet(ref pat, ref cond, ref block, label) => {
+            Loop::new_while(Some(pat), cond, block, label).rewrite(context, width, offset)
+        }
+        ast::Expr_::ExprForLoop(ref pat, ref cond, ref block, label) => {
+            Loop::new_for(pat, cond, block, label).rewrite(context, width, offset)
+        }
+        ast::Expr_::ExprLoop(ref block, label) => {
+            Loop::new_loop(block, label).rewrite(context, width, offset)
+        }
+        ast::Expr_::ExprBlock(ref block) => {
+            block.rewrite(context, width, offset)
+        }
+        ast::Expr_::ExprIf(ref cond, ref if_block, ref else_block) => {
+            rewrite_if_else(context,
+                            cond,
+                            if_block,
+                            else_block.as_ref().map(|e| &**e),
+                            None,
+                            width,
+                            offset)
+        }
+        ast::Expr_::ExprIfLet(ref pat, ref cond, ref if_block, ref else_block) => {
+            rewrite_if_else(context,
+                            cond,
+                            if_block,
+                            else_block.as_ref().map(|e| &**e),
+                            Some(pat),
+                            width,
+                            offset)
+        }
+        // We reformat it ourselves because rustc gives us a bad span
+        // for ranges, see rust#27162
+        ast::Expr_::ExprMatch(ref cond, ref arms) => {
+            rewrite_match(context, cond, arms, expr.span, width, offset)
+        }
+        ast::Expr_::ExprRange(ref left, ref right) => {
+            rewrite_range(context,
+                          left.as_ref().map(|e| &**e),
+                          right.as_ref().map(|e| &**e),
+                          width,
+                          offset)
+        }
+        ast::Expr_::ExprPath(ref qself, ref path) => {
+            rewrite_path(context, qself.as_ref(), path, width, offset)
+        }
+        ast::Expr_::ExprMac(ref mac) => macros::rewrite_macro(mac, context, width, offset),
+        ast::Expr_::ExprRet(ref value) => {
+            rewrite_return(context, value.as_ref().map(|e| &**e), expr.span, width, offset)
+        }
+        ast::Expr_::ExprClosure(capture, ref fn_decl, ref body) => {
+            rewrite_closure(context, capture, fn_decl, body, expr.span, width, offset)
+        }
+        ast::Expr_::ExprBreak(..) => rewrite_jump(context, "break", expr.span, width),
+        ast::Expr_::ExprAgain(..) => rewrite_jump(context, "continue", expr.span, width),
+        _ => context.unformatted_snippet(expr.span),
     }
 }
 
 impl Rewrite for ast::Block {
-    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> Option<String> {
+    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> RewriteResult {
         let mut visitor = FmtVisitor::from_codemap(context.codemap, context.config);
         visitor.block_indent = context.block_indent;
+        visitor.plugins = context.plugins;
+        visitor.cache = context.cache.clone();
+        visitor.trait_orders = context.trait_orders.clone();
+        visitor.profile = context.profile.clone();
 
         let prefix = match self.rules {
             ast::BlockCheckMode::PushUnsafeBlock(..) |
             ast::BlockCheckMode::UnsafeBlock(..) => {
-                let snippet = try_opt!(context.codemap.span_to_snippet(self.span).ok());
-                let open_pos = try_opt!(snippet.find_uncommented("{"));
+                let snippet = try!(context.snippet(self.span));
+                let open_pos = match snippet.find_uncommented("{") {
+                    Some(open_pos) => open_pos,
+                    None => return Err(RewriteError::Macro),
+                };
                 visitor.last_pos = self.span.lo + BytePos(open_pos as u32);
 
                 // Extract comment between unsafe and block start.
                 let trimmed = &snippet[6..open_pos].trim();
 
                 if trimmed.len() > 0 {
-                    // 9 = "unsafe  {".len(), 7 = "unsafe ".len()
-                    format!("unsafe {} ", rewrite_comment(trimmed, true, width - 9, offset + 7))
+                    let comment_width = try!(reserve(width, "unsafe  {"));
+                    let comment_offset = offset + "unsafe ".len();
+                    format!("unsafe {} ",
+                            rewrite_comment(trimmed,
+                                            true,
+                                            comment_width,
+                                            comment_offset,
+                                            context.config.tab_width))
                 } else {
                     "unsafe ".to_owned()
                 }
@@ -143,14 +329,142 @@ impl Rewrite for ast::Block {
         let snippet = visitor.snippet(mk_sp(visitor.last_pos, self.span.hi));
         visitor.buffer.push_str(&snippet);
 
-        Some(format!("{}{}", prefix, visitor.buffer))
+        let result = format!("{}{}", prefix, visitor.buffer);
+        context.failures.borrow_mut().extend(visitor.failures.into_inner());
+        Ok(result)
     }
 }
 
-// TODO(#18): implement pattern formatting
+// TODO(#18): implement pattern formatting. Only slice patterns, `@` bindings
+// and box patterns are formatted so far, anything else is copied.
 impl Rewrite for ast::Pat {
-    fn rewrite(&self, context: &RewriteContext, _: usize, _: usize) -> Option<String> {
-        context.codemap.span_to_snippet(self.span).ok()
+    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> RewriteResult {
+        match self.node {
+            ast::Pat_::PatVec(ref before, ref slice, ref after) => {
+                rewrite_slice_pat(context,
+                                  before,
+                                  slice.as_ref().map(|p| &**p),
+                                  after,
+                                  self.span,
+                                  width,
+                                  offset)
+            }
+            ast::Pat_::PatIdent(_, ref ident, Some(ref subpat)) => {
+                rewrite_at_binding(context, ident.span, subpat, self.span, width, offset)
+            }
+            ast::Pat_::PatBox(ref subpat) => {
+                rewrite_box(context, &**subpat, subpat.span, self.span, width, offset)
+            }
+            _ => context.unformatted_snippet(self.span),
+        }
+    }
+}
+
+// `x @ pat`, with a space either side of the `@` as for other operators.
+fn rewrite_at_binding(context: &RewriteContext,
+                      ident_span: Span,
+                      subpat: &ast::Pat,
+                      span: Span,
+                      width: usize,
+                      offset: usize)
+                      -> RewriteResult {
+    let binding = try!(context.snippet(mk_sp(span.lo, ident_span.hi)));
+    let between = try!(context.snippet(mk_sp(ident_span.hi, subpat.span.lo)));
+    if binding.contains("//") || binding.contains("/*") || between.trim() != "@" {
+        return context.unformatted_snippet(span);
+    }
+    // `ref mut x`
+    let binding = binding.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let overhead = binding.len() + " @ ".len();
+    let subpat_str = try!(subpat.rewrite(context,
+                                         try!(reserve_width(width, overhead)),
+                                         offset + overhead));
+    Ok(format!("{} @ {}", binding, subpat_str))
+}
+
+// Slice patterns are laid out like array literals. The `..` for the rest of
+// the slice goes straight after its pattern, if it has one, e.g.,
+// `[first, rest.., last]`.
+fn rewrite_slice_pat(context: &RewriteContext,
+                     before: &[ptr::P<ast::Pat>],
+                     slice: Option<&ast::Pat>,
+                     after: &[ptr::P<ast::Pat>],
+                     span: Span,
+                     width: usize,
+                     offset: usize)
+                     -> RewriteResult {
+    let indent = offset + "[".len();
+
+    // Each element with where it ends in the source, which for the rest of
+    // the slice is after the `..`, and whether it is the rest.
+    let mut elems: Vec<(&ast::Pat, BytePos, bool)> = before.iter()
+                                                           .map(|p| (&**p, p.span.hi, false))
+                                                           .collect();
+    if let Some(slice) = slice {
+        let rest = try!(context.snippet(mk_sp(slice.span.lo, span.hi)));
+        let dots = match rest.find_uncommented("..") {
+            Some(dots) => dots,
+            None => return context.unformatted_snippet(span),
+        };
+        elems.push((slice, slice.span.lo + BytePos(dots as u32 + 2), true));
+    }
+    elems.extend(after.iter().map(|p| (&**p, p.span.hi, false)));
+
+    let elem_width = saturating_reserve_width(context.config.max_width, indent + 1);
+    let elem_strs = try!(elems.iter()
+                              .map(|elem| {
+                                  let elem_span = mk_sp(elem.0.span.lo, elem.1);
+                                  rewrite_slice_pat_elem(context, elem, elem_width, indent)
+                                      .or_else(|_| context.fallback_snippet(elem_span, indent))
+                              })
+                              .collect::<Result<Vec<_>, _>>());
+    let items = itemize_list(context.codemap,
+                             Vec::new(),
+                             elems.iter().zip(elem_strs),
+                             ",",
+                             "]",
+                             |&(elem, _)| elem.0.span.lo,
+                             |&(elem, _)| elem.1,
+                             |&(_, ref elem_str)| elem_str.clone(),
+                             span.lo + BytePos(1),
+                             span.hi - BytePos(1));
+
+    let list_width = try!(reserve(width, "[]"));
+    let fmt = ListFormatting {
+        tactic: ListTactic::HorizontalVertical,
+        separator: ",",
+        trailing_separator: context.config.trailing_comma,
+        separator_place: SeparatorPlace::Back,
+        indent: indent,
+        h_width: list_width,
+        v_width: list_width,
+        ends_with_newline: false,
+        config: context.config,
+    };
+
+    Ok(format!("[{}]", write_list(&items, &fmt)))
+}
+
+fn rewrite_slice_pat_elem(context: &RewriteContext,
+                          elem: &(&ast::Pat, BytePos, bool),
+                          width: usize,
+                          offset: usize)
+                          -> RewriteResult {
+    let (pat, hi, is_rest) = *elem;
+    if !is_rest {
+        return pat.rewrite(context, width, offset);
+    }
+    let rest = try!(context.snippet(mk_sp(pat.span.lo, hi)));
+    if rest == ".." {
+        return Ok(rest);
+    }
+    let pat_str = try!(pat.rewrite(context, try!(reserve(width, "..")), offset));
+    // The span of the pattern may take in the `..` already.
+    if pat_str.ends_with("..") {
+        Ok(pat_str)
+    } else {
+        Ok(format!("{}..", pat_str))
     }
 }
 
@@ -191,7 +505,7 @@ impl<'a> Loop<'a> {
             keyword: "while ",
             matcher: match pat {
                 Some(..) => "let ",
-                None => ""
+                None => "",
             },
             connector: " =",
         }
@@ -215,21 +529,22 @@ impl<'a> Loop<'a> {
 }
 
 impl<'a> Rewrite for Loop<'a> {
-    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> Option<String> {
+    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> RewriteResult {
         let label_string = rewrite_label(self.label);
-        // 2 = " {".len()
-        let inner_width = width - self.keyword.len() - 2 - label_string.len();
+        let inner_width = try!(reserve(width, self.keyword)
+                                   .and_then(|w| reserve(w, " {"))
+                                   .and_then(|w| reserve(w, &label_string)));
         let inner_offset = offset + self.keyword.len() + label_string.len();
 
         let pat_expr_string = match self.cond {
-            Some(cond) => try_opt!(rewrite_pat_expr(context,
-                                                    self.pat,
-                                                    cond,
-                                                    self.matcher,
-                                                    self.connector,
-                                                    inner_width,
-                                                    inner_offset)),
-            None => String::new()
+            Some(cond) => try!(rewrite_pat_expr(context,
+                                                self.pat,
+                                                cond,
+                                                self.matcher,
+                                                self.connector,
+                                                inner_width,
+                                                inner_offset)),
+            None => String::new(),
         };
 
         // FIXME: this drops any comment between "loop" and the block.
@@ -242,7 +557,7 @@ impl<'a> Rewrite for Loop<'a> {
 fn rewrite_label(label: Option<ast::Ident>) -> String {
     match label {
         Some(ident) => format!("{}: ", ident),
-        None => "".to_owned()
+        None => "".to_owned(),
     }
 }
 
@@ -252,23 +567,21 @@ fn rewrite_range(context: &RewriteContext,
                  right: Option<&ast::Expr>,
                  width: usize,
                  offset: usize)
-                 -> Option<String> {
+                 -> RewriteResult {
     let left_string = match left {
-        // 2 = ..
-        Some(expr) => try_opt!(expr.rewrite(context, width - 2, offset)),
-        None => String::new()
+        Some(expr) => try!(expr.rewrite(context, try!(reserve(width, "..")), offset)),
+        None => String::new(),
     };
 
     let right_string = match right {
         Some(expr) => {
-            // 2 = ..
-            let max_width = (width - 2).checked_sub(left_string.len()).unwrap_or(0);
-            try_opt!(expr.rewrite(context, max_width, offset + 2 + left_string.len()))
+            let max_width = saturating_reserve(saturating_reserve(width, ".."), &left_string);
+            try!(expr.rewrite(context, max_width, offset + "..".len() + left_string.len()))
         }
-        None => String::new()
+        None => String::new(),
     };
 
-    Some(format!("{}..{}", left_string, right_string))
+    Ok(format!("{}..{}", left_string, right_string))
 }
 
 // Rewrites if-else blocks. If let Some(_) = pat, the expression is
@@ -280,27 +593,27 @@ fn rewrite_if_else(context: &RewriteContext,
                    pat: Option<&ast::Pat>,
                    width: usize,
                    offset: usize)
-                   -> Option<String> {
-    // 3 = "if ", 2 = " {"
-    let pat_expr_string = try_opt!(rewrite_pat_expr(context,
-                                                    pat,
-                                                    cond,
-                                                    "let ",
-                                                    " =",
-                                                    width - 3 - 2,
-                                                    offset + 3));
-
-    let if_block_string = try_opt!(if_block.rewrite(context, width, offset));
+                   -> RewriteResult {
+    let pat_expr_width = try!(reserve(width, "if ").and_then(|w| reserve(w, " {")));
+    let pat_expr_string = try!(rewrite_pat_expr(context,
+                                                pat,
+                                                cond,
+                                                "let ",
+                                                " =",
+                                                pat_expr_width,
+                                                offset + "if ".len()));
+
+    let if_block_string = try!(if_block.rewrite(context, width, offset));
     let mut result = format!("if {} {}", pat_expr_string, if_block_string);
 
     if let Some(else_block) = else_block {
-        let else_block_string = try_opt!(else_block.rewrite(context, width, offset));
+        let else_block_string = try!(else_block.rewrite(context, width, offset));
 
         result.push_str(" else ");
         result.push_str(&else_block_string);
     }
 
-    Some(result)
+    Ok(result)
 }
 
 fn rewrite_pat_expr(context: &RewriteContext,
@@ -310,20 +623,20 @@ fn rewrite_pat_expr(context: &RewriteContext,
                     connector: &str,
                     width: usize,
                     offset: usize)
-                    -> Option<String> {
+                    -> RewriteResult {
+    let expr = strip_parens(context, expr, true);
     let pat_offset = offset + matcher.len();
     let mut result = match pat {
         Some(pat) => {
-            let pat_string = try_opt!(pat.rewrite(context,
-                                                  width - connector.len() - matcher.len(),
-                                                  pat_offset));
+            let pat_width = try!(reserve(width, connector).and_then(|w| reserve(w, matcher)));
+            let pat_string = try!(pat.rewrite(context, pat_width, pat_offset));
             format!("{}{}{}", matcher, pat_string, connector)
         }
-        None => String::new()
+        None => String::new(),
     };
 
     // Consider only the last line of the pat string.
-    let extra_offset = extra_offset(&result, offset);
+    let extra_offset = extra_offset(&result, offset, context.config.tab_width);
 
     // The expression may (partionally) fit on the current line.
     if width > extra_offset + 1 {
@@ -334,39 +647,406 @@ fn rewrite_pat_expr(context: &RewriteContext,
             corrected_offset += 1;
         }
 
-        let expr_rewrite = expr.rewrite(context,
-                                        width - corrected_offset,
-                                        offset + corrected_offset);
+        let expr_offset = offset + corrected_offset;
+        let expr_rewrite = rewrite_condition(context,
+                                             expr,
+                                             width - corrected_offset,
+                                             expr_offset,
+                                             condition_indent(context, expr_offset));
 
-        if let Some(expr_string) = expr_rewrite {
+        if let Ok(expr_string) = expr_rewrite {
             result.push_str(&expr_string);
-            return Some(result);
+            return Ok(result);
         }
     }
 
     // The expression won't fit on the current line, jump to next.
+    let expr_offset = condition_indent(context, pat_offset);
     result.push('\n');
-    result.push_str(&make_indent(pat_offset));
+    result.push_str(&make_indent(expr_offset));
+
+    let expr_width = try!(reserve_width(context.config.max_width, expr_offset));
+    let expr_rewrite = expr.rewrite(context, expr_width, expr_offset);
+    result.push_str(&&try!(expr_rewrite));
+
+    Ok(result)
+}
+
+// Only the scrutinee and the commas after the arms are formatted, the arms
+// themselves are copied as they are.
+fn rewrite_match(context: &RewriteContext,
+                 cond: &ast::Expr,
+                 arms: &[ast::Arm],
+                 span: Span,
+                 width: usize,
+                 offset: usize)
+                 -> RewriteResult {
+    // Leave matches with comments around the scrutinee alone.
+    let keyword = try!(context.snippet(mk_sp(span.lo, cond.span.lo)));
+    let rest = try!(context.snippet(mk_sp(cond.span.hi, span.hi)));
+    let brace_pos = rest.len() - rest.trim_left().len();
+    if keyword.trim() != "match" || !rest[brace_pos..].starts_with('{') {
+        return context.unformatted_snippet(span);
+    }
+
+    let cond_width = try!(reserve(width, "match ").and_then(|w| reserve(w, " {")));
+    let cond_offset = offset + "match ".len();
+    let cond_string = try!(rewrite_condition(context,
+                                             strip_parens(context, cond, true),
+                                             cond_width,
+                                             cond_offset,
+                                             condition_indent(context, cond_offset)));
+    let arms_string = try!(rewrite_match_arms(context,
+                                              arms,
+                                              cond.span.hi + BytePos(brace_pos as u32),
+                                              span.hi));
+
+    Ok(format!("match {} {}", cond_string, arms_string))
+}
+
+// Copies the arms of a match, from the opening brace at lo to the closing one
+// just before hi. Arms with an expression body are followed by a comma, those
+// with a block body only with match_block_trailing_comma.
+fn rewrite_match_arms(context: &RewriteContext,
+                      arms: &[ast::Arm],
+                      lo: BytePos,
+                      hi: BytePos)
+                      -> RewriteResult {
+    let mut result = String::new();
+    let mut last_pos = lo;
+
+    for (i, arm) in arms.iter().enumerate() {
+        let body_hi = arm.body.span.hi;
+        let next_lo = match arms.get(i + 1) {
+            Some(next) => arm_start(next),
+            None => hi - BytePos(1),
+        };
+        if body_hi < last_pos || next_lo < body_hi {
+            return context.unformatted_snippet(mk_sp(lo, hi));
+        }
+        result.push_str(&try!(context.unformatted_snippet(mk_sp(last_pos, body_hi))));
+
+        let needs_comma = match arm.body.node {
+            ast::Expr_::ExprBlock(..) => context.config.match_block_trailing_comma,
+            _ => true,
+        };
+        if needs_comma {
+            result.push(',');
+        }
+        // Drop any comma the arm already had, keeping comments before and
+        // after it.
+        let between = try!(context.snippet(mk_sp(body_hi, next_lo)));
+        match between.find_uncommented(",") {
+            Some(comma_pos) => {
+                result.push_str(&between[..comma_pos]);
+                result.push_str(&between[comma_pos + 1..]);
+            }
+            None => result.push_str(&between),
+        }
 
-    let expr_rewrite = expr.rewrite(context, context.config.max_width - pat_offset, pat_offset);
-    result.push_str(&&try_opt!(expr_rewrite));
+        last_pos = next_lo;
+    }
+    result.push_str(&try!(context.snippet(mk_sp(last_pos, hi))));
 
-    Some(result)
+    Ok(result)
+}
+
+fn arm_start(arm: &ast::Arm) -> BytePos {
+    match arm.attrs.first() {
+        Some(attr) => attr.span.lo,
+        None => arm.pats[0].span.lo,
+    }
+}
+
+fn rewrite_return(context: &RewriteContext,
+                  value: Option<&ast::Expr>,
+                  span: Span,
+                  width: usize,
+                  offset: usize)
+                  -> RewriteResult {
+    let value = match value {
+        Some(value) => value,
+        None => return Ok("return".to_owned()),
+    };
+    // Leave returns with comments before the value alone.
+    let keyword = try!(context.snippet(mk_sp(span.lo, value.span.lo)));
+    if keyword.trim() != "return" {
+        return context.unformatted_snippet(span);
+    }
+
+    let value_width = try!(reserve(width, "return "));
+    let value_string = try!(strip_parens(context, value, false)
+                                .rewrite(context, value_width, offset + "return ".len()));
+
+    Ok(format!("return {}", value_string))
+}
+
+// The header of a closure, `move |args| -> Ret`, goes on one line if it and the
+// first line of the body fit, otherwise the arguments are lined up one a line
+// after the first `|`. The return type follows the last argument if it fits
+// there, and starts a line lined up with the arguments if not. The body is
+// kept as written unless closure_indent_style says how to indent it.
+fn rewrite_closure(context: &RewriteContext,
+                   capture: ast::CaptureClause,
+                   fn_decl: &ast::FnDecl,
+                   body: &ast::Block,
+                   span: Span,
+                   width: usize,
+                   offset: usize)
+                   -> RewriteResult {
+    let mover = match capture {
+        ast::CaptureClause::CaptureByValue => "move ",
+        ast::CaptureClause::CaptureByRef => "",
+    };
+    // Leave closures with comments in their headers alone.
+    let header_snippet = try!(context.snippet(mk_sp(span.lo, body.span.lo)));
+    let bar = match header_snippet.find('|') {
+        Some(bar) if header_snippet[..bar].trim() == mover.trim() => bar,
+        _ => return context.unformatted_snippet(span),
+    };
+    if header_snippet.contains("//") || header_snippet.contains("/*") {
+        return context.unformatted_snippet(span);
+    }
+
+    let body_str = try!(context.unformatted_snippet(body.span));
+    // Only a block's `{` has to go on the line with the header.
+    let body_first_line = if body_str.starts_with('{') {
+        1
+    } else {
+        0
+    };
+    let ret_str = match fn_decl.output {
+        ast::FunctionRetTy::DefaultReturn(_) => String::new(),
+        ast::FunctionRetTy::NoReturn(_) => "-> !".to_owned(),
+        ast::FunctionRetTy::Return(ref ty) => format!("-> {}", pprust::ty_to_string(ty)),
+    };
+    let ret_width = if ret_str.is_empty() {
+        0
+    } else {
+        ret_str.len() + 1
+    };
+
+    let arg_offset = offset + mover.len() + "|".len();
+    let args_width = try!(reserve_width(width, mover.len()).and_then(|w| reserve(w, "||")));
+    let arg_strs = try!(fn_decl.inputs
+                               .iter()
+                               .map(|arg| {
+                                   rewrite_closure_arg(context, arg, args_width, arg_offset)
+                                       .or_else(|_| {
+                                           let arg_span = mk_sp(arg.pat.span.lo,
+                                                                closure_arg_hi(context, arg));
+                                           context.fallback_snippet(arg_span, arg_offset)
+                                       })
+                               })
+                               .collect::<Result<Vec<_>, _>>());
+    let items = itemize_list(context.codemap,
+                             Vec::new(),
+                             fn_decl.inputs.iter().zip(arg_strs),
+                             ",",
+                             "|",
+                             |&(arg, _)| arg.pat.span.lo,
+                             |&(arg, _)| closure_arg_hi(context, arg),
+                             |&(_, ref arg_str)| arg_str.clone(),
+                             span.lo + BytePos(bar as u32 + 1),
+                             body.span.lo);
+    let fmt = ListFormatting {
+        tactic: ListTactic::HorizontalVertical,
+        separator: ",",
+        trailing_separator: SeparatorTactic::Never,
+        separator_place: SeparatorPlace::Back,
+        indent: arg_offset,
+        h_width: saturating_reserve_width(args_width, ret_width + body_first_line + 1),
+        v_width: args_width,
+        ends_with_newline: false,
+        config: context.config,
+    };
+    let mut header = format!("{}|{}|", mover, write_list(&items, &fmt));
+
+    if !ret_str.is_empty() {
+        let header_width = extra_offset(&header, offset, context.config.tab_width);
+        if header_width + ret_width + body_first_line + 1 <= width {
+            header.push(' ');
+        } else {
+            header.push('\n');
+            header.push_str(&make_indent(arg_offset));
+        }
+        header.push_str(&ret_str);
+    }
+
+    let body_context = match context.config.closure_indent_style {
+        ClosureIndentStyle::Preserve => return Ok(format!("{} {}", header, body_str)),
+        ClosureIndentStyle::Visual => RewriteContext { block_indent: offset, ..*context },
+        ClosureIndentStyle::Block => {
+            RewriteContext { block_indent: context.stmt_indent, ..*context }
+        }
+    };
+    let header_width = extra_offset(&header, offset, context.config.tab_width) + " ".len();
+    let body_width = try!(reserve_width(width, header_width));
+    let body_offset = offset + header_width;
+    let body_str = match body.expr {
+        Some(ref expr) if body.stmts.is_empty() && expr.span == body.span => {
+            try!(expr.rewrite(&body_context, body_width, body_offset))
+        }
+        _ => try!(body.rewrite(&body_context, body_width, body_offset)),
+    };
+
+    Ok(format!("{} {}", header, body_str))
+}
+
+// The type of a closure argument, if it is given one.
+fn closure_arg_ty<'a>(context: &RewriteContext, arg: &'a ast::Arg) -> Option<&'a ast::Ty> {
+    // Arguments without a type have a placeholder spanning the next token.
+    match context.snippet(mk_sp(arg.pat.span.hi, arg.ty.span.lo)) {
+        Ok(ref between) if between.contains(':') => Some(&arg.ty),
+        _ => None,
+    }
+}
+
+fn closure_arg_hi(context: &RewriteContext, arg: &ast::Arg) -> BytePos {
+    match closure_arg_ty(context, arg) {
+        Some(ty) => ty.span.hi,
+        None => arg.pat.span.hi,
+    }
+}
+
+fn rewrite_closure_arg(context: &RewriteContext,
+                       arg: &ast::Arg,
+                       width: usize,
+                       offset: usize)
+                       -> RewriteResult {
+    let pat_str = try!(arg.pat.rewrite(context, width, offset));
+    match closure_arg_ty(context, arg) {
+        Some(ty) => Ok(format!("{}: {}", pat_str, pprust::ty_to_string(ty))),
+        None => Ok(pat_str),
+    }
+}
+
+// `break` or `continue`, with one space before the label if there is one.
+fn rewrite_jump(context: &RewriteContext,
+                keyword: &str,
+                span: Span,
+                width: usize)
+                -> RewriteResult {
+    let snippet = try!(context.snippet(span));
+    if snippet.contains("//") || snippet.contains("/*") || !snippet.starts_with(keyword) {
+        return context.unformatted_snippet(span);
+    }
+    let label = snippet[keyword.len()..].trim();
+    let result = if label.is_empty() {
+        keyword.to_owned()
+    } else {
+        format!("{} {}", keyword, label)
+    };
+    if result.len() > width {
+        return Err(RewriteError::WidthExhausted);
+    }
+    Ok(result)
+}
+
+// With remove_redundant_parens, expr without the parentheses around it. Only
+// call this where expr is the whole of a condition, a returned value or the
+// contents of other parentheses. Parentheses with comments in them are kept,
+// as are those in conditions around struct literals, which would otherwise be
+// read as the block.
+fn strip_parens<'e>(context: &RewriteContext,
+                    expr: &'e ast::Expr,
+                    in_condition: bool)
+                    -> &'e ast::Expr {
+    if !context.config.remove_redundant_parens {
+        return expr;
+    }
+
+    let mut expr = expr;
+    while let ast::Expr_::ExprParen(ref subexpr) = expr.node {
+        if in_condition && contains_struct_lit(subexpr) {
+            break;
+        }
+        if !parens_are_blank(context, expr.span, subexpr.span) {
+            break;
+        }
+        expr = &**subexpr;
+    }
+
+    expr
+}
+
+// Whether there is only whitespace between the parentheses spanning span and
+// the expression spanning inner.
+fn parens_are_blank(context: &RewriteContext, span: Span, inner: Span) -> bool {
+    let is_blank = |lo, hi| {
+        context.snippet(mk_sp(lo, hi)).map(|s| s.trim().is_empty()).unwrap_or(false)
+    };
+    is_blank(span.lo + BytePos(1), inner.lo) && is_blank(inner.hi, span.hi - BytePos(1))
+}
+
+fn contains_struct_lit(expr: &ast::Expr) -> bool {
+    struct StructLitFinder {
+        found: bool,
+    }
+
+    impl<'v> Visitor<'v> for StructLitFinder {
+        fn visit_expr(&mut self, ex: &'v ast::Expr) {
+            match ex.node {
+                ast::Expr_::ExprStruct(..) => self.found = true,
+                _ => visit::walk_expr(self, ex),
+            }
+        }
+
+        // Macros could expand to anything, assume the worst.
+        fn visit_mac(&mut self, _: &'v ast::Mac) {
+            self.found = true;
+        }
+    }
+
+    let mut finder = StructLitFinder { found: false };
+    finder.visit_expr(expr);
+    finder.found
+}
+
+// The column the continuation lines of a condition starting at offset go in.
+fn condition_indent(context: &RewriteContext, offset: usize) -> usize {
+    match context.config.condition_indent_style {
+        ConditionIndentStyle::Visual => offset,
+        ConditionIndentStyle::Double => context.block_indent + 2 * context.config.tab_spaces,
+    }
+}
+
+// Rewrites a condition starting at offset, with the lines of a chain of binary
+// operators after the first starting at indent.
+fn rewrite_condition(context: &RewriteContext,
+                     expr: &ast::Expr,
+                     width: usize,
+                     offset: usize,
+                     indent: usize)
+                     -> RewriteResult {
+    match expr.node {
+        ast::Expr_::ExprBinary(ref op, ref lhs, ref rhs) if indent != offset => {
+            let operator_str = ast_util::binop_to_string(op.node);
+            rewrite_binary_op_indented(context, operator_str, lhs, rhs, width, offset, indent)
+        }
+        _ => expr.rewrite(context, width, offset),
+    }
 }
 
 fn rewrite_string_lit(context: &RewriteContext,
-                      s: &str,
                       span: Span,
                       width: usize,
                       offset: usize)
-                      -> Option<String> {
+                      -> RewriteResult {
     // Check if there is anything to fix: we always try to fixup multi-line
     // strings, or if the string is too long for the line.
     let l_loc = context.codemap.lookup_char_pos(span.lo);
     let r_loc = context.codemap.lookup_char_pos(span.hi);
     if l_loc.line == r_loc.line && r_loc.col.to_usize() <= context.config.max_width {
-        return context.codemap.span_to_snippet(span).ok();
+        return context.snippet(span);
     }
+    // The text is split as written, so escapes are never re-escaped or
+    // normalized. Strings with newlines in them are left alone.
+    let snippet = try!(context.snippet(span));
+    let body = match string_lit_body(&snippet) {
+        Some(ref body) if !body.contains('\n') => body.clone(),
+        _ => return Ok(snippet),
+    };
     let fmt = StringFormat {
         opener: "\"",
         closer: "\"",
@@ -375,9 +1055,10 @@ fn rewrite_string_lit(context: &RewriteContext,
         width: width,
         offset: offset,
         trim_end: false,
+        tab_width: context.config.tab_width,
     };
 
-    Some(rewrite_string(&s.escape_default(), &fmt))
+    Ok(rewrite_string(&body, &fmt))
 }
 
 fn rewrite_call(context: &RewriteContext,
@@ -386,51 +1067,147 @@ fn rewrite_call(context: &RewriteContext,
                 span: Span,
                 width: usize,
                 offset: usize)
-                -> Option<String> {
+                -> RewriteResult {
     debug!("rewrite_call, width: {}, offset: {}", width, offset);
 
     // TODO using byte lens instead of char lens (and probably all over the place too)
-    // 2 is for parens
-    let max_callee_width = try_opt!(width.checked_sub(2));
-    let callee_str = try_opt!(callee.rewrite(context, max_callee_width, offset));
+    let max_callee_width = try!(reserve(width, "()"));
+    let callee_str = try!(callee.rewrite(context, max_callee_width, offset));
     debug!("rewrite_call, callee_str: `{}`", callee_str);
 
+    rewrite_call_args(context,
+                      &callee_str,
+                      args,
+                      callee.span.hi + BytePos(1),
+                      span,
+                      |arg, context, width, offset| arg.rewrite(context, width, offset),
+                      context.config.trailing_comma,
+                      width,
+                      offset)
+}
+
+// Rewrites the arguments of a call to callee_str, which is already written
+// from offset, with rewrite_arg. args_lo is just after the opening paren.
+// trailing_separator says when to put a comma after the last argument, which
+// macros, unlike functions, needn't accept.
+pub fn rewrite_call_args<F>(context: &RewriteContext,
+                            callee_str: &str,
+                            args: &[ptr::P<ast::Expr>],
+                            args_lo: BytePos,
+                            span: Span,
+                            rewrite_arg: F,
+                            trailing_separator: SeparatorTactic,
+                            width: usize,
+                            offset: usize)
+                            -> RewriteResult
+    where F: Fn(&ast::Expr, &RewriteContext, usize, usize) -> RewriteResult
+{
     if args.len() == 0 {
-        return Some(format!("{}()", callee_str));
+        return Ok(format!("{}()", callee_str));
     }
 
-    let extra_offset = extra_offset(&callee_str, offset);
-    // 2 is for parens.
-    let remaining_width = try_opt!(width.checked_sub(extra_offset + 2));
-    let offset = offset + extra_offset + 1;
+    let extra_offset = extra_offset(callee_str, offset, context.config.tab_width);
+    let remaining_width = try!(reserve_width(width, extra_offset).and_then(|w| reserve(w, "()")));
+    let offset = offset + extra_offset + "(".len();
     let block_indent = expr_block_indent(context, offset);
     let inner_context = &RewriteContext { block_indent: block_indent, ..*context };
 
+    if let Some(args_str) = overflow_last_closure(context,
+                                                  args,
+                                                  args_lo,
+                                                  span,
+                                                  &rewrite_arg,
+                                                  remaining_width,
+                                                  offset) {
+        return Ok(format!("{}({})", callee_str, args_str));
+    }
+
+    // Take old span when rewrite fails.
+    let arg_strs = try!(args.iter()
+                            .map(|arg| {
+                                rewrite_arg(&**arg, inner_context, remaining_width, offset)
+                                    .or_else(|_| context.fallback_snippet(arg.span, offset))
+                            })
+                            .collect::<Result<Vec<_>, _>>());
     let items = itemize_list(context.codemap,
                              Vec::new(),
-                             args.iter(),
+                             args.iter().zip(arg_strs),
                              ",",
                              ")",
-                             |item| item.span.lo,
-                             |item| item.span.hi,
-                             // Take old span when rewrite fails.
-                             |item| item.rewrite(inner_context, remaining_width, offset)
-                                        .unwrap_or(context.codemap.span_to_snippet(item.span)
-                                                                  .unwrap()),
-                             callee.span.hi + BytePos(1),
+                             |&(arg, _)| arg.span.lo,
+                             |&(arg, _)| arg.span.hi,
+                             |&(_, ref arg_str)| arg_str.clone(),
+                             args_lo,
                              span.hi);
 
     let fmt = ListFormatting {
         tactic: ListTactic::HorizontalVertical,
         separator: ",",
-        trailing_separator: SeparatorTactic::Never,
+        trailing_separator: trailing_separator,
+        separator_place: SeparatorPlace::Back,
         indent: offset,
         h_width: remaining_width,
         v_width: remaining_width,
         ends_with_newline: false,
+        config: context.config,
     };
 
-    Some(format!("{}({})", callee_str, write_list(&items, &fmt)))
+    Ok(format!("{}({})", callee_str, write_list(&items, &fmt)))
+}
+
+// With closure_indent_style = "Block", a call's arguments all on the line with
+// the call when the last is a closure with a block body, which then carries on
+// past the end of that line: `foo(a, |x| {`. None if they can't go that way.
+fn overflow_last_closure<F>(context: &RewriteContext,
+                            args: &[ptr::P<ast::Expr>],
+                            args_lo: BytePos,
+                            span: Span,
+                            rewrite_arg: &F,
+                            width: usize,
+                            offset: usize)
+                            -> Option<String>
+    where F: Fn(&ast::Expr, &RewriteContext, usize, usize) -> RewriteResult
+{
+    if context.config.closure_indent_style != ClosureIndentStyle::Block {
+        return None;
+    }
+    let last = try_opt!(args.last());
+    let body = match last.node {
+        ast::Expr_::ExprClosure(_, _, ref body) => body,
+        _ => return None,
+    };
+    let body_snippet = try_opt!(context.snippet(body.span).ok());
+    if !body_snippet.starts_with('{') {
+        return None;
+    }
+    // Comments between the arguments would have to move.
+    let before_last = try_opt!(context.snippet(mk_sp(args_lo, last.span.lo)).ok());
+    let after_last = try_opt!(context.snippet(mk_sp(last.span.hi, span.hi)).ok());
+    if before_last.contains("//") || before_last.contains("/*") ||
+       after_last.contains("//") || after_last.contains("/*") {
+        return None;
+    }
+
+    let tab_width = context.config.tab_width;
+    let mut prefix = String::new();
+    let mut prefix_width = 0;
+    for arg in &args[..args.len() - 1] {
+        let arg_str = try_opt!(rewrite_arg(&**arg, context, width, offset + prefix_width).ok());
+        if arg_str.contains('\n') {
+            return None;
+        }
+        prefix.push_str(&arg_str);
+        prefix.push_str(", ");
+        prefix_width = str_width(&prefix, tab_width);
+    }
+    let last_width = try_opt!(reserve_width(width, prefix_width).ok());
+    let last_str = try_opt!(rewrite_arg(&**last, context, last_width, offset + prefix_width).ok());
+    let first_line = last_str.lines().next().unwrap_or("");
+    if !first_line.ends_with('{') || prefix_width + str_width(first_line, tab_width) > width {
+        return None;
+    }
+
+    Some(format!("{}{}", prefix, last_str))
 }
 
 fn expr_block_indent(context: &RewriteContext, offset: usize) -> usize {
@@ -443,13 +1220,19 @@ fn expr_block_indent(context: &RewriteContext, offset: usize) -> usize {
 
 fn rewrite_paren(context: &RewriteContext,
                  subexpr: &ast::Expr,
+                 span: Span,
                  width: usize,
                  offset: usize)
-                 -> Option<String> {
+                 -> RewriteResult {
     debug!("rewrite_paren, width: {}, offset: {}", width, offset);
-    // 1 is for opening paren, 2 is for opening+closing, we want to keep the closing
-    // paren on the same line as the subexpr
-    let subexpr_str = subexpr.rewrite(context, width-2, offset+1);
+    // Leave parentheses with comments in them alone.
+    if !parens_are_blank(context, span, subexpr.span) {
+        return context.unformatted_snippet(span);
+    }
+    // Doubled up parentheses are always redundant.
+    let subexpr = strip_parens(context, subexpr, false);
+    // We want to keep the closing paren on the same line as the subexpr.
+    let subexpr_str = subexpr.rewrite(context, try!(reserve(width, "()")), offset + "(".len());
     debug!("rewrite_paren, subexpr_str: `{:?}`", subexpr_str);
     subexpr_str.map(|s| format!("({})", s))
 }
@@ -461,7 +1244,7 @@ fn rewrite_struct_lit<'a>(context: &RewriteContext,
                           span: Span,
                           width: usize,
                           offset: usize)
-                          -> Option<String> {
+                          -> RewriteResult {
     debug!("rewrite_struct_lit: width {}, offset {}", width, offset);
     assert!(fields.len() > 0 || base.is_some());
 
@@ -470,14 +1253,12 @@ fn rewrite_struct_lit<'a>(context: &RewriteContext,
         Base(&'a ast::Expr),
     }
 
-    // 2 = " {".len()
-    let path_str = try_opt!(path.rewrite(context, width - 2, offset));
+    let path_str = try!(path.rewrite(context, try!(reserve(width, " {")), offset));
 
-    // Foo { a: Foo } - indent is +3, width is -5.
-    let h_budget = width.checked_sub(path_str.len() + 5).unwrap_or(0);
+    let h_budget = saturating_reserve(saturating_reserve_width(width, path_str.len()), " {  }");
     let (indent, v_budget) = match context.config.struct_lit_style {
         StructLitStyle::VisualIndent => {
-            (offset + path_str.len() + 3, h_budget)
+            (offset + path_str.len() + " { ".len(), h_budget)
         }
         StructLitStyle::BlockIndent => {
             // If we are all on one line, then we'll ignore the indent, and we
@@ -488,46 +1269,65 @@ fn rewrite_struct_lit<'a>(context: &RewriteContext,
         }
     };
 
-    let field_iter = fields.into_iter().map(StructLitField::Regular)
-                           .chain(base.into_iter().map(StructLitField::Base));
+    let struct_fields: Vec<_> = fields.into_iter()
+                                      .map(StructLitField::Regular)
+                                      .chain(base.into_iter().map(StructLitField::Base))
+                                      .collect();
 
     let inner_context = &RewriteContext { block_indent: indent, ..*context };
 
+    let field_strs = try!(struct_fields.iter()
+                                       .map(|item| {
+                                           match *item {
+                                               StructLitField::Regular(field) => {
+                                                   rewrite_field(inner_context,
+                                                                 field,
+                                                                 h_budget,
+                                                                 indent)
+                                                       .or_else(|_| {
+                                                           context.fallback_snippet(field.span,
+                                                                                    indent)
+                                                       })
+                                               }
+                                               StructLitField::Base(expr) => {
+                                                   let budget = saturating_reserve(h_budget,
+                                                                                   "..");
+                                                   let expr_indent = indent + "..".len();
+                                                   expr.rewrite(inner_context,
+                                                                budget,
+                                                                expr_indent)
+                                                       .or_else(|_| {
+                                                           context.fallback_snippet(expr.span,
+                                                                                    expr_indent)
+                                                       })
+                                                       .map(|expr_str| format!("..{}", expr_str))
+                                               }
+                                           }
+                                       })
+                                       .collect::<Result<Vec<_>, _>>());
+
+    let list_lo = try!(span_after(span, "{", context.codemap));
     let items = itemize_list(context.codemap,
                              Vec::new(),
-                             field_iter,
+                             struct_fields.iter().zip(field_strs),
                              ",",
                              "}",
-                             |item| {
+                             |&(item, _)| {
                                  match *item {
                                      StructLitField::Regular(ref field) => field.span.lo,
-                                     // 2 = ..
-                                     StructLitField::Base(ref expr) => expr.span.lo - BytePos(2)
+                                     StructLitField::Base(ref expr) => {
+                                         expr.span.lo - BytePos("..".len() as u32)
+                                     }
                                  }
                              },
-                             |item| {
+                             |&(item, _)| {
                                  match *item {
                                      StructLitField::Regular(ref field) => field.span.hi,
-                                     StructLitField::Base(ref expr) => expr.span.hi
+                                     StructLitField::Base(ref expr) => expr.span.hi,
                                  }
                              },
-                             |item| {
-                                 match *item {
-                                     StructLitField::Regular(ref field) => {
-                                         rewrite_field(inner_context, &field, h_budget, indent)
-                                            .unwrap_or(context.codemap.span_to_snippet(field.span)
-                                                                      .unwrap())
-                                     },
-                                     StructLitField::Base(ref expr) => {
-                                         // 2 = ..
-                                         expr.rewrite(inner_context, h_budget - 2, indent + 2)
-                                             .map(|s| format!("..{}", s))
-                                             .unwrap_or(context.codemap.span_to_snippet(expr.span)
-                                                                       .unwrap())
-                                     }
-                                 }
-                             },
-                             span_after(span, "{", context.codemap),
+                             |&(_, ref field_str)| field_str.clone(),
+                             list_lo,
                              span.hi);
 
     let fmt = ListFormatting {
@@ -538,34 +1338,106 @@ fn rewrite_struct_lit<'a>(context: &RewriteContext,
         } else {
             context.config.struct_lit_trailing_comma
         },
+        separator_place: SeparatorPlace::Back,
         indent: indent,
-        h_width: h_budget,
+        h_width: struct_lit_h_width(context, h_budget),
         v_width: v_budget,
         ends_with_newline: false,
+        config: context.config,
+    };
+    let tactic = definitive_tactic(&items, &fmt);
+    let items = if tactic == ListTactic::Vertical && context.config.struct_lit_align_threshold > 0 {
+        let names: Vec<_> = fields.iter().map(|field| field.ident.node.to_string()).collect();
+        align_fields(items,
+                     &names,
+                     context.config.struct_lit_align_threshold,
+                     saturating_reserve(v_budget, ","),
+                     context.config.tab_width)
+    } else {
+        items
     };
     let fields_str = write_list(&items, &fmt);
 
     match context.config.struct_lit_style {
-        StructLitStyle::BlockIndent if fields_str.contains('\n') => {
+        StructLitStyle::BlockIndent if tactic == ListTactic::Vertical => {
             let inner_indent = make_indent(context.block_indent + context.config.tab_spaces);
             let outer_indent = make_indent(context.block_indent);
-            Some(format!("{} {{\n{}{}\n{}}}", path_str, inner_indent, fields_str, outer_indent))
+            Ok(format!("{} {{\n{}{}\n{}}}", path_str, inner_indent, fields_str, outer_indent))
         }
-        _ => Some(format!("{} {{ {} }}", path_str, fields_str)),
+        _ => Ok(format!("{} {{ {} }}", path_str, fields_str)),
     }
 
     // FIXME if context.config.struct_lit_style == VisualIndent, but we run out
     // of space, we should fall back to BlockIndent.
 }
 
+// The width the fields of a struct literal have to go on one line, out of
+// budget, which is none at all if they mustn't.
+fn struct_lit_h_width(context: &RewriteContext, budget: usize) -> usize {
+    if !context.config.struct_lit_single_line {
+        0
+    } else if context.config.struct_lit_width > 0 {
+        cmp::min(budget, context.config.struct_lit_width)
+    } else {
+        budget
+    }
+}
+
+// Pads the names of single line fields so their values line up with those of
+// the other fields in their group, a run of fields with no blank lines between
+// them. Fields which would need more than `threshold` columns of padding, or
+// would no longer fit in `width`, are left alone. `names` are the names of the
+// first items, any base expression comes after them.
+fn align_fields(mut items: Vec<ListItem>,
+                names: &[String],
+                threshold: usize,
+                width: usize,
+                tab_width: usize)
+                -> Vec<ListItem> {
+    let is_aligned = |item: &ListItem, name: &str| {
+        !item.item.contains('\n') && item.item.starts_with(&format!("{}: ", name))
+    };
+
+    let mut group_start = 0;
+    while group_start < names.len() {
+        let group_end = (group_start + 1..names.len())
+                            .find(|&i| items[i].blank_lines_before > 0)
+                            .unwrap_or(names.len());
+        let name_width = (group_start..group_end)
+                             .filter(|&i| is_aligned(&items[i], &names[i]))
+                             .map(|i| names[i].len())
+                             .max()
+                             .unwrap_or(0);
+
+        for i in group_start..group_end {
+            let padding = name_width.checked_sub(names[i].len()).unwrap_or(0);
+            if padding == 0 || padding > threshold || !is_aligned(&items[i], &names[i]) ||
+               str_width(&items[i].item, tab_width) + padding > width {
+                continue;
+            }
+            let value = items[i].item[names[i].len() + ": ".len()..].to_owned();
+            items[i].item = format!("{}:{} {}", names[i], make_indent(padding), value);
+        }
+
+        group_start = group_end;
+    }
+
+    items
+}
+
 fn rewrite_field(context: &RewriteContext,
                  field: &ast::Field,
                  width: usize,
                  offset: usize)
-                 -> Option<String> {
+                 -> RewriteResult {
     let name = &field.ident.node.to_string();
-    let overhead = name.len() + 2;
-    let expr = field.expr.rewrite(context, width - overhead, offset + overhead);
+    // Leave fields with comments between the name and the value alone.
+    let between = try!(context.snippet(mk_sp(field.ident.span.hi, field.expr.span.lo)));
+    if between.trim() != ":" {
+        return Err(RewriteError::Comment);
+    }
+    let overhead = name.len() + ": ".len();
+    let expr = field.expr.rewrite(context, try!(reserve_width(width, overhead)), offset + overhead);
     expr.map(|s| format!("{}: {}", name, s))
 }
 
@@ -574,41 +1446,118 @@ fn rewrite_tuple_lit(context: &RewriteContext,
                      span: Span,
                      width: usize,
                      offset: usize)
-                     -> Option<String> {
+                     -> RewriteResult {
     debug!("rewrite_tuple_lit: width: {}, offset: {}", width, offset);
-    let indent = offset + 1;
+    let indent = offset + "(".len();
     // In case of length 1, need a trailing comma
     if items.len() == 1 {
-        // 3 = "(" + ",)"
-        return items[0].rewrite(context, width - 3, indent).map(|s| format!("({},)", s));
+        let item_width = try!(reserve(width, "(,)"));
+        return items[0].rewrite(context, item_width, indent).map(|s| format!("({},)", s));
     }
 
+    let item_strs = try!(rewrite_list_items(context, items, indent));
     let items = itemize_list(context.codemap,
                              Vec::new(),
-                             items.into_iter(),
+                             items.iter().zip(item_strs),
                              ",",
                              ")",
-                             |item| item.span.lo,
-                             |item| item.span.hi,
-                             |item| item.rewrite(context,
-                                                 context.config.max_width - indent - 1,
-                                                 indent)
-                                        .unwrap_or(context.codemap.span_to_snippet(item.span)
-                                                                  .unwrap()),
+                             |&(item, _)| item.span.lo,
+                             |&(item, _)| item.span.hi,
+                             |&(_, ref item_str)| item_str.clone(),
                              span.lo + BytePos(1), // Remove parens
                              span.hi - BytePos(1));
 
+    let list_width = try!(reserve(width, "()"));
     let fmt = ListFormatting {
         tactic: ListTactic::HorizontalVertical,
         separator: ",",
+        trailing_separator: context.config.trailing_comma,
+        separator_place: SeparatorPlace::Back,
+        indent: indent,
+        h_width: list_width,
+        v_width: list_width,
+        ends_with_newline: false,
+        config: context.config,
+    };
+
+    Ok(format!("({})", write_list(&items, &fmt)))
+}
+
+// The items of a tuple or array literal, each rewritten to start at indent, or
+// as written if that fails.
+fn rewrite_list_items(context: &RewriteContext,
+                      items: &[ptr::P<ast::Expr>],
+                      indent: usize)
+                      -> Result<Vec<String>, RewriteError> {
+    let item_width = saturating_reserve_width(context.config.max_width, indent + 1);
+    items.iter()
+         .map(|item| {
+             item.rewrite(context, item_width, indent)
+                 .or_else(|_| context.fallback_snippet(item.span, indent))
+         })
+         .collect()
+}
+
+fn rewrite_array_lit(context: &RewriteContext,
+                     items: &[ptr::P<ast::Expr>],
+                     span: Span,
+                     width: usize,
+                     offset: usize)
+                     -> RewriteResult {
+    debug!("rewrite_array_lit: width: {}, offset: {}", width, offset);
+    let indent = offset + "[".len();
+    // Long arrays of numbers and the like would be far too tall one per line,
+    // so they are packed into as few lines as possible instead.
+    let tactic = if items.iter().all(|item| is_simple_lit(item)) {
+        ListTactic::Mixed
+    } else {
+        ListTactic::HorizontalVertical
+    };
+
+    let item_strs = try!(rewrite_list_items(context, items, indent));
+    let items = itemize_list(context.codemap,
+                             Vec::new(),
+                             items.iter().zip(item_strs),
+                             ",",
+                             "]",
+                             |&(item, _)| item.span.lo,
+                             |&(item, _)| item.span.hi,
+                             |&(_, ref item_str)| item_str.clone(),
+                             span.lo + BytePos(1), // Remove brackets
+                             span.hi - BytePos(1));
+
+    let list_width = try!(reserve(width, "[]"));
+    let fmt = ListFormatting {
+        tactic: tactic,
+        separator: ",",
+        // The closing bracket comes straight after the last item, and arrays
+        // of literals are packed several to a line, so a trailing comma would
+        // only be in the way.
         trailing_separator: SeparatorTactic::Never,
+        separator_place: SeparatorPlace::Back,
         indent: indent,
-        h_width: width - 2,
-        v_width: width - 2,
+        h_width: list_width,
+        v_width: list_width,
         ends_with_newline: false,
+        config: context.config,
     };
 
-    Some(format!("({})", write_list(&items, &fmt)))
+    Ok(format!("[{}]", write_list(&items, &fmt)))
+}
+
+// Literals other than strings, possibly negated, which are short enough to
+// pack several to a line.
+fn is_simple_lit(expr: &ast::Expr) -> bool {
+    match expr.node {
+        ast::Expr_::ExprLit(ref l) => {
+            match l.node {
+                ast::Lit_::LitStr(..) => false,
+                _ => true,
+            }
+        }
+        ast::Expr_::ExprUnary(ast::UnOp::UnNeg, ref subexpr) => is_simple_lit(subexpr),
+        _ => false,
+    }
 }
 
 fn rewrite_binary_op(context: &RewriteContext,
@@ -617,43 +1566,154 @@ fn rewrite_binary_op(context: &RewriteContext,
                      rhs: &ast::Expr,
                      width: usize,
                      offset: usize)
-                     -> Option<String> {
-    // FIXME: format comments between operands and operator
+                     -> RewriteResult {
+    let operator_str = ast_util::binop_to_string(op.node);
+    rewrite_binary_op_indented(context, operator_str, lhs, rhs, width, offset, offset)
+}
 
-    let operator_str = context.codemap.span_to_snippet(op.span).unwrap();
+// Assignments are laid out like binary operators, e.g., `a += b`.
+fn rewrite_assignment(context: &RewriteContext,
+                      op: Option<&ast::BinOp>,
+                      lhs: &ast::Expr,
+                      rhs: &ast::Expr,
+                      width: usize,
+                      offset: usize)
+                      -> RewriteResult {
+    let operator_str = match op {
+        Some(op) => format!("{}=", ast_util::binop_to_string(op.node)),
+        None => "=".to_owned(),
+    };
+    rewrite_binary_op_indented(context, &operator_str, lhs, rhs, width, offset, offset)
+}
+
+// As rewrite_binary_op, with any operand which doesn't fit on the line before
+// starting a line at indent. The operator is written as the AST has it rather
+// than copied from the source, where the lexer may have split or joined
+// tokens, e.g., `>>` and `>=`.
+fn rewrite_binary_op_indented(context: &RewriteContext,
+                              operator_str: &str,
+                              lhs: &ast::Expr,
+                              rhs: &ast::Expr,
+                              width: usize,
+                              offset: usize,
+                              indent: usize)
+                              -> RewriteResult {
+    // FIXME: format comments between operands and operator
 
-    // 1 = space between lhs expr and operator
-    let mut result =
-        try_opt!(lhs.rewrite(context,
-                             context.config.max_width - offset - 1 - operator_str.len(),
-                             offset));
+    let lhs_width = try!(reserve_width(context.config.max_width, offset)
+                             .and_then(|w| reserve(w, " "))
+                             .and_then(|w| reserve(w, operator_str)));
+    let mut result = try!(rewrite_condition(context, lhs, lhs_width, offset, indent));
 
     result.push(' ');
-    result.push_str(&operator_str);
+    result.push_str(operator_str);
 
-    // 1 = space between operator and rhs
-    let used_width = result.len() + 1;
+    // The space between the operator and the rhs.
+    let used_width = result.len() + " ".len();
     let remaining_width = match result.rfind('\n') {
-        Some(idx) => (offset + width + idx).checked_sub(used_width).unwrap_or(0),
-        None => width.checked_sub(used_width).unwrap_or(0)
+        Some(idx) => saturating_reserve_width(offset + width + idx, used_width),
+        None => saturating_reserve_width(width, used_width),
     };
 
     // Get "full width" rhs and see if it fits on the current line. This
     // usually works fairly well since it tends to place operands of
-    // operations with high precendence close together.
-    let rhs_result = try_opt!(rhs.rewrite(context, width, offset));
+    // operations with high precendence close together. The right edge stays
+    // where it was if the rhs starts a line further left or right.
+    let rhs_width = try!(reserve_width(offset + width, indent));
+    let rhs_result = try!(rhs.rewrite(context, rhs_width, indent));
 
     // Second condition is needed in case of line break not caused by a
     // shortage of space, but by end-of-line comments, for example.
     if rhs_result.len() > remaining_width || rhs_result.contains('\n') {
         result.push('\n');
-        result.push_str(&make_indent(offset));
+        result.push_str(&make_indent(indent));
     } else {
         result.push(' ');
     };
 
     result.push_str(&rhs_result);
-    Some(result)
+    Ok(result)
+}
+
+// The operands of the chain of `+`s expr, from left to right.
+fn add_operands<'e>(expr: &'e ast::Expr) -> Vec<&'e ast::Expr> {
+    match expr.node {
+        ast::Expr_::ExprBinary(ref op, ref lhs, ref rhs) if op.node == ast::BinOp_::BiAdd => {
+            let mut operands = add_operands(lhs);
+            operands.push(rhs);
+            operands
+        }
+        _ => vec![expr],
+    }
+}
+
+// Whether operands are a chain of `+`s with string literals in it, and
+// nothing but the operators between them.
+fn is_string_concat(context: &RewriteContext, operands: &[&ast::Expr]) -> bool {
+    operands.iter().any(|operand| is_str_lit_expr(operand)) &&
+    operands.windows(2).all(|pair| {
+        context.snippet(mk_sp(pair[0].span.hi, pair[1].span.lo))
+               .map(|between| between.trim() == "+")
+               .unwrap_or(false)
+    })
+}
+
+fn is_str_lit_expr(expr: &ast::Expr) -> bool {
+    match expr.node {
+        ast::Expr_::ExprLit(ref lit) => macros::is_str_lit(lit),
+        _ => false,
+    }
+}
+
+// A string concatenation goes on one line if it fits, and otherwise has one
+// operand a line, lined up with the first, with the operators at the ends of
+// the lines as for other binary operators. The literals are kept as written
+// rather than split.
+fn rewrite_string_concat(context: &RewriteContext,
+                         operands: &[&ast::Expr],
+                         width: usize,
+                         offset: usize)
+                         -> RewriteResult {
+    let last = operands.len() - 1;
+    let mut operand_strs = Vec::with_capacity(operands.len());
+    for (i, operand) in operands.iter().enumerate() {
+        let operand_str = if is_str_lit_expr(operand) {
+            try!(context.snippet(operand.span))
+        } else {
+            let operand_width = if i == last {
+                width
+            } else {
+                try!(reserve(width, " +"))
+            };
+            try!(operand.rewrite(context, operand_width, offset))
+        };
+        operand_strs.push(operand_str);
+    }
+
+    let one_line = operand_strs.join(" + ");
+    if !one_line.contains('\n') && str_width(&one_line, context.config.tab_width) <= width {
+        return Ok(one_line);
+    }
+    Ok(operand_strs.join(&format!(" +\n{}", make_indent(offset))))
+}
+
+// `box x`, as an expression or a pattern, with one space after the keyword.
+// Anything else between the keyword and x, a comment or a place for placement
+// box, is left as it is.
+fn rewrite_box<R: Rewrite>(context: &RewriteContext,
+                           inner: &R,
+                           inner_span: Span,
+                           span: Span,
+                           width: usize,
+                           offset: usize)
+                           -> RewriteResult {
+    let keyword = try!(context.snippet(mk_sp(span.lo, inner_span.lo)));
+    if keyword.trim() != "box" {
+        return context.unformatted_snippet(span);
+    }
+    let inner_width = try!(reserve(width, "box "));
+    let inner_str = try!(inner.rewrite(context, inner_width, offset + "box ".len()));
+    Ok(format!("box {}", inner_str))
 }
 
 fn rewrite_unary_op(context: &RewriteContext,
@@ -661,16 +1721,16 @@ fn rewrite_unary_op(context: &RewriteContext,
                     expr: &ast::Expr,
                     width: usize,
                     offset: usize)
-                    -> Option<String> {
+                    -> RewriteResult {
     // For some reason, an UnOp is not spanned like BinOp!
     let operator_str = match *op {
         ast::UnOp::UnUniq => "box ",
         ast::UnOp::UnDeref => "*",
         ast::UnOp::UnNot => "!",
-        ast::UnOp::UnNeg => "-"
+        ast::UnOp::UnNeg => "-",
     };
 
-    let subexpr = try_opt!(expr.rewrite(context, width - operator_str.len(), offset));
+    let subexpr = try!(expr.rewrite(context, try!(reserve(width, operator_str)), offset));
 
-    Some(format!("{}{}", operator_str, subexpr))
+    Ok(format!("{}{}", operator_str, subexpr))
 }
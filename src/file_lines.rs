@@ -0,0 +1,139 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Restricting formatting to certain lines of certain files.
+
+use std::path::Path;
+
+use rustc_serialize::{Decodable, Decoder};
+
+// An inclusive range of lines, numbered from 1.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct LineRange {
+    pub lo: usize,
+    pub hi: usize,
+}
+
+impl LineRange {
+    pub fn intersects(&self, other: LineRange) -> bool {
+        self.lo <= other.hi && other.lo <= self.hi
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+struct FileRange {
+    // None means the range applies to every file.
+    file: Option<String>,
+    range: LineRange,
+}
+
+// The lines rustfmt is allowed to change. By default that is all lines of all
+// files.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct FileLines(Option<Vec<FileRange>>);
+
+impl FileLines {
+    pub fn all() -> FileLines {
+        FileLines(None)
+    }
+
+    pub fn is_all(&self) -> bool {
+        self.0.is_none()
+    }
+
+    // Parses and adds a range of the form `[FILE:]LO-HI`.
+    pub fn add_spec(&mut self, spec: &str) -> Result<(), String> {
+        let (file, range) = match spec.rfind(':') {
+            Some(i) => (Some(spec[..i].to_owned()), &spec[i + 1..]),
+            None => (None, spec),
+        };
+        let range = try!(parse_range(range).ok_or(format!("bad line range: `{}`", spec)));
+
+        if self.0.is_none() {
+            self.0 = Some(Vec::new());
+        }
+        self.0.as_mut().unwrap().push(FileRange { file: file, range: range });
+        Ok(())
+    }
+
+    // Returns true if any line in range of file may be formatted.
+    pub fn intersects(&self, file: &str, range: LineRange) -> bool {
+        match self.0 {
+            None => true,
+            Some(ref ranges) => {
+                ranges.iter().any(|r| {
+                    r.range.intersects(range) &&
+                    r.file.as_ref().map_or(true, |f| same_file(f, file))
+                })
+            }
+        }
+    }
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    let (a, b) = (Path::new(a), Path::new(b));
+    a == b || a.ends_with(b) || b.ends_with(a)
+}
+
+fn parse_range(s: &str) -> Option<LineRange> {
+    let mut parts = s.splitn(2, '-');
+    let lo = match parts.next().and_then(|lo| lo.trim().parse().ok()) {
+        Some(lo) => lo,
+        None => return None,
+    };
+    let hi = match parts.next() {
+        Some(hi) => match hi.trim().parse().ok() {
+            Some(hi) => hi,
+            None => return None,
+        },
+        None => lo,
+    };
+
+    if lo == 0 || hi < lo {
+        return None;
+    }
+    Some(LineRange { lo: lo, hi: hi })
+}
+
+// Decoded from a list of `[FILE:]LO-HI` strings, an empty list means all lines.
+impl Decodable for FileLines {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
+        let specs: Vec<String> = try!(Decodable::decode(d));
+        let mut result = FileLines::all();
+        for spec in specs.iter() {
+            if let Err(msg) = result.add_spec(spec) {
+                return Err(d.error(&msg));
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[test]
+fn line_ranges() {
+    assert_eq!(Some(LineRange { lo: 3, hi: 7 }), parse_range("3-7"));
+    assert_eq!(Some(LineRange { lo: 4, hi: 4 }), parse_range("4"));
+    assert_eq!(None, parse_range("7-3"));
+    assert_eq!(None, parse_range("0-3"));
+    assert_eq!(None, parse_range("a-b"));
+
+    let mut lines = FileLines::all();
+    assert!(lines.intersects("src/lib.rs", LineRange { lo: 1, hi: 1 }));
+
+    lines.add_spec("src/lib.rs:10-20").unwrap();
+    assert!(lines.intersects("src/lib.rs", LineRange { lo: 5, hi: 10 }));
+    assert!(lines.intersects("lib.rs", LineRange { lo: 20, hi: 25 }));
+    assert!(!lines.intersects("src/lib.rs", LineRange { lo: 21, hi: 25 }));
+    assert!(!lines.intersects("src/main.rs", LineRange { lo: 10, hi: 20 }));
+
+    lines.add_spec("30-31").unwrap();
+    assert!(lines.intersects("src/main.rs", LineRange { lo: 31, hi: 40 }));
+    assert!(lines.add_spec("foo.rs:bar").is_err());
+}
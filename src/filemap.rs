@@ -12,27 +12,135 @@
 // TODO tests
 
 use strings::string_buffer::StringBuffer;
+use rustc_serialize::json;
+use std::cmp;
 use std::collections::HashMap;
+use std::ffi::OsStr;
 use std::fs::{self, File};
-use std::io::{self, Write, stdout};
+use std::io::{self, Read, Write, stdout};
+use std::path::{Component, Path, PathBuf};
 use WriteMode;
 use NewlineStyle;
+use FormatReport;
 use config::Config;
+use rustfmt_diff::{make_diff, print_diff, write_patch};
+use summary::Summary;
+use syntax::codemap::CodeMap;
+use checkstyle;
 
 // A map of the files of a crate, with their new content
 pub type FileMap = HashMap<String, StringBuffer>;
 
-// Append a newline to the end of each file.
-pub fn append_newlines(file_map: &mut FileMap) {
-    for (_, s) in file_map.iter_mut() {
-        s.push_str("\n");
+// The byte order mark some editors put at the start of UTF-8 files.
+pub const BOM: &'static str = "\u{feff}";
+
+// Ends each non-empty file with exactly one newline, or none if the config
+// says so, however many the source had.
+pub fn normalize_trailing_newlines(file_map: &mut FileMap, config: &Config) {
+    for (_, text) in file_map.iter_mut() {
+        let mut content_end = 0;
+        for (c, b) in text.chars() {
+            if !c.is_whitespace() {
+                content_end = b + c.len_utf8();
+            }
+        }
+        text.truncate(content_end);
+        if content_end > 0 && config.newline_at_eof {
+            text.push_str("\n");
+        }
+    }
+}
+
+// The parser drops any byte order mark, put it back on each file which
+// started with one.
+pub fn restore_boms(file_map: &mut FileMap) {
+    for (filename, text) in file_map.iter_mut() {
+        if starts_with_bom(filename) {
+            restore_bom(text);
+        }
     }
 }
 
+pub fn restore_bom(text: &mut StringBuffer) {
+    let mut with_bom = StringBuffer::new();
+    with_bom.push_str(BOM);
+    with_bom.push_str(&text.to_string());
+    *text = with_bom;
+}
+
+fn starts_with_bom(filename: &str) -> bool {
+    let mut start = Vec::new();
+    match File::open(filename).and_then(|file| file.take(3).read_to_end(&mut start)) {
+        Ok(_) => start == BOM.as_bytes(),
+        Err(_) => false,
+    }
+}
+
+// Writes out every file according to mode. Modes which only check the
+// formatting record any differences they find in summary.
 pub fn write_all_files(file_map: &FileMap,
                        mode: WriteMode,
-                       config: &Config)
+                       config: &Config,
+                       summary: &mut Summary)
                        -> Result<(HashMap<String, String>), io::Error> {
+    match mode {
+        WriteMode::Json => {
+            try!(write_json(file_map, config, summary));
+            return Ok(HashMap::new());
+        }
+        WriteMode::Checkstyle => {
+            try!(write_checkstyle(file_map, config, summary));
+            return Ok(HashMap::new());
+        }
+        WriteMode::List => {
+            for filename in sorted_filenames(file_map) {
+                if try!(read_original(filename)) != system_text(&file_map[filename], config) {
+                    println!("{}", filename);
+                    summary.add_diff();
+                }
+            }
+            return Ok(HashMap::new());
+        }
+        WriteMode::Diff => {
+            for filename in sorted_filenames(file_map) {
+                let original = try!(read_original(filename));
+                let mismatches = make_diff(&original, &system_text(&file_map[filename], config));
+                if !mismatches.is_empty() {
+                    let stdout = stdout();
+                    let color = config.color.use_color();
+                    try!(print_diff(stdout.lock(), filename, &mismatches, color));
+                    summary.add_diff();
+                }
+            }
+            return Ok(HashMap::new());
+        }
+        WriteMode::Patch => {
+            for filename in sorted_filenames(file_map) {
+                let original = try!(read_original(filename));
+                let formatted = system_text(&file_map[filename], config);
+                let stdout = stdout();
+                if try!(write_patch(stdout.lock(), filename, &original, &formatted)) {
+                    summary.add_diff();
+                }
+            }
+            return Ok(HashMap::new());
+        }
+        WriteMode::PatchFiles => {
+            for filename in sorted_filenames(file_map) {
+                let original = try!(read_original(filename));
+                let formatted = system_text(&file_map[filename], config);
+                let mut patch = Vec::new();
+                if try!(write_patch(&mut patch, filename, &original, &formatted)) {
+                    let mut file = try!(File::create(filename.to_owned() + ".patch"));
+                    try!(file.write_all(&patch));
+                    summary.add_diff();
+                }
+            }
+            return Ok(HashMap::new());
+        }
+        _ => {}
+    }
+
     let mut result = HashMap::new();
     for filename in file_map.keys() {
         let one_result = try!(write_file(&file_map[filename], filename, mode, config));
@@ -44,48 +152,273 @@ pub fn write_all_files(file_map: &FileMap,
     Ok(result)
 }
 
+// Writes each file, in order of file name, to the writer open returns for
+// it, e.g., to capture the output in memory rather than on disk.
+pub fn write_all_to<F, W>(file_map: &FileMap,
+                          config: &Config,
+                          mut open: F)
+                          -> Result<(), io::Error>
+    where F: FnMut(&str) -> Result<W, io::Error>,
+          W: Write
+{
+    for filename in sorted_filenames(file_map) {
+        let writer = try!(open(filename));
+        try!(write_system_newlines(writer, &file_map[filename], config));
+    }
+    Ok(())
+}
+
+// Calls f with the name and formatted text of each file, in order of file
+// name.
+pub fn for_each_file<F>(file_map: &FileMap, config: &Config, mut f: F)
+    where F: FnMut(&str, &str)
+{
+    for filename in sorted_filenames(file_map) {
+        f(filename, &system_text(&file_map[filename], config));
+    }
+}
+
+#[derive(RustcEncodable)]
+struct MismatchRecord {
+    file: String,
+    original_start_line: usize,
+    original_lines: Vec<String>,
+    expected_lines: Vec<String>,
+}
+
+// Print a single JSON array describing every region which would be changed
+// by formatting, across all files.
+fn write_json(file_map: &FileMap,
+              config: &Config,
+              summary: &mut Summary)
+              -> Result<(), io::Error> {
+    let mut records = Vec::new();
+    for filename in sorted_filenames(file_map) {
+        let original = try!(read_original(filename));
+        let formatted = system_text(&file_map[filename], config);
+
+        for mismatch in make_diff(&original, &formatted) {
+            records.push(MismatchRecord {
+                file: filename.clone(),
+                original_start_line: mismatch.original_start_line,
+                original_lines: mismatch.original_lines,
+                expected_lines: mismatch.expected_lines,
+            });
+        }
+    }
+
+    if !records.is_empty() {
+        summary.add_diff();
+    }
+    println!("{}", json::as_pretty_json(&records));
+    Ok(())
+}
+
+// How many lines of filename formatting changes, counting the larger of the
+// original and formatted lines for each changed region. Zero if there is no
+// original to compare with, e.g., for stdin.
+pub fn lines_changed(filename: &str, text: &StringBuffer, config: &Config) -> usize {
+    match read_original(filename) {
+        Ok(original) => lines_changed_from(&original, text, config),
+        Err(_) => 0,
+    }
+}
+
+// As lines_changed, but against original rather than a file on disk.
+pub fn lines_changed_from(original: &str, text: &StringBuffer, config: &Config) -> usize {
+    make_diff(original, &system_text(text, config))
+        .iter()
+        .map(|m| cmp::max(m.original_lines.len(), m.expected_lines.len()))
+        .fold(0, |a, b| a + b)
+}
+
+// Whether formatting leaves filename, or original if given, as it was.
+// Unreadable files count as changed.
+pub fn is_unchanged(filename: &str,
+                    text: &StringBuffer,
+                    original: Option<&str>,
+                    config: &Config)
+                    -> bool {
+    let formatted = system_text(text, config);
+    match original {
+        Some(original) => original == formatted,
+        None => read_original(filename).map(|original| original == formatted).unwrap_or(false),
+    }
+}
+
+// Write the code rustfmt gave up on to path, as a JSON array.
+pub fn write_failure_report(report: &FormatReport, path: &str) -> Result<(), io::Error> {
+    let mut file = try!(File::create(path));
+    writeln!(file, "{}", json::as_pretty_json(&report.rewrite_failures()))
+}
+
+fn write_checkstyle(file_map: &FileMap,
+                    config: &Config,
+                    summary: &mut Summary)
+                    -> Result<(), io::Error> {
+    let stdout = stdout();
+    let mut stdout_lock = stdout.lock();
+
+    try!(checkstyle::header(&mut stdout_lock));
+    for filename in sorted_filenames(file_map) {
+        let original = try!(read_original(filename));
+        let mismatches = make_diff(&original, &system_text(&file_map[filename], config));
+        if !mismatches.is_empty() {
+            summary.add_diff();
+        }
+        try!(checkstyle::write_file(&mut stdout_lock, filename, &mismatches));
+    }
+    checkstyle::footer(&mut stdout_lock)
+}
+
+fn sorted_filenames(file_map: &FileMap) -> Vec<&String> {
+    let mut filenames: Vec<_> = file_map.keys().collect();
+    filenames.sort();
+    filenames
+}
+
+// The contents of filename before formatting.
+pub fn read_original(filename: &str) -> Result<String, io::Error> {
+    let mut original = String::new();
+    try!(try!(File::open(filename)).read_to_string(&mut original));
+    Ok(original)
+}
+
+// Find where the backup for filename should go, creating the backup directory
+// if necessary. A backup left by an earlier run is kept, the new one being
+// numbered `.bk.1`, `.bk.2` and so on.
+fn backup_path(filename: &str, config: &Config) -> Result<PathBuf, io::Error> {
+    let path = backup_file_path(filename, &config.backup_dir);
+    if config.backup_dir.len() > 0 {
+        try!(fs::create_dir_all(path.parent().unwrap()));
+    }
+
+    Ok(unused_path(path))
+}
+
+// path, or if something is already there, the first of `path.1`, `path.2`
+// and so on which is free.
+fn unused_path(path: PathBuf) -> PathBuf {
+    if fs::metadata(&path).is_err() {
+        return path;
+    }
+    let mut n = 1;
+    loop {
+        let mut numbered = path.clone().into_os_string();
+        numbered.push(format!(".{}", n));
+        let numbered = PathBuf::from(numbered);
+        if fs::metadata(&numbered).is_err() {
+            return numbered;
+        }
+        n += 1;
+    }
+}
+
+// The backup for filename, next to it or in backup_dir if that is set.
+fn backup_file_path(filename: &str, backup_dir: &str) -> PathBuf {
+    let bk_name = filename.to_owned() + ".bk";
+    if backup_dir.len() == 0 {
+        return PathBuf::from(bk_name);
+    }
+
+    // Mirror the layout of the crate inside the backup directory. The root of
+    // an absolute path would replace the backup directory entirely, so only
+    // the rest of it is kept. `..` is resolved against the components before
+    // it, and dropped where there are none, so that backups never land
+    // outside the backup directory.
+    let mut rest: Vec<&OsStr> = Vec::new();
+    for component in Path::new(&bk_name).components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => {
+                rest.pop();
+            }
+            Component::Normal(name) => rest.push(name),
+        }
+    }
+    let mut path = PathBuf::from(backup_dir);
+    for name in rest {
+        path.push(name);
+    }
+    path
+}
+
+// prints all newlines either as `\n` or as `\r\n`
+fn write_system_newlines<T>(mut writer: T,
+                            text: &StringBuffer,
+                            config: &Config)
+                            -> Result<(), io::Error>
+    where T: Write
+{
+    match config.newline_style {
+        NewlineStyle::Unix => writer.write_all(convert_newlines(text, false).as_bytes()),
+        NewlineStyle::Windows => writer.write_all(convert_newlines(text, true).as_bytes()),
+        // match_source_newlines has already given text the right line endings.
+        NewlineStyle::Auto => write!(writer, "{}", text),
+    }
+}
+
+// text with every newline as `\r\n` if windows, or `\n` if not. Carriage
+// returns copied from the source are dropped, so line endings are never mixed.
+fn convert_newlines(text: &StringBuffer, windows: bool) -> String {
+    let mut result = String::with_capacity(text.len);
+    for (c, _) in text.chars() {
+        match c {
+            '\n' if windows => result.push_str("\r\n"),
+            '\r' => {}
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+// For NewlineStyle::Auto, gives each file the line endings of its source:
+// `\r\n` if the source uses any, otherwise `\n`.
+pub fn match_source_newlines(file_map: &mut FileMap, codemap: &CodeMap) {
+    for (filename, text) in file_map.iter_mut() {
+        let filemap = codemap.get_filemap(filename);
+        let windows = filemap.src.as_ref().map_or(false, |src| src.contains("\r\n"));
+        let converted = convert_newlines(text, windows);
+        *text = StringBuffer::new();
+        text.push_str(&converted);
+    }
+}
+
+// The formatted text of a file, exactly as it would be written to disk.
+fn system_text(text: &StringBuffer, config: &Config) -> String {
+    // io::Write is not implemented for String, working around with Vec<u8>
+    let mut v = Vec::new();
+    // Writing to a Vec can't fail.
+    write_system_newlines(&mut v, text, config).unwrap();
+    // won't panic, we are writing correct utf8
+    String::from_utf8(v).unwrap()
+}
+
 fn write_file(text: &StringBuffer,
               filename: &str,
               mode: WriteMode,
               config: &Config)
               -> Result<Option<String>, io::Error> {
-
-    // prints all newlines either as `\n` or as `\r\n`
-    fn write_system_newlines<T>(mut writer: T,
-                                text: &StringBuffer,
-                                config: &Config)
-                                -> Result<(), io::Error>
-        where T: Write
-    {
-        match config.newline_style {
-                NewlineStyle::Unix => write!(writer, "{}", text),
-                NewlineStyle::Windows => {
-                    for (c, _) in text.chars() {
-                        match c {
-                            '\n' => try!(write!(writer, "\r\n")),
-                            '\r' => continue,
-                            c => try!(write!(writer, "{}", c)),
-                        }
-                    }
-                    Ok(())
-                },
-            }
-    }
-
     match mode {
             WriteMode::Overwrite => {
                 // Do a little dance to make writing safer - write to a temp file
-                // rename the original to a .bk, then rename the temp file to the
-                // original.
+                // rename the original to a .bk (if requested), then rename the
+                // temp file to the original.
                 let tmp_name = filename.to_owned() + ".tmp";
-                let bk_name = filename.to_owned() + ".bk";
+                let bk_name = if config.write_backup {
+                    Some(try!(backup_path(filename, config)))
+                } else {
+                    None
+                };
                 {
                     // Write text to temp file
                     let tmp_file = try!(File::create(&tmp_name));
                     try!(write_system_newlines(tmp_file, text, config));
                 }
 
-                try!(fs::rename(filename, bk_name));
+                if let Some(bk_name) = bk_name {
+                    try!(fs::rename(filename, bk_name));
+                }
                 try!(fs::rename(tmp_name, filename));
             }
             WriteMode::NewFile(extn) => {
@@ -99,14 +432,112 @@ fn write_file(text: &StringBuffer,
                 let stdout_lock = stdout.lock();
                 try!(write_system_newlines(stdout_lock, text, config));
             }
+            WriteMode::Plain => {
+                let stdout = stdout();
+                let stdout_lock = stdout.lock();
+                try!(write_system_newlines(stdout_lock, text, config));
+            }
             WriteMode::Return(_) => {
-                // io::Write is not implemented for String, working around with Vec<u8>
-                let mut v = Vec::new();
-                try!(write_system_newlines(&mut v, text, config));
-                // won't panic, we are writing correct utf8
-                return Ok(Some(String::from_utf8(v).unwrap()));
+                return Ok(Some(system_text(text, config)));
             }
+            WriteMode::Json |
+            WriteMode::Checkstyle |
+            WriteMode::List |
+            WriteMode::Diff |
+            WriteMode::Patch |
+            WriteMode::PatchFiles => unreachable!(),
         }
 
     Ok(None)
 }
+
+#[test]
+fn for_each_file_test() {
+    let mut config = Config::from_toml(include_str!("default.toml"));
+    config.newline_style = NewlineStyle::Windows;
+    let mut file_map = FileMap::new();
+    let mut text = StringBuffer::new();
+    text.push_str("fn main() {}\n");
+    file_map.insert("a.rs".to_owned(), text);
+
+    let mut seen = Vec::new();
+    for_each_file(&file_map, &config, |filename, text| {
+        seen.push((filename.to_owned(), text.to_owned()));
+    });
+    assert_eq!(seen, vec![("a.rs".to_owned(), "fn main() {}\r\n".to_owned())]);
+}
+
+#[test]
+fn normalize_trailing_newlines_test() {
+    let mut config = Config::from_toml(include_str!("default.toml"));
+    let mut file_map = FileMap::new();
+    let files = [("a.rs", "fn a() {}"), ("b.rs", "fn b() {}\n\n  \n"), ("c.rs", "")];
+    for &(filename, formatted) in &files {
+        let mut text = StringBuffer::new();
+        text.push_str(formatted);
+        file_map.insert(filename.to_owned(), text);
+    }
+
+    normalize_trailing_newlines(&mut file_map, &config);
+    assert_eq!(file_map["a.rs"].to_string(), "fn a() {}\n");
+    assert_eq!(file_map["b.rs"].to_string(), "fn b() {}\n");
+    assert_eq!(file_map["c.rs"].to_string(), "");
+
+    config.newline_at_eof = false;
+    normalize_trailing_newlines(&mut file_map, &config);
+    assert_eq!(file_map["a.rs"].to_string(), "fn a() {}");
+}
+
+#[test]
+fn newline_style_test() {
+    let mut config = Config::from_toml(include_str!("default.toml"));
+    let mut text = StringBuffer::new();
+    text.push_str("// A comment\r\nfn main() {\n}\n");
+
+    config.newline_style = NewlineStyle::Unix;
+    assert_eq!(system_text(&text, &config), "// A comment\nfn main() {\n}\n");
+    config.newline_style = NewlineStyle::Windows;
+    assert_eq!(system_text(&text, &config), "// A comment\r\nfn main() {\r\n}\r\n");
+}
+
+#[test]
+fn match_source_newlines_test() {
+    let codemap = CodeMap::new();
+    codemap.new_filemap("a.rs".to_owned(), "fn a() {\r\n}\r\n".to_owned());
+    codemap.new_filemap("b.rs".to_owned(), "fn b() {\n}\n".to_owned());
+    let mut file_map = FileMap::new();
+    for &(filename, formatted) in &[("a.rs", "fn a() {\r\n}\n"), ("b.rs", "fn b() {\n}\n")] {
+        let mut text = StringBuffer::new();
+        text.push_str(formatted);
+        file_map.insert(filename.to_owned(), text);
+    }
+
+    match_source_newlines(&mut file_map, &codemap);
+    assert_eq!(file_map["a.rs"].to_string(), "fn a() {\r\n}\r\n");
+    assert_eq!(file_map["b.rs"].to_string(), "fn b() {\n}\n");
+}
+
+#[test]
+fn backup_file_path_test() {
+    assert_eq!(backup_file_path("src/a.rs", ""), PathBuf::from("src/a.rs.bk"));
+    assert_eq!(backup_file_path("./src/a.rs", "bk"), PathBuf::from("bk/src/a.rs.bk"));
+    assert_eq!(backup_file_path("/x/a/mod.rs", "bk"), PathBuf::from("bk/x/a/mod.rs.bk"));
+    assert_eq!(backup_file_path("/x/b/mod.rs", "bk"), PathBuf::from("bk/x/b/mod.rs.bk"));
+    assert_eq!(backup_file_path("../x.rs", "bk"), PathBuf::from("bk/x.rs.bk"));
+    assert_eq!(backup_file_path("a/../../b/x.rs", "bk"), PathBuf::from("bk/b/x.rs.bk"));
+}
+
+#[test]
+fn unused_path_test() {
+    let dir = ::std::env::temp_dir().join("rustfmt_unused_path_test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("a.rs.bk");
+    assert_eq!(unused_path(path.clone()), path);
+
+    File::create(&path).unwrap();
+    assert_eq!(unused_path(path.clone()), dir.join("a.rs.bk.1"));
+    File::create(dir.join("a.rs.bk.1")).unwrap();
+    assert_eq!(unused_path(path.clone()), dir.join("a.rs.bk.2"));
+    fs::remove_dir_all(&dir).unwrap();
+}
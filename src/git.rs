@@ -0,0 +1,269 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Asking git which files have changed, and reading and updating the index.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use file_lines::LineRange;
+
+// Runs git with args in the current directory, returning its stdout.
+fn git(args: &[&str]) -> Result<String, String> {
+    let output = try!(Command::new("git")
+                          .args(args)
+                          .output()
+                          .map_err(|e| format!("could not run git: {}", e)));
+    if !output.status.success() {
+        return Err(format!("git {} failed: {}",
+                           args.join(" "),
+                           String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    String::from_utf8(output.stdout).map_err(|_| "git output was not UTF-8".to_owned())
+}
+
+// As git, writing input to its stdin.
+fn git_with_input(args: &[&str], input: &str) -> Result<String, String> {
+    let mut child = try!(Command::new("git")
+                             .args(args)
+                             .stdin(Stdio::piped())
+                             .stdout(Stdio::piped())
+                             .stderr(Stdio::piped())
+                             .spawn()
+                             .map_err(|e| format!("could not run git: {}", e)));
+    try!(child.stdin
+              .take()
+              .unwrap()
+              .write_all(input.as_bytes())
+              .map_err(|e| format!("could not write to git: {}", e)));
+    let output = try!(child.wait_with_output().map_err(|e| format!("could not run git: {}", e)));
+    if !output.status.success() {
+        return Err(format!("git {} failed: {}",
+                           args.join(" "),
+                           String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    String::from_utf8(output.stdout).map_err(|_| "git output was not UTF-8".to_owned())
+}
+
+/// Lists the tracked `.rs` files under the current directory which differ
+/// from `revision`, including uncommitted changes. With no revision, changes
+/// are relative to HEAD. Otherwise they are relative to the merge base of
+/// revision and HEAD, so that only changes made on the current branch are
+/// included. Deleted files are omitted. The paths are relative to the current
+/// directory, like those found by walking it, so that the `ignore` patterns
+/// apply to them in the same way.
+pub fn changed_rust_files(revision: Option<&str>) -> Result<Vec<PathBuf>, String> {
+    changed_rust_files_in(".", revision)
+}
+
+// As changed_rust_files, for the directory dir, the paths being relative to
+// it.
+fn changed_rust_files_in(dir: &str, revision: Option<&str>) -> Result<Vec<PathBuf>, String> {
+    let base = match revision {
+        Some(revision) => {
+            try!(git(&["-C", dir, "merge-base", revision, "HEAD"])).trim().to_owned()
+        }
+        None => "HEAD".to_owned(),
+    };
+    let names = try!(git(&["-C", dir, "diff", "--name-only", "--relative", "--diff-filter=ACMR",
+                           &base, "--", "*.rs"]));
+
+    Ok(names.lines()
+            .filter(|name| name.len() > 0)
+            .map(PathBuf::from)
+            .collect())
+}
+
+// A file with changes staged for commit.
+pub struct StagedFile {
+    // The file's name relative to the top of the repository, as git knows it.
+    pub name: String,
+    pub path: PathBuf,
+    // The lines of the staged version which differ from HEAD.
+    pub lines: Vec<LineRange>,
+}
+
+/// Lists the `.rs` files with staged changes, and the lines of their staged
+/// versions which are new. Deleted files are omitted, as are files whose
+/// staged changes only remove lines, since there is nothing new to format.
+pub fn staged_rust_files() -> Result<Vec<StagedFile>, String> {
+    let top_level = try!(git(&["rev-parse", "--show-toplevel"]));
+    let top_level = top_level.trim();
+    // The pathspec is resolved against the directory git runs in, so run it
+    // at the top to see the whole repository.
+    let diff = try!(git(&["-C", top_level, "diff", "--cached", "--unified=0", "--no-color",
+                          "--diff-filter=ACMR", "--", "*.rs"]));
+    let top_level = PathBuf::from(top_level);
+
+    Ok(changed_lines(&diff)
+           .into_iter()
+           .filter(|&(_, ref lines)| !lines.is_empty())
+           .map(|(name, lines)| {
+               StagedFile {
+                   path: top_level.join(&name),
+                   name: name,
+                   lines: lines,
+               }
+           })
+           .collect())
+}
+
+/// The staged version of the file called name in the index.
+pub fn staged_text(name: &str) -> Result<String, String> {
+    git(&["show", &format!(":{}", name)])
+}
+
+/// Replaces the staged version of the file called name with text, leaving the
+/// working tree alone.
+pub fn update_staged(name: &str, text: &str) -> Result<(), String> {
+    // name is relative to the top of the repository, so that is where git has
+    // to look for it.
+    let top_level = try!(git(&["rev-parse", "--show-toplevel"]));
+    let top_level = top_level.trim();
+    // Each entry is `MODE OBJECT STAGE\tNAME`.
+    let entry = try!(git(&["-C", top_level, "ls-files", "--stage", "--", name]));
+    let mode = try!(entry.split_whitespace()
+                         .next()
+                         .ok_or(format!("{} is not in the index", name)));
+    let object = try!(git_with_input(&["hash-object", "-w", "--stdin"], text));
+    let info = format!("{},{},{}", mode, object.trim(), name);
+    try!(git(&["-C", top_level, "update-index", "--cacheinfo", &info]));
+    Ok(())
+}
+
+/// Parses a unified diff, returning the name of each file it changes with the
+/// ranges of lines added to the new version of that file. Names are as given
+/// in the diff, without any `b/` prefix. Hunks which only remove lines are
+/// ignored.
+pub fn changed_lines(diff: &str) -> Vec<(String, Vec<LineRange>)> {
+    let mut result: Vec<(String, Vec<LineRange>)> = Vec::new();
+    // Whether the hunks being read belong to a deleted file.
+    let mut deleted = false;
+    for line in diff.lines() {
+        if line.starts_with("+++ ") {
+            let name = line[4..].split('\t').next().unwrap().trim();
+            deleted = name == "/dev/null";
+            if deleted {
+                continue;
+            }
+            let name = if name.starts_with("b/") {
+                &name[2..]
+            } else {
+                name
+            };
+            result.push((name.to_owned(), Vec::new()));
+        } else if line.starts_with("@@ ") && !deleted {
+            let range = match line.split_whitespace().nth(2).and_then(parse_hunk_range) {
+                Some(range) => range,
+                None => continue,
+            };
+            if let Some(&mut (_, ref mut ranges)) = result.last_mut() {
+                ranges.push(range);
+            }
+        }
+    }
+    result
+}
+
+// Parses the `+START[,COUNT]` part of a hunk header, None if no lines were
+// added.
+fn parse_hunk_range(s: &str) -> Option<LineRange> {
+    if !s.starts_with('+') {
+        return None;
+    }
+    let mut parts = s[1..].splitn(2, ',');
+    let start = match parts.next().and_then(|start| start.parse::<usize>().ok()) {
+        Some(start) => start,
+        None => return None,
+    };
+    let count = match parts.next() {
+        Some(count) => {
+            match count.parse::<usize>() {
+                Ok(count) => count,
+                Err(_) => return None,
+            }
+        }
+        None => 1,
+    };
+
+    if count == 0 {
+        return None;
+    }
+    Some(LineRange {
+        lo: start,
+        hi: start + count - 1,
+    })
+}
+
+#[test]
+fn changed_rust_files_test() {
+    use std::fs::{self, File};
+    use config::Config;
+    use walk::is_ignored;
+
+    let dir = ::std::env::temp_dir().join("rustfmt_changed_rust_files_test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("src/gen")).unwrap();
+    let dir_str = dir.to_str().unwrap();
+    let run = |args: &[&str]| {
+        let mut full_args = vec!["-C", dir_str, "-c", "user.name=a", "-c", "user.email=a@b"];
+        full_args.extend(args.iter().cloned());
+        git(&full_args).unwrap();
+    };
+    run(&["init", "-q"]);
+    for name in &["src/a.rs", "src/gen/b.rs", "src/c.rs"] {
+        File::create(dir.join(name)).unwrap();
+    }
+    run(&["add", "."]);
+    run(&["commit", "-q", "-m", "init"]);
+    for name in &["src/a.rs", "src/gen/b.rs"] {
+        File::create(dir.join(name)).unwrap().write_all(b"fn main() {}\n").unwrap();
+    }
+
+    let mut files = changed_rust_files_in(dir_str, None).unwrap();
+    files.sort();
+    assert_eq!(files, vec![PathBuf::from("src/a.rs"), PathBuf::from("src/gen/b.rs")]);
+
+    // The paths are relative, so ignore patterns match them.
+    let mut config = Config::from_toml(include_str!("default.toml"));
+    config.ignore = vec!["src/gen".to_owned()];
+    let kept: Vec<_> = files.iter().filter(|file| !is_ignored(file, &config)).collect();
+    assert_eq!(kept, vec![&PathBuf::from("src/a.rs")]);
+
+    let sub_dir = dir.join("src/gen");
+    assert_eq!(changed_rust_files_in(sub_dir.to_str().unwrap(), None).unwrap(),
+               vec![PathBuf::from("b.rs")]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn changed_lines_test() {
+    let diff = "diff --git a/src/foo.rs b/src/foo.rs
+index 1234567..89abcde 100644
+--- a/src/foo.rs
++++ b/src/foo.rs
+@@ -1,2 +1,3 @@ fn foo() {
+@@ -10 +11 @@
+@@ -20,4 +21,0 @@
+diff --git a/bar.rs b/bar.rs
+--- a/bar.rs
++++ /dev/null
+@@ -1,3 +0,0 @@
+diff --git a/baz.rs b/baz.rs
+--- /dev/null
++++ b/baz.rs
+@@ -0,0 +1,5 @@
+";
+    let range = |lo, hi| LineRange { lo: lo, hi: hi };
+    assert_eq!(changed_lines(diff),
+               vec![("src/foo.rs".to_owned(), vec![range(1, 3), range(11, 11)]),
+                    ("baz.rs".to_owned(), vec![range(1, 5)])]);
+}
@@ -0,0 +1,184 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// A reader for the common subset of .gitignore files: comments, negation with
+// `!`, directory only patterns with a trailing `/`, patterns anchored by a
+// `/`, and the `*`, `**` and `?` wildcards.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+struct Pattern {
+    glob: String,
+    negated: bool,
+    dir_only: bool,
+    // Matches against the path relative to the .gitignore, rather than
+    // against the file name at any depth.
+    anchored: bool,
+}
+
+pub struct GitIgnore {
+    dir: PathBuf,
+    patterns: Vec<Pattern>,
+}
+
+// Whether a path is matched by a .gitignore. Later patterns take precedence,
+// so a negated pattern can re-include a path.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Match {
+    None,
+    Ignore,
+    Include,
+}
+
+impl GitIgnore {
+    // Reads dir/.gitignore, if it exists.
+    pub fn from_dir(dir: &Path) -> Option<GitIgnore> {
+        let mut text = String::new();
+        match File::open(dir.join(".gitignore")) {
+            Ok(mut file) => {
+                if file.read_to_string(&mut text).is_err() {
+                    return None;
+                }
+            }
+            Err(_) => return None,
+        }
+
+        Some(GitIgnore::parse(dir, &text))
+    }
+
+    pub fn parse(dir: &Path, text: &str) -> GitIgnore {
+        let patterns = text.lines().filter_map(|line| {
+            let mut line = line.trim_right();
+            if line.len() == 0 || line.starts_with("#") {
+                return None;
+            }
+
+            let negated = line.starts_with("!");
+            if negated {
+                line = &line[1..];
+            }
+            let dir_only = line.ends_with("/");
+            if dir_only {
+                line = &line[..line.len() - 1];
+            }
+            let anchored = line.contains('/');
+            if line.starts_with("/") {
+                line = &line[1..];
+            }
+
+            Some(Pattern {
+                glob: line.to_owned(),
+                negated: negated,
+                dir_only: dir_only,
+                anchored: anchored,
+            })
+        }).collect();
+
+        GitIgnore { dir: dir.to_owned(), patterns: patterns }
+    }
+
+    pub fn matches(&self, path: &Path, is_dir: bool) -> Match {
+        let mut components = path.components();
+        for dir_component in self.dir.components() {
+            if components.next() != Some(dir_component) {
+                return Match::None;
+            }
+        }
+        let relative = match components.as_path().to_str() {
+            Some(relative) => relative.replace("\\", "/"),
+            None => return Match::None,
+        };
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => return Match::None,
+        };
+
+        for pattern in self.patterns.iter().rev() {
+            if pattern.dir_only && !is_dir {
+                continue;
+            }
+            let text = if pattern.anchored {
+                &relative[..]
+            } else {
+                name
+            };
+            if glob_match(pattern.glob.as_bytes(), text.as_bytes()) {
+                return if pattern.negated {
+                    Match::Include
+                } else {
+                    Match::Ignore
+                };
+            }
+        }
+
+        Match::None
+    }
+}
+
+// `*` and `?` don't match `/`, `**` matches anything.
+fn glob_match(glob: &[u8], text: &[u8]) -> bool {
+    if glob.len() == 0 {
+        return text.len() == 0;
+    }
+
+    match glob[0] {
+        b'*' if glob.len() > 1 && glob[1] == b'*' => {
+            let mut rest = &glob[2..];
+            // `a/**/b` also matches `a/b`.
+            if rest.len() > 0 && rest[0] == b'/' {
+                rest = &rest[1..];
+            }
+            (0..text.len() + 1).any(|i| glob_match(rest, &text[i..]))
+        }
+        b'*' => {
+            let rest = &glob[1..];
+            for i in 0..text.len() + 1 {
+                if glob_match(rest, &text[i..]) {
+                    return true;
+                }
+                if i < text.len() && text[i] == b'/' {
+                    return false;
+                }
+            }
+            false
+        }
+        b'?' => text.len() > 0 && text[0] != b'/' && glob_match(&glob[1..], &text[1..]),
+        c => text.len() > 0 && text[0] == c && glob_match(&glob[1..], &text[1..]),
+    }
+}
+
+#[test]
+fn globs() {
+    assert!(glob_match(b"*.rs", b"foo.rs"));
+    assert!(!glob_match(b"*.rs", b"foo.rs.bk"));
+    assert!(!glob_match(b"*.rs", b"src/foo.rs"));
+    assert!(glob_match(b"src/*.rs", b"src/foo.rs"));
+    assert!(glob_match(b"**/gen", b"a/b/gen"));
+    assert!(glob_match(b"a/**/b", b"a/b"));
+    assert!(glob_match(b"a/**/b", b"a/x/y/b"));
+    assert!(glob_match(b"fo?", b"foo"));
+    assert!(!glob_match(b"fo?", b"fo/"));
+}
+
+#[test]
+fn gitignore_matches() {
+    let ignore = GitIgnore::parse(Path::new("root"),
+                                  "# comment\n*.bk\ngen/\n/build.rs\n!keep.bk\n");
+
+    assert_eq!(Match::Ignore, ignore.matches(Path::new("root/src/foo.bk"), false));
+    assert_eq!(Match::Include, ignore.matches(Path::new("root/keep.bk"), false));
+    assert_eq!(Match::Ignore, ignore.matches(Path::new("root/src/gen"), true));
+    assert_eq!(Match::None, ignore.matches(Path::new("root/src/gen"), false));
+    assert_eq!(Match::Ignore, ignore.matches(Path::new("root/build.rs"), false));
+    assert_eq!(Match::None, ignore.matches(Path::new("root/src/build.rs"), false));
+    assert_eq!(Match::None, ignore.matches(Path::new("other/foo.bk"), false));
+}
@@ -8,9 +8,10 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use lists::{write_list, itemize_list, ListItem, ListFormatting, SeparatorTactic, ListTactic};
+use lists::{write_list, itemize_list, ListItem, ListFormatting, SeparatorPlace, ListTactic};
 use utils::span_after;
-use rewrite::{Rewrite, RewriteContext};
+use rewrite::{Rewrite, RewriteContext, RewriteResult, RewriteError};
+use budget::{reserve, saturating_reserve, saturating_reserve_width};
 
 use syntax::ast;
 use syntax::codemap::Span;
@@ -19,26 +20,26 @@ use syntax::codemap::Span;
 
 impl Rewrite for ast::ViewPath {
     // Returns an empty string when the ViewPath is empty (like foo::bar::{})
-    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> Option<String> {
+    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> RewriteResult {
         match self.node {
             ast::ViewPath_::ViewPathList(ref path, ref path_list) => {
-                Some(rewrite_use_list(width,
-                                      offset,
-                                      path,
-                                      path_list,
-                                      self.span,
-                                      context).unwrap_or("".to_owned()))
+                Ok(rewrite_use_list(width,
+                                    offset,
+                                    path,
+                                    path_list,
+                                    self.span,
+                                    context).unwrap_or("".to_owned()))
             }
             ast::ViewPath_::ViewPathGlob(_) => {
                 // FIXME convert to list?
-                None
+                Err(RewriteError::Unsupported)
             }
             ast::ViewPath_::ViewPathSimple(ident, ref path) => {
                 let ident_str = ident.to_string();
-                // 4 = " as ".len()
-                let path_str = try_opt!(path.rewrite(context, width - ident_str.len() - 4, offset));
+                let path_width = try!(reserve(width, &ident_str).and_then(|w| reserve(w, " as ")));
+                let path_str = try!(path.rewrite(context, path_width, offset));
 
-                Some(if path.segments.last().unwrap().identifier == ident {
+                Ok(if path.segments.last().unwrap().identifier == ident {
                          path_str
                      } else {
                          format!("{} as {}", path_str, ident_str)
@@ -75,30 +76,28 @@ pub fn rewrite_use_list(width: usize,
                         span: Span,
                         context: &RewriteContext)
                         -> Option<String> {
-    // 1 = {}
-    let path_str = try_opt!(path.rewrite(context, width - 1, offset));
+    let path_width = try_opt!(reserve(width, "{").ok());
+    let path_str = try_opt!(path.rewrite(context, path_width, offset).ok());
 
     match path_list.len() {
         0 => return None,
         1 => return Some(rewrite_single_use_list(path_str, path_list[0])),
-        _ => ()
+        _ => (),
     }
 
-    // 2 = ::
     let path_separation_w = if path_str.len() > 0 {
-        2
+        "::".len()
     } else {
         0
     };
-    // 1 = {
-    let supp_indent = path_str.len() + path_separation_w + 1;
-    // 1 = }
-    let remaining_width = width.checked_sub(supp_indent + 1).unwrap_or(0);
+    let supp_indent = path_str.len() + path_separation_w + "{".len();
+    let remaining_width = saturating_reserve(saturating_reserve_width(width, supp_indent), "}");
 
     let fmt = ListFormatting {
         tactic: ListTactic::Mixed,
         separator: ",",
-        trailing_separator: SeparatorTactic::Never,
+        trailing_separator: context.config.trailing_comma,
+        separator_place: SeparatorPlace::Back,
         indent: offset + supp_indent,
         h_width: remaining_width,
         // FIXME This is too conservative, and will not use all width
@@ -106,8 +105,10 @@ pub fn rewrite_use_list(width: usize,
         // (loose 1 column (";"))
         v_width: remaining_width,
         ends_with_newline: false,
+        config: context.config,
     };
 
+    let list_lo = try_opt!(span_after(span, "{", context.codemap).ok());
     let mut items = itemize_list(context.codemap,
                                  vec![ListItem::from_str("")], /* Dummy value, explanation
                                                                 * below */
@@ -124,7 +125,7 @@ pub fn rewrite_use_list(width: usize,
                                          "self".to_owned()
                                      }
                                  },
-                                 span_after(span, "{", context.codemap),
+                                 list_lo,
                                  span.hi);
 
     // We prefixed the item list with a dummy value so that we can
@@ -158,7 +159,7 @@ fn move_self_to_front(items: &mut Vec<ListItem>) -> bool {
         Some(pos) => {
             items[0] = items.remove(pos);
             true
-        },
-        None => false
+        }
+        None => false,
     }
 }
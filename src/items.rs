@@ -11,12 +11,13 @@
 // Formatting top-level items - functions, structs, enums, traits, impls.
 
 use {ReturnIndent, BraceStyle};
+use budget::{saturating_reserve, saturating_reserve_width};
 use utils::{format_mutability, format_visibility, make_indent, contains_skip, span_after,
             end_typaram};
-use lists::{write_list, itemize_list, ListItem, ListFormatting, SeparatorTactic, ListTactic};
-use comment::FindUncommented;
+use lists::{write_list, itemize_list, definitive_tactic, count_blank_lines, ListItem,
+            ListFormatting, SeparatorPlace, ListTactic};
 use visitor::FmtVisitor;
-use rewrite::Rewrite;
+use rewrite::{Rewrite, RewriteError, RewriteResult};
 use config::Config;
 
 use syntax::{ast, abi};
@@ -24,7 +25,86 @@ use syntax::codemap::{self, Span, BytePos};
 use syntax::print::pprust;
 use syntax::parse::token;
 
+use std::cmp;
+
+// The kinds of item with their own options for the blank lines around them.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ItemKind {
+    Fn,
+    Impl,
+    Mod,
+    Other,
+}
+
+impl ItemKind {
+    pub fn of_item(item: &ast::Item) -> ItemKind {
+        match item.node {
+            ast::Item_::ItemFn(..) => ItemKind::Fn,
+            ast::Item_::ItemImpl(..) |
+            ast::Item_::ItemDefaultImpl(..) => ItemKind::Impl,
+            ast::Item_::ItemMod(..) => ItemKind::Mod,
+            _ => ItemKind::Other,
+        }
+    }
+
+    // None if the blank lines around this kind of item are left as written.
+    fn blank_lines(self, config: &Config) -> Option<usize> {
+        let blank_lines = match self {
+            ItemKind::Fn => config.fn_blank_lines,
+            ItemKind::Impl => config.impl_blank_lines,
+            ItemKind::Mod => config.mod_blank_lines,
+            ItemKind::Other => 0,
+        };
+        if blank_lines == 0 {
+            None
+        } else {
+            Some(blank_lines)
+        }
+    }
+}
+
+// The blank lines wanted in front of an item, worked out when we reach the
+// item and put in by the missed span which gets to its start.
+#[derive(Copy, Clone, Debug)]
+pub struct ItemSpacing {
+    // The start of the item's attributes, or of the item if it has none.
+    pub lo: BytePos,
+    // None leaves the blank lines as written.
+    pub blank_lines: Option<usize>,
+    // Whether to remove the blank lines between an opening brace and the
+    // item.
+    pub trim_after_brace: bool,
+}
+
 impl<'a> FmtVisitor<'a> {
+    // Asks for the blank lines around an item of kind starting at lo to be
+    // normalized against the last item at this level. Returns what the next
+    // item needs to know about this one, to be put in last_item once this one
+    // has been formatted.
+    pub fn space_item(&mut self, lo: BytePos, span: Span, kind: ItemKind) -> (ItemKind, bool) {
+        let one_line = self.codemap.lookup_char_pos(span.lo).line ==
+                       self.codemap.lookup_char_pos(span.hi).line;
+        let blank_lines = match self.last_item.take() {
+            Some((prev_kind, prev_one_line)) => {
+                // Runs of one line items of the same kind, e.g., `mod foo;`,
+                // are left grouped however they were written.
+                if prev_kind == kind && prev_one_line && one_line {
+                    None
+                } else {
+                    cmp::max(prev_kind.blank_lines(self.config), kind.blank_lines(self.config))
+                }
+            }
+            None => None,
+        };
+        self.item_spacing.push(ItemSpacing {
+            lo: lo,
+            blank_lines: blank_lines,
+            trim_after_brace: kind.blank_lines(self.config).is_some(),
+        });
+
+        (kind, one_line)
+    }
+
     pub fn rewrite_fn(&mut self,
                       indent: usize,
                       ident: ast::Ident,
@@ -36,20 +116,20 @@ impl<'a> FmtVisitor<'a> {
                       abi: &abi::Abi,
                       vis: ast::Visibility,
                       span: Span)
-                      -> String {
+                      -> RewriteResult {
         let newline_brace = self.newline_for_brace(&generics.where_clause);
 
-        let mut result = self.rewrite_fn_base(indent,
-                                              ident,
-                                              fd,
-                                              explicit_self,
-                                              generics,
-                                              unsafety,
-                                              constness,
-                                              abi,
-                                              vis,
-                                              span,
-                                              newline_brace);
+        let mut result = try!(self.rewrite_fn_base(indent,
+                                                   ident,
+                                                   fd,
+                                                   explicit_self,
+                                                   generics,
+                                                   unsafety,
+                                                   constness,
+                                                   abi,
+                                                   vis,
+                                                   span,
+                                                   newline_brace));
 
         // Prepare for the function body by possibly adding a newline and indent.
         // FIXME we'll miss anything between the end of the signature and the start
@@ -61,7 +141,7 @@ impl<'a> FmtVisitor<'a> {
             result.push(' ');
         }
 
-        result
+        Ok(result)
     }
 
     pub fn rewrite_required_fn(&mut self,
@@ -69,26 +149,26 @@ impl<'a> FmtVisitor<'a> {
                                ident: ast::Ident,
                                sig: &ast::MethodSig,
                                span: Span)
-                               -> String {
+                               -> RewriteResult {
         // Drop semicolon or it will be interpreted as comment
         let span = codemap::mk_sp(span.lo, span.hi - BytePos(1));
 
-        let mut result = self.rewrite_fn_base(indent,
-                                              ident,
-                                              &sig.decl,
-                                              Some(&sig.explicit_self),
-                                              &sig.generics,
-                                              &sig.unsafety,
-                                              &sig.constness,
-                                              &sig.abi,
-                                              ast::Visibility::Inherited,
-                                              span,
-                                              false);
+        let mut result = try!(self.rewrite_fn_base(indent,
+                                                   ident,
+                                                   &sig.decl,
+                                                   Some(&sig.explicit_self),
+                                                   &sig.generics,
+                                                   &sig.unsafety,
+                                                   &sig.constness,
+                                                   &sig.abi,
+                                                   ast::Visibility::Inherited,
+                                                   span,
+                                                   false));
 
         // Re-attach semicolon
         result.push(';');
 
-        result
+        Ok(result)
     }
 
     fn rewrite_fn_base(&mut self,
@@ -103,7 +183,7 @@ impl<'a> FmtVisitor<'a> {
                        vis: ast::Visibility,
                        span: Span,
                        newline_brace: bool)
-                       -> String {
+                       -> RewriteResult {
         // FIXME we'll lose any comments in between parts of the function decl, but anyone
         // who comments there probably deserves what they get.
 
@@ -131,10 +211,8 @@ impl<'a> FmtVisitor<'a> {
 
         // Generics.
         let generics_indent = indent + result.len();
-        result.push_str(&self.rewrite_generics(generics,
-                                               generics_indent,
-                                               codemap::mk_sp(span.lo,
-                                                              span_for_return(&fd.output).lo)));
+        let generics_span = codemap::mk_sp(span.lo, span_for_return(&fd.output).lo);
+        result.push_str(&try!(self.rewrite_generics(generics, generics_indent, generics_span)));
 
         let ret_str = self.rewrite_return(&fd.output);
 
@@ -150,7 +228,7 @@ impl<'a> FmtVisitor<'a> {
             if self.config.fn_args_paren_newline {
                 result.push('\n');
                 result.push_str(&make_indent(arg_indent));
-                arg_indent = arg_indent + 1; // extra space for `(`
+                arg_indent = arg_indent + "(".len();
                 result.push('(');
             } else {
                 result.push_str("(\n");
@@ -165,16 +243,14 @@ impl<'a> FmtVisitor<'a> {
                                  .last()
                                  .map(|tp| end_typaram(tp))
                                  .unwrap_or(span.lo);
-        let args_span = codemap::mk_sp(span_after(codemap::mk_sp(args_start, span.hi),
-                                                  "(",
-                                                  self.codemap),
-                                       span_for_return(&fd.output).lo);
-        result.push_str(&self.rewrite_args(&fd.inputs,
-                                           explicit_self,
-                                           one_line_budget,
-                                           multi_line_budget,
-                                           arg_indent,
-                                           args_span));
+        let args_lo = try!(span_after(codemap::mk_sp(args_start, span.hi), "(", self.codemap));
+        let args_span = codemap::mk_sp(args_lo, span_for_return(&fd.output).lo);
+        result.push_str(&try!(self.rewrite_args(&fd.inputs,
+                                                explicit_self,
+                                                one_line_budget,
+                                                multi_line_budget,
+                                                arg_indent,
+                                                args_span)));
         result.push(')');
 
         // Return type.
@@ -221,7 +297,7 @@ impl<'a> FmtVisitor<'a> {
                                                    indent,
                                                    span.hi));
 
-        result
+        Ok(result)
     }
 
     fn rewrite_args(&self,
@@ -231,7 +307,7 @@ impl<'a> FmtVisitor<'a> {
                     multi_line_budget: usize,
                     arg_indent: usize,
                     span: Span)
-                    -> String {
+                    -> RewriteResult {
         let mut arg_item_strs: Vec<_> = args.iter().map(|a| self.rewrite_fn_input(a)).collect();
         // Account for sugary self.
         // FIXME: the comment for the self argument is dropped. This is blocked
@@ -254,7 +330,7 @@ impl<'a> FmtVisitor<'a> {
         // You also don't get to put a comment on self, unless it is explicit.
         if args.len() >= min_args {
             let comment_span_start = if min_args == 2 {
-                span_after(span, ",", self.codemap)
+                try!(span_after(span, ",", self.codemap))
             } else {
                 span.lo
             };
@@ -280,14 +356,16 @@ impl<'a> FmtVisitor<'a> {
         let fmt = ListFormatting {
             tactic: ListTactic::HorizontalVertical,
             separator: ",",
-            trailing_separator: SeparatorTactic::Never,
+            trailing_separator: self.config.trailing_comma,
+            separator_place: SeparatorPlace::Back,
             indent: arg_indent,
             h_width: one_line_budget,
             v_width: multi_line_budget,
             ends_with_newline: false,
+            config: self.config,
         };
 
-        write_list(&arg_items, &fmt)
+        Ok(write_list(&arg_items, &fmt))
     }
 
     fn compute_budgets_for_args(&self,
@@ -300,40 +378,33 @@ impl<'a> FmtVisitor<'a> {
 
         // Try keeping everything on the same line
         if !result.contains("\n") {
-            // 3 = `() `, space is before ret_string
-            let mut used_space = indent + result.len() + ret_str_len + 3;
+            // The space is before ret_string.
+            let mut used_space = indent + result.len() + ret_str_len + "() ".len();
             if !newline_brace {
-                used_space += 2;
+                used_space += " {".len();
             }
-            let one_line_budget = if used_space > self.config.max_width {
-                0
-            } else {
-                self.config.max_width - used_space
-            };
+            let one_line_budget = saturating_reserve_width(self.config.max_width, used_space);
 
-            // 2 = `()`
-            let used_space = indent + result.len() + 2;
+            let used_space = indent + result.len() + "()".len();
             let max_space = self.config.ideal_width + self.config.leeway;
             debug!("compute_budgets_for_args: used_space: {}, max_space: {}",
                    used_space, max_space);
             if used_space < max_space {
                 budgets = Some((one_line_budget,
                                 max_space - used_space,
-                                indent + result.len() + 1));
+                                indent + result.len() + "(".len()));
             }
         }
 
         // Didn't work. we must force vertical layout and put args on a newline.
         if let None = budgets {
             let new_indent = indent + self.config.tab_spaces;
-            let used_space = new_indent + 2; // account for `(` and `)`
+            let used_space = new_indent + "()".len();
             let max_space = self.config.ideal_width + self.config.leeway;
-            if used_space > max_space {
-                // Whoops! bankrupt.
-                // TODO take evasive action, perhaps kill the indent or something.
-            } else {
-                budgets = Some((0, max_space - used_space, new_indent));
-            }
+            // If we are bankrupt there is no room at all, and the arguments
+            // will overflow. TODO take evasive action, perhaps kill the indent
+            // or something.
+            budgets = Some((0, saturating_reserve_width(max_space, used_space), new_indent));
         }
 
         budgets.unwrap()
@@ -354,19 +425,28 @@ impl<'a> FmtVisitor<'a> {
                       generics: &ast::Generics,
                       span: Span) {
         let header_str = self.format_header("enum ", ident, vis);
+        let header = span_after(span, "{", self.codemap).and_then(|body_start| {
+            let generics_str = try!(self.format_generics(generics,
+                                                         " {",
+                                                         self.block_indent +
+                                                         self.config.tab_spaces,
+                                                         codemap::mk_sp(span.lo, body_start)));
+            Ok((body_start, generics_str))
+        });
+        let (body_start, generics_str) = match header {
+            Ok(result) => result,
+            Err(e) => {
+                self.record_failure(span, &format!("could not format enum: {}", e));
+                self.push_verbatim(span);
+                return;
+            }
+        };
         self.buffer.push_str(&header_str);
-
-        let enum_snippet = self.snippet(span);
-        let body_start = span.lo + BytePos(enum_snippet.find_uncommented("{").unwrap() as u32 + 1);
-        let generics_str = self.format_generics(generics,
-                                                " {",
-                                                self.block_indent + self.config.tab_spaces,
-                                                codemap::mk_sp(span.lo,
-                                                               body_start));
         self.buffer.push_str(&generics_str);
 
         self.last_pos = body_start;
         self.block_indent += self.config.tab_spaces;
+        let name_widths = self.discrim_name_widths(enum_def);
         for (i, f) in enum_def.variants.iter().enumerate() {
             let next_span_start: BytePos = if i == enum_def.variants.len() - 1 {
                 span.hi
@@ -374,16 +454,70 @@ impl<'a> FmtVisitor<'a> {
                 enum_def.variants[i + 1].span.lo
             };
 
-            self.visit_variant(f, i == enum_def.variants.len() - 1, next_span_start);
+            self.visit_variant(f,
+                               i == enum_def.variants.len() - 1,
+                               next_span_start,
+                               name_widths[i]);
         }
         self.block_indent -= self.config.tab_spaces;
 
+        let enum_snippet = self.snippet(span);
         self.format_missing_with_indent(span.lo + BytePos(enum_snippet.rfind('}').unwrap() as u32));
         self.buffer.push_str("}");
     }
 
-    // Variant of an enum
-    fn visit_variant(&mut self, field: &ast::Variant, last_field: bool, next_span_start: BytePos) {
+    // The width to pad the name of each variant of enum_def to, so that the
+    // discriminants of a group of variants, a run with no blank lines between
+    // them, line up. Variants with longer names than
+    // enum_discrim_align_threshold don't count towards the width of their
+    // group and aren't padded, nor are those without discriminants; theirs is
+    // zero.
+    fn discrim_name_widths(&self, enum_def: &ast::EnumDef) -> Vec<usize> {
+        let variants = &enum_def.variants;
+        let threshold = self.config.enum_discrim_align_threshold;
+        let mut widths = vec![0; variants.len()];
+        if threshold == 0 {
+            return widths;
+        }
+
+        let names: Vec<Option<usize>> = variants.iter()
+                                                .map(|v| aligned_name_width(v, threshold))
+                                                .collect();
+
+        let mut group_start = 0;
+        while group_start < variants.len() {
+            let mut group_end = group_start + 1;
+            while group_end < variants.len() {
+                let between = codemap::mk_sp(variants[group_end - 1].span.hi,
+                                             variants[group_end].span.lo);
+                if count_blank_lines(self.codemap, between) > 0 {
+                    break;
+                }
+                group_end += 1;
+            }
+            let name_width = names[group_start..group_end]
+                                 .iter()
+                                 .filter_map(|&width| width)
+                                 .max()
+                                 .unwrap_or(0);
+            for i in group_start..group_end {
+                if names[i].is_some() {
+                    widths[i] = name_width;
+                }
+            }
+            group_start = group_end;
+        }
+
+        widths
+    }
+
+    // Variant of an enum. name_width is the width to pad its name to when it
+    // has a discriminant.
+    fn visit_variant(&mut self,
+                     field: &ast::Variant,
+                     last_field: bool,
+                     next_span_start: BytePos,
+                     name_width: usize) {
         if self.visit_attrs(&field.node.attrs) {
             return;
         }
@@ -392,66 +526,8 @@ impl<'a> FmtVisitor<'a> {
 
         let result = match field.node.kind {
             ast::VariantKind::TupleVariantKind(ref types) => {
-                let vis = format_visibility(field.node.vis);
-                self.buffer.push_str(vis);
-                let name = field.node.name.to_string();
-                self.buffer.push_str(&name);
-
-                let mut result = String::new();
-
-                if types.len() > 0 {
-                    let items = itemize_list(self.codemap,
-                                             Vec::new(),
-                                             types.iter(),
-                                             ",",
-                                             ")",
-                                             |arg| arg.ty.span.lo,
-                                             |arg| arg.ty.span.hi,
-                                             |arg| pprust::ty_to_string(&arg.ty),
-                                             span_after(field.span, "(", self.codemap),
-                                             next_span_start);
-
-                    result.push('(');
-
-                    let indent = self.block_indent
-                                 + vis.len()
-                                 + field.node.name.to_string().len()
-                                 + 1; // Open paren
-
-                    let comma_cost = if self.config.enum_trailing_comma {
-                        1
-                    } else {
-                        0
-                    };
-                    let budget = self.config.ideal_width - indent - comma_cost - 1; // 1 = )
-
-                    let fmt = ListFormatting {
-                        tactic: ListTactic::HorizontalVertical,
-                        separator: ",",
-                        trailing_separator: SeparatorTactic::Never,
-                        indent: indent,
-                        h_width: budget,
-                        v_width: budget,
-                        ends_with_newline: true,
-                    };
-                    result.push_str(&write_list(&items, &fmt));
-                    result.push(')');
-                }
-
-                if let Some(ref expr) = field.node.disr_expr {
-                    result.push_str(" = ");
-                    let expr_snippet = self.snippet(expr.span);
-                    result.push_str(&expr_snippet);
-
-                    // Make sure we do not exceed column limit
-                    // 4 = " = ,"
-                    assert!(
-                        self.config.max_width >= vis.len() + name.len() + expr_snippet.len() + 4,
-                        "Enum variant exceeded column limit");
-                }
-
-                result
-            },
+                self.format_tuple_variant(field, types, next_span_start, name_width)
+            }
             ast::VariantKind::StructVariantKind(ref struct_def) => {
                 // TODO Should limit the width, as we have a trailing comma
                 self.format_struct("",
@@ -463,6 +539,14 @@ impl<'a> FmtVisitor<'a> {
                                    self.block_indent)
             }
         };
+        let result = match result {
+            Ok(ref s) if !self.accept_rewrite("variant", field.span, s) => self.snippet(field.span),
+            Ok(s) => s,
+            Err(e) => {
+                self.record_failure(field.span, &format!("could not format variant: {}", e));
+                self.snippet(field.span)
+            }
+        };
         self.buffer.push_str(&result);
 
         if !last_field || self.config.enum_trailing_comma {
@@ -472,6 +556,79 @@ impl<'a> FmtVisitor<'a> {
         self.last_pos = field.span.hi + BytePos(1);
     }
 
+    fn format_tuple_variant(&self,
+                            field: &ast::Variant,
+                            types: &[ast::VariantArg],
+                            next_span_start: BytePos,
+                            name_width: usize)
+                            -> RewriteResult {
+        let vis = format_visibility(field.node.vis);
+        let name = field.node.name.to_string();
+        let mut result = format!("{}{}", vis, name);
+
+        if types.len() > 0 {
+            let list_lo = try!(span_after(field.span, "(", self.codemap));
+            let items = itemize_list(self.codemap,
+                                     Vec::new(),
+                                     types.iter(),
+                                     ",",
+                                     ")",
+                                     |arg| arg.ty.span.lo,
+                                     |arg| arg.ty.span.hi,
+                                     |arg| pprust::ty_to_string(&arg.ty),
+                                     list_lo,
+                                     next_span_start);
+
+            result.push('(');
+
+            let indent = self.block_indent
+                         + vis.len()
+                         + field.node.name.to_string().len()
+                         + "(".len();
+
+            let comma_cost = if self.config.enum_trailing_comma {
+                ",".len()
+            } else {
+                0
+            };
+            let budget = saturating_reserve(saturating_reserve_width(self.config.ideal_width,
+                                                                     indent + comma_cost),
+                                            ")");
+
+            let fmt = ListFormatting {
+                tactic: ListTactic::HorizontalVertical,
+                separator: ",",
+                trailing_separator: self.config.trailing_comma,
+                separator_place: SeparatorPlace::Back,
+                indent: indent,
+                h_width: budget,
+                v_width: budget,
+                ends_with_newline: true,
+                config: self.config,
+            };
+            result.push_str(&write_list(&items, &fmt));
+            result.push(')');
+        }
+
+        if let Some(ref expr) = field.node.disr_expr {
+            let expr_snippet = self.snippet(expr.span);
+            let padding = name_width.checked_sub(vis.len() + name.len()).unwrap_or(0);
+            if self.block_indent + name_width + expr_snippet.len() + " = ,".len() <=
+               self.config.max_width {
+                result.push_str(&make_indent(padding));
+            }
+            result.push_str(" = ");
+            result.push_str(&expr_snippet);
+
+            // Leave variants which can't fit in the column limit alone.
+            if self.config.max_width < vis.len() + name.len() + expr_snippet.len() + " = ,".len() {
+                return Err(RewriteError::WidthExhausted);
+            }
+        }
+
+        Ok(result)
+    }
+
     fn format_struct(&self,
                      item_name: &str,
                      ident: ast::Ident,
@@ -480,7 +637,7 @@ impl<'a> FmtVisitor<'a> {
                      generics: Option<&ast::Generics>,
                      span: Span,
                      offset: usize)
-                     -> String {
+                     -> RewriteResult {
         let mut result = String::with_capacity(1024);
 
         let header_str = self.format_header(item_name, ident, vis);
@@ -488,12 +645,12 @@ impl<'a> FmtVisitor<'a> {
 
         if struct_def.fields.len() == 0 {
             result.push(';');
-            return result;
+            return Ok(result);
         }
 
         let is_tuple = match struct_def.fields[0].node.kind {
             ast::StructFieldKind::NamedField(..) => false,
-            ast::StructFieldKind::UnnamedField(..) => true
+            ast::StructFieldKind::UnnamedField(..) => true,
         };
 
         let (opener, terminator) = if is_tuple {
@@ -503,15 +660,16 @@ impl<'a> FmtVisitor<'a> {
         };
 
         let generics_str = match generics {
-            Some(g) => self.format_generics(g,
-                                            opener,
-                                            offset + header_str.len(),
-                                            codemap::mk_sp(span.lo,
-                                                           struct_def.fields[0].span.lo)),
-            None => opener.to_owned()
+            Some(g) => try!(self.format_generics(g,
+                                                 opener,
+                                                 offset + header_str.len(),
+                                                 codemap::mk_sp(span.lo,
+                                                                struct_def.fields[0].span.lo))),
+            None => opener.to_owned(),
         };
         result.push_str(&generics_str);
 
+        let list_lo = try!(span_after(span, opener.trim(), self.codemap));
         let items = itemize_list(self.codemap,
                                  Vec::new(),
                                  struct_def.fields.iter(),
@@ -528,41 +686,42 @@ impl<'a> FmtVisitor<'a> {
                                  },
                                  |field| field.node.ty.span.hi,
                                  |field| self.format_field(field),
-                                 span_after(span, opener.trim(), self.codemap),
+                                 list_lo,
                                  span.hi);
 
-        // 2 terminators and a semicolon
-        let used_budget = offset + header_str.len() + generics_str.len() + 3;
+        // The terminators and a semicolon.
+        let used_budget = offset + header_str.len() + generics_str.len() + "();".len();
+
+        let budget = saturating_reserve(saturating_reserve_width(self.config.ideal_width +
+                                                                 self.config.tab_spaces,
+                                                                 offset),
+                                        ",");
+        let mut fmt = ListFormatting {
+            tactic: ListTactic::Horizontal,
+            separator: ",",
+            trailing_separator: self.config.struct_trailing_comma,
+            separator_place: SeparatorPlace::Back,
+            indent: offset + self.config.tab_spaces,
+            h_width: self.config.max_width,
+            v_width: budget,
+            ends_with_newline: true,
+            config: self.config,
+        };
 
         // Conservative approximation
         let single_line_cost = (span.hi - struct_def.fields[0].span.lo).0;
+        // Fields spanning lines, e.g. with attributes, can't share a line.
         let break_line = !is_tuple || generics_str.contains('\n') ||
-                         single_line_cost as usize + used_budget > self.config.max_width;
+                         single_line_cost as usize + used_budget > self.config.max_width ||
+                         definitive_tactic(&items, &fmt) == ListTactic::Vertical;
 
         if break_line {
             let indentation = make_indent(offset + self.config.tab_spaces);
             result.push('\n');
             result.push_str(&indentation);
+            fmt.tactic = ListTactic::Vertical;
         }
 
-        let tactic = if break_line {
-            ListTactic::Vertical
-        } else {
-            ListTactic::Horizontal
-        };
-
-        // 1 = ,
-        let budget = self.config.ideal_width - offset + self.config.tab_spaces - 1;
-        let fmt = ListFormatting {
-            tactic: tactic,
-            separator: ",",
-            trailing_separator: self.config.struct_trailing_comma,
-            indent: offset + self.config.tab_spaces,
-            h_width: self.config.max_width,
-            v_width: budget,
-            ends_with_newline: true,
-        };
-
         result.push_str(&write_list(&items, &fmt));
 
         if break_line {
@@ -576,7 +735,7 @@ impl<'a> FmtVisitor<'a> {
             result.push(';');
         }
 
-        result
+        Ok(result)
     }
 
     pub fn visit_struct(&mut self,
@@ -586,15 +745,15 @@ impl<'a> FmtVisitor<'a> {
                         generics: &ast::Generics,
                         span: Span) {
         let indent = self.block_indent;
-        let result = self.format_struct("struct ",
-                                        ident,
-                                        vis,
-                                        struct_def,
-                                        Some(generics),
-                                        span,
-                                        indent);
-        self.buffer.push_str(&result);
-        self.last_pos = span.hi;
+        self.push_item_rewrite("struct", span, |visitor| {
+            visitor.format_struct("struct ",
+                                  ident,
+                                  vis,
+                                  struct_def,
+                                  Some(generics),
+                                  span,
+                                  indent)
+        });
     }
 
     fn format_header(&self, item_name: &str, ident: ast::Ident, vis: ast::Visibility) -> String {
@@ -606,8 +765,8 @@ impl<'a> FmtVisitor<'a> {
                        opener: &str,
                        offset: usize,
                        span: Span)
-                       -> String {
-        let mut result = self.rewrite_generics(generics, offset, span);
+                       -> RewriteResult {
+        let mut result = try!(self.rewrite_generics(generics, offset, span));
 
         if generics.where_clause.predicates.len() > 0 || result.contains('\n') {
             result.push_str(&self.rewrite_where_clause(&generics.where_clause,
@@ -621,7 +780,7 @@ impl<'a> FmtVisitor<'a> {
             result.push_str(opener);
         }
 
-        result
+        Ok(result)
     }
 
     // Field of a struct
@@ -636,7 +795,7 @@ impl<'a> FmtVisitor<'a> {
         };
         let vis = match field.node.kind {
             ast::StructFieldKind::NamedField(_, vis) |
-            ast::StructFieldKind::UnnamedField(vis) => format_visibility(vis)
+            ast::StructFieldKind::UnnamedField(vis) => format_visibility(vis),
         };
         let typ = pprust::ty_to_string(&field.node.ty);
 
@@ -649,46 +808,47 @@ impl<'a> FmtVisitor<'a> {
 
         match name {
             Some(name) => format!("{}{}{}: {}", attr_str, vis, name, typ),
-            None => format!("{}{}{}", attr_str, vis, typ)
+            None => format!("{}{}{}", attr_str, vis, typ),
         }
     }
 
-    fn rewrite_generics(&self, generics: &ast::Generics, offset: usize, span: Span) -> String {
+    fn rewrite_generics(&self,
+                        generics: &ast::Generics,
+                        offset: usize,
+                        span: Span)
+                        -> RewriteResult {
         // FIXME convert bounds to where clauses where they get too big or if
         // there is a where clause at all.
         let mut result = String::new();
         let lifetimes: &[_] = &generics.lifetimes;
         let tys: &[_] = &generics.ty_params;
         if lifetimes.len() + tys.len() == 0 {
-            return result;
+            return Ok(result);
         }
 
-        let budget = self.config.max_width - offset - 2;
+        let budget = saturating_reserve(saturating_reserve_width(self.config.max_width, offset),
+                                        "<>");
         // TODO might need to insert a newline if the generics are really long
         result.push('<');
 
         // Strings for the generics.
-        // 1 = <
+        let list_offset = offset + "<".len();
         let context = self.get_context();
-        // FIXME: don't unwrap
+        // A generic which doesn't fit in the budget is left as it was.
         let lt_strs = lifetimes.iter().map(|lt| {
-            lt.rewrite(&context, budget, offset + 1).unwrap()
+            lt.rewrite(&context, budget, list_offset)
+              .unwrap_or_else(|_| self.snippet(span_for_lifetime_def(lt)))
         });
         let ty_strs = tys.iter().map(|ty_param| {
-            ty_param.rewrite(&context, budget, offset + 1).unwrap()
+            ty_param.rewrite(&context, budget, list_offset)
+                    .unwrap_or_else(|_| self.snippet(span_for_ty_param(ty_param)))
         });
 
         // Extract comments between generics.
-        let lt_spans = lifetimes.iter().map(|l| {
-            let hi = if l.bounds.len() == 0 {
-                l.lifetime.span.hi
-            } else {
-                l.bounds[l.bounds.len() - 1].span.hi
-            };
-            codemap::mk_sp(l.lifetime.span.lo, hi)
-        });
+        let lt_spans = lifetimes.iter().map(span_for_lifetime_def);
         let ty_spans = tys.iter().map(span_for_ty_param);
 
+        let list_lo = try!(span_after(span, "<", self.codemap));
         let mut items = itemize_list(self.codemap,
                                      Vec::new(),
                                      lt_spans.chain(ty_spans),
@@ -697,7 +857,7 @@ impl<'a> FmtVisitor<'a> {
                                      |sp| sp.lo,
                                      |sp| sp.hi,
                                      |_| String::new(),
-                                     span_after(span, "<", self.codemap),
+                                     list_lo,
                                      span.hi);
 
         for (item, ty) in items.iter_mut().zip(lt_strs.chain(ty_strs)) {
@@ -707,17 +867,19 @@ impl<'a> FmtVisitor<'a> {
         let fmt = ListFormatting {
             tactic: ListTactic::HorizontalVertical,
             separator: ",",
-            trailing_separator: SeparatorTactic::Never,
-            indent: offset + 1,
+            trailing_separator: self.config.trailing_comma,
+            separator_place: SeparatorPlace::Back,
+            indent: list_offset,
             h_width: budget,
             v_width: budget,
             ends_with_newline: false,
+            config: self.config,
         };
         result.push_str(&write_list(&items, &fmt));
 
         result.push('>');
 
-        result
+        Ok(result)
     }
 
     fn rewrite_where_clause(&self,
@@ -736,9 +898,9 @@ impl<'a> FmtVisitor<'a> {
         result.push_str("where ");
 
         let context = self.get_context();
-        // 6 = "where ".len()
-        let offset = indent + config.tab_spaces + 6;
-        let budget = self.config.ideal_width + self.config.leeway - offset;
+        let offset = indent + config.tab_spaces + "where ".len();
+        let budget = saturating_reserve_width(self.config.ideal_width + self.config.leeway,
+                                              offset);
         let span_start = span_for_where_pred(&where_clause.predicates[0]).lo;
         let items = itemize_list(self.codemap,
                                  Vec::new(),
@@ -750,18 +912,25 @@ impl<'a> FmtVisitor<'a> {
                                  // FIXME: we should handle failure better
                                  // this will be taken care of when write_list
                                  // takes Rewrite object: see issue #133
-                                 |pred| pred.rewrite(&context, budget, offset).unwrap(),
+                                 |pred| {
+                                     pred.rewrite(&context, budget, offset)
+                                         .unwrap_or_else(|_| {
+                                             self.snippet(span_for_where_pred(pred))
+                                         })
+                                 },
                                  span_start,
                                  span_end);
 
         let fmt = ListFormatting {
             tactic: ListTactic::Vertical,
             separator: ",",
-            trailing_separator: SeparatorTactic::Never,
+            trailing_separator: self.config.trailing_comma,
+            separator_place: self.config.where_separator_place,
             indent: offset,
             h_width: budget,
             v_width: budget,
             ends_with_newline: false,
+            config: self.config,
         };
         result.push_str(&write_list(&items, &fmt));
 
@@ -789,6 +958,24 @@ impl<'a> FmtVisitor<'a> {
     }
 }
 
+// The width of the name of variant if it has a discriminant to line up, one no
+// wider than threshold.
+fn aligned_name_width(variant: &ast::Variant, threshold: usize) -> Option<usize> {
+    match variant.node.kind {
+        ast::VariantKind::TupleVariantKind(ref types) if types.is_empty() => {}
+        _ => return None,
+    }
+    if variant.node.disr_expr.is_none() {
+        return None;
+    }
+    let width = format_visibility(variant.node.vis).len() + variant.node.name.to_string().len();
+    if width <= threshold {
+        Some(width)
+    } else {
+        None
+    }
+}
+
 fn rewrite_explicit_self(explicit_self: &ast::ExplicitSelf, args: &[ast::Arg]) -> Option<String> {
     match explicit_self.node {
         ast::ExplicitSelf_::SelfRegion(lt, m, _) => {
@@ -816,7 +1003,7 @@ fn rewrite_explicit_self(explicit_self: &ast::ExplicitSelf, args: &[ast::Arg]) -
 
             Some(format!("{}self", mut_str))
         }
-        _ => None
+        _ => None,
     }
 }
 
@@ -844,6 +1031,15 @@ fn span_for_return(ret: &ast::FunctionRetTy) -> Span {
     }
 }
 
+fn span_for_lifetime_def(l: &ast::LifetimeDef) -> Span {
+    let hi = if l.bounds.len() == 0 {
+        l.lifetime.span.hi
+    } else {
+        l.bounds[l.bounds.len() - 1].span.hi
+    };
+    codemap::mk_sp(l.lifetime.span.lo, hi)
+}
+
 fn span_for_ty_param(ty: &ast::TyParam) -> Span {
     // Note that ty.span is the span for ty.ident, not the whole item.
     let lo = ty.span.lo;
@@ -8,9 +8,7 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-#![feature(rustc_private)]
-#![feature(str_escape)]
-#![feature(str_char)]
+#![cfg_attr(not(feature = "syntex"), feature(rustc_private))]
 
 
 // TODO we're going to allocate a whole bunch of temp Strings, is it worth
@@ -21,49 +19,87 @@
 #[macro_use]
 extern crate log;
 
+#[cfg(not(feature = "syntex"))]
 extern crate getopts;
+#[cfg(not(feature = "syntex"))]
 extern crate rustc;
+#[cfg(not(feature = "syntex"))]
 extern crate rustc_driver;
+#[cfg(not(feature = "syntex"))]
 extern crate syntax;
+// A copy of the compiler's parser which builds on stable Rust.
+#[cfg(feature = "syntex")]
+extern crate syntex_syntax as syntax;
 extern crate rustc_serialize;
 
 extern crate strings;
-
-use rustc::session::Session;
-use rustc::session::config as rustc_config;
-use rustc::session::config::Input;
-use rustc_driver::{driver, CompilerCalls, Compilation};
+extern crate diff;
+extern crate time;
+extern crate libc;
+extern crate unicode_width;
 
 use syntax::ast;
 use syntax::codemap::CodeMap;
-use syntax::diagnostics;
 
 use std::path::PathBuf;
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::rc::Rc;
 use std::fmt;
-use std::mem::swap;
+use std::fs;
+use std::io::{self, Write};
+
+use strings::string_buffer::StringBuffer;
 
 use issues::{BadIssueSeeker, Issue};
 use filemap::FileMap;
 use visitor::FmtVisitor;
-use config::Config;
+use config::{Config, Verbosity};
+use summary::Summary;
+use profile::Profile;
+use stats::Stats;
+use driver::format_inner;
 
 #[macro_use]
 mod utils;
 pub mod config;
-mod filemap;
+pub mod walk;
+mod gitignore;
+pub mod git;
+pub mod file_lines;
+pub mod filemap;
+pub mod parallel;
 mod visitor;
 mod items;
 mod missed_spans;
 mod lists;
 mod types;
 mod expr;
+mod bindings;
+mod reorder;
 mod imports;
 mod issues;
 mod rewrite;
+mod budget;
 mod string;
+mod literals;
 mod comment;
 mod modules;
+mod macros;
+mod rustfmt_diff;
+pub mod summary;
+pub mod snippet;
+pub mod markdown;
+pub mod session;
+pub mod profile;
+pub mod stats;
+mod cache;
+pub mod edits;
+pub mod daemon;
+pub mod lint;
+mod checkstyle;
+mod equivalence;
+mod driver;
 
 const MIN_STRING: usize = 10;
 // When we get scoped annotations, we should have rustfmt::skip.
@@ -76,17 +112,55 @@ pub enum WriteMode {
     NewFile(&'static str),
     // Write the output to stdout.
     Display,
+    // Write the output to stdout, without a filename header. Used when
+    // formatting stdin.
+    Plain,
     // Return the result as a mapping from filenames to StringBuffers.
     Return(&'static Fn(HashMap<String, String>)),
+    // Write a JSON description of the differences between each file and its
+    // formatted version to stdout, leaving the files untouched.
+    Json,
+    // Write Checkstyle XML listing the lines which would change to stdout,
+    // leaving the files untouched.
+    Checkstyle,
+    // Print the names of files which would be changed by formatting, leaving
+    // the files untouched.
+    List,
+    // Print the changes formatting would make as a diff, leaving the files
+    // untouched.
+    Diff,
+    // Print the changes formatting would make to stdout as a patch which
+    // `git apply` accepts, leaving the files untouched.
+    Patch,
+    // Write the changes formatting would make to each file as a patch, in a
+    // new file named after it with `.patch` added, leaving the files
+    // untouched.
+    PatchFiles,
+}
+
+impl WriteMode {
+    // True if the result is written to stdout with no decoration, in which
+    // case our own messages must go elsewhere.
+    fn is_machine_output(&self) -> bool {
+        match *self {
+            WriteMode::Plain |
+            WriteMode::Json |
+            WriteMode::Checkstyle |
+            WriteMode::List |
+            WriteMode::Patch => true,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum NewlineStyle {
     Windows, // \r\n
     Unix, // \n
+    Auto, // Whichever the file already uses.
 }
 
-impl_enum_decodable!(NewlineStyle, Windows, Unix);
+impl_enum_decodable!(NewlineStyle, Windows, Unix, Auto);
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum BraceStyle {
@@ -124,8 +198,9 @@ pub enum StructLitStyle {
 impl_enum_decodable!(StructLitStyle, VisualIndent, BlockIndent);
 
 enum ErrorKind {
-    // Line has exceeded character limit
-    LineOverflow,
+    // Line has exceeded character limit, at the given column, in the given
+    // kind of code.
+    LineOverflow(usize, &'static str),
     // Line ends in whitespace
     TrailingWhitespace,
     // TO-DO or FIX-ME item without an issue number
@@ -135,8 +210,8 @@ enum ErrorKind {
 impl fmt::Display for ErrorKind {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match *self {
-            ErrorKind::LineOverflow => {
-                write!(fmt, "line exceeded maximum length")
+            ErrorKind::LineOverflow(_, kind) => {
+                write!(fmt, "line exceeded maximum length in {}", kind)
             }
             ErrorKind::TrailingWhitespace => {
                 write!(fmt, "left behind trailing whitespace")
@@ -157,7 +232,7 @@ struct FormattingError {
 impl FormattingError {
     fn msg_prefix(&self) -> &str {
         match self.kind {
-            ErrorKind::LineOverflow |
+            ErrorKind::LineOverflow(..) |
             ErrorKind::TrailingWhitespace => "Rustfmt failed at",
             ErrorKind::BadIssue(_) => "WARNING:",
         }
@@ -165,28 +240,167 @@ impl FormattingError {
 
     fn msg_suffix(&self) -> &str {
         match self.kind {
-            ErrorKind::LineOverflow |
+            ErrorKind::LineOverflow(..) |
             ErrorKind::TrailingWhitespace => "(sorry)",
             ErrorKind::BadIssue(_) => "",
         }
     }
 }
 
-struct FormatReport {
+// A piece of code rustfmt gave up on and left as it was. Lines and columns
+// are numbered from 1.
+#[derive(RustcEncodable, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct RewriteFailure {
+    pub file: String,
+    pub lo_line: usize,
+    pub lo_col: usize,
+    pub hi_line: usize,
+    pub hi_col: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for RewriteFailure {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(fmt,
+               "{}:{}:{}: {}:{}: {}",
+               self.file,
+               self.lo_line,
+               self.lo_col,
+               self.hi_line,
+               self.hi_col,
+               self.reason)
+    }
+}
+
+// A rewrite rustfmt is about to make, as passed to a RewriteHook or a
+// RewritePassFn. Lines and columns are numbered from 1.
+pub struct RewriteEvent<'a> {
+    // The kind of code: "import", "fn signature", "struct" or the kind of an
+    // expression, e.g., "call" or "method call chain", see expr::expr_kind.
+    pub kind: &'a str,
+    pub file: &'a str,
+    pub lo_line: usize,
+    pub lo_col: usize,
+    pub hi_line: usize,
+    pub hi_col: usize,
+    pub original: &'a str,
+    pub proposed: &'a str,
+}
+
+// Called for each rewrite of an expression or import. Returning false vetoes
+// the rewrite and the original code is kept. The hook may be called more than
+// once for the same code, since rustfmt tries several layouts for some
+// expressions.
+pub type RewriteHookFn = Fn(&RewriteEvent) -> bool + Send + Sync;
+pub type RewriteHook = Arc<RewriteHookFn>;
+
+// A custom rewrite of one kind of code, run after rustfmt's own. It is given
+// rustfmt's rewrite as the event's proposed text, and returns new text for the
+// code, or None to keep the proposed text. Passes run on the statements,
+// imports, function signatures and structs rustfmt writes out, not on the
+// expressions nested in them, and like hooks may be called more than once for
+// the same code.
+pub type RewritePassFn = Fn(&RewriteEvent, &Config) -> Option<String> + Send + Sync;
+
+// What a library user adds to rustfmt: a hook to approve each rewrite, and
+// passes which rewrite certain kinds of code further.
+#[derive(Clone, Default)]
+pub struct Plugins {
+    pub hook: Option<RewriteHook>,
+    // The kind of code each pass applies to, see RewriteEvent::kind, in the
+    // order the passes run in.
+    passes: Vec<(String, Arc<RewritePassFn>)>,
+}
+
+impl Plugins {
+    pub fn new() -> Plugins {
+        Plugins::default()
+    }
+
+    pub fn with_hook(hook: RewriteHook) -> Plugins {
+        Plugins { hook: Some(hook), passes: Vec::new() }
+    }
+
+    // Runs pass on each rewrite of kind, after the passes added before it.
+    pub fn add_pass(&mut self, kind: &str, pass: Arc<RewritePassFn>) {
+        self.passes.push((kind.to_owned(), pass));
+    }
+}
+
+pub struct FormatReport {
     // Maps stringified file paths to their associated formatting errors
     file_error_map: HashMap<String, Vec<FormattingError>>,
+    // Sorted by position, without duplicates.
+    rewrite_failures: Vec<RewriteFailure>,
+    profile: Profile,
+    stats: Stats,
+}
+
+impl FormatReport {
+    pub fn new() -> FormatReport {
+        FormatReport {
+            file_error_map: HashMap::new(),
+            rewrite_failures: Vec::new(),
+            profile: Profile::new(),
+            stats: Stats::new(),
+        }
+    }
+
+    // Empty unless the config asks for profiling.
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    // Empty unless the config asks for statistics. Failed rewrites are not
+    // included, see rewrite_failures.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn rewrite_failures(&self) -> &[RewriteFailure] {
+        &self.rewrite_failures
+    }
+
+    fn add_rewrite_failures(&mut self, failures: Vec<RewriteFailure>) {
+        self.rewrite_failures.extend(failures);
+        self.rewrite_failures.sort();
+        // A failure can be recorded more than once when an enclosing
+        // expression is rewritten several times trying different widths.
+        self.rewrite_failures.dedup();
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.file_error_map.values().map(|errors| errors.len()).fold(0, |a, b| a + b)
+    }
+
+    // Add the errors from another run, e.g., formatting another crate.
+    pub fn merge(&mut self, other: FormatReport) {
+        for (file, errors) in other.file_error_map {
+            self.file_error_map.insert(file, errors);
+        }
+        self.add_rewrite_failures(other.rewrite_failures);
+        self.profile.merge(other.profile);
+        self.stats.merge(other.stats);
+    }
 }
 
 impl fmt::Display for FormatReport {
     // Prints all the formatting errors.
     fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        for (file, errors) in self.file_error_map.iter() {
-            for error in errors {
+        let mut files: Vec<_> = self.file_error_map.keys().collect();
+        files.sort();
+        for file in files {
+            for error in &self.file_error_map[file] {
+                let col = match error.kind {
+                    ErrorKind::LineOverflow(col, _) => format!(":{}", col),
+                    _ => String::new(),
+                };
                 try!(write!(fmt,
-                            "{} {}:{}: {} {}\n",
+                            "{} {}:{}{}: {} {}\n",
                             error.msg_prefix(),
                             file,
                             error.line,
+                            col,
                             error.kind,
                             error.msg_suffix()));
             }
@@ -196,12 +410,50 @@ impl fmt::Display for FormatReport {
 }
 
 // Formatting which depends on the AST.
-fn fmt_ast(krate: &ast::Crate, codemap: &CodeMap, config: &Config) -> FileMap {
+// If `follow_mods` is false, only the root module of the crate is formatted
+// and out of line modules are left untouched.
+// Any code which could not be formatted is recorded in failures.
+fn fmt_ast(krate: &ast::Crate,
+           codemap: &CodeMap,
+           config: &Config,
+           follow_mods: bool,
+           plugins: &Plugins,
+           failures: &mut Vec<RewriteFailure>,
+           profile: &mut Profile,
+           stats: &mut Stats)
+           -> FileMap {
     let mut file_map = FileMap::new();
-    for (path, module) in modules::list_files(krate, codemap) {
+    let files = if follow_mods {
+        modules::list_files(krate, codemap)
+    } else {
+        let mut files = HashMap::new();
+        files.insert(PathBuf::from(codemap.span_to_filename(krate.span)), &krate.module);
+        files
+    };
+    let trait_orders = if config.reorder_impl_items {
+        reorder::trait_orders(krate)
+    } else {
+        Rc::new(HashMap::new())
+    };
+    for (path, module) in files {
         let path = path.to_str().unwrap();
+        let start = time::precise_time_ns();
         let mut visitor = FmtVisitor::from_codemap(codemap, config);
+        visitor.plugins = Some(plugins);
+        visitor.trait_orders = trait_orders.clone();
         visitor.format_separate_mod(module, path);
+        if config.verbosity == Verbosity::Verbose {
+            let _ = writeln!(io::stderr(),
+                             "Formatted {} in {}ms",
+                             path,
+                             (time::precise_time_ns() - start) / 1_000_000);
+        }
+        if config.profile {
+            profile.add("visit", time::precise_time_ns() - start);
+        }
+        failures.extend(visitor.failures.into_inner());
+        profile.merge(visitor.profile.borrow().clone());
+        stats.merge(visitor.stats.into_inner());
         file_map.insert(path.to_owned(), visitor.buffer);
     }
     file_map
@@ -210,17 +462,18 @@ fn fmt_ast(krate: &ast::Crate, codemap: &CodeMap, config: &Config) -> FileMap {
 // Formatting done on a char by char or line by line basis.
 // TODO warn on bad license
 // TODO other stuff for parity with make tidy
-fn fmt_lines(file_map: &mut FileMap, config: &Config) -> FormatReport {
-    let mut truncate_todo = Vec::new();
-    let mut report = FormatReport { file_error_map: HashMap::new() };
+fn fmt_lines(file_map: &FileMap, config: &Config) -> FormatReport {
+    let mut report = FormatReport::new();
 
     // Iterate over the chars in the file map.
     for (f, text) in file_map.iter() {
         let mut trims = vec![];
         let mut last_wspace: Option<usize> = None;
+        // Widths are in columns, see utils::char_width.
         let mut line_len = 0;
+        let mut len_before_wspace = 0;
+        let mut line = String::new();
         let mut cur_line = 1;
-        let mut newline_count = 0;
         let mut errors = vec![];
         let mut issue_seeker = BadIssueSeeker::new(config.report_todo, config.report_fixme);
 
@@ -241,37 +494,32 @@ fn fmt_lines(file_map: &mut FileMap, config: &Config) -> FormatReport {
                 // Check for (and record) trailing whitespace.
                 if let Some(lw) = last_wspace {
                     trims.push((cur_line, lw, b));
-                    line_len -= b - lw;
+                    line_len = len_before_wspace;
                 }
                 // Check for any line width errors we couldn't correct.
                 if line_len > config.max_width {
+                    let kind = utils::overflow_kind(&line, config.max_width, config.tab_width);
                     errors.push(FormattingError {
                         line: cur_line,
-                        kind: ErrorKind::LineOverflow
+                        kind: ErrorKind::LineOverflow(config.max_width + 1, kind)
                     });
                 }
                 line_len = 0;
+                line.clear();
                 cur_line += 1;
-                newline_count += 1;
                 last_wspace = None;
             } else {
-                newline_count = 0;
-                line_len += 1;
-                if c.is_whitespace() {
-                    if last_wspace.is_none() {
-                        last_wspace = Some(b);
-                    }
-                } else {
+                if !c.is_whitespace() {
                     last_wspace = None;
+                } else if last_wspace.is_none() {
+                    last_wspace = Some(b);
+                    len_before_wspace = line_len;
                 }
+                line_len += utils::char_width(c, config.tab_width);
+                line.push(c);
             }
         }
 
-        if newline_count > 1 {
-            debug!("track truncate: {} {} {}", f, text.len, newline_count);
-            truncate_todo.push((f.to_owned(), text.len - newline_count + 1))
-        }
-
         for &(l, _, _) in trims.iter() {
             errors.push(FormattingError {
                 line: l,
@@ -282,70 +530,390 @@ fn fmt_lines(file_map: &mut FileMap, config: &Config) -> FormatReport {
         report.file_error_map.insert(f.to_owned(), errors);
     }
 
-    for (f, l) in truncate_todo {
-        file_map.get_mut(&f).unwrap().truncate(l);
+    report
+}
+
+// The result of formatting a crate: the new text of each file and the problems
+// found in it.
+pub type FormatResult = (FileMap, FormatReport);
+
+// Formats a parsed crate.
+fn format_crate(krate: &ast::Crate,
+                codemap: &CodeMap,
+                config: &Config,
+                follow_mods: bool,
+                plugins: &Plugins)
+                -> FormatResult {
+    let mut failures = Vec::new();
+    let mut profile = Profile::new();
+    let mut stats = Stats::new();
+    let mut file_map = fmt_ast(krate,
+                               codemap,
+                               config,
+                               follow_mods,
+                               plugins,
+                               &mut failures,
+                               &mut profile,
+                               &mut stats);
+    filemap::normalize_trailing_newlines(&mut file_map, config);
+    let start = time::precise_time_ns();
+    let mut report = fmt_lines(&file_map, config);
+    if config.profile {
+        profile.add("check lines", time::precise_time_ns() - start);
+    }
+    report.add_rewrite_failures(failures);
+    report.profile.merge(profile);
+    report.stats.merge(stats);
+    if config.newline_style == NewlineStyle::Auto {
+        filemap::match_source_newlines(&mut file_map, codemap);
+    }
+    if config.keep_bom {
+        filemap::restore_boms(&mut file_map);
     }
 
-    report
+    (file_map, report)
+}
+
+// Formats the crate whose root file is given in args, without writing
+// anything. Returns None if the crate could not be parsed.
+pub fn format(args: Vec<String>, config: &Config) -> Option<FormatResult> {
+    format_inner(args, config, None, Plugins::new())
+}
+
+// Formats input as a single file, without writing anything.
+pub fn format_stdin(input: String, config: &Config) -> Option<FormatResult> {
+    format_inner(vec!["rustfmt".to_owned()], config, Some(input), Plugins::new())
 }
 
-struct RustFmtCalls {
-    write_mode: WriteMode,
-    config: Option<Box<config::Config>>,
-}
-
-impl<'a> CompilerCalls<'a> for RustFmtCalls {
-    fn no_input(&mut self,
-                _: &getopts::Matches,
-                _: &rustc_config::Options,
-                _: &Option<PathBuf>,
-                _: &Option<PathBuf>,
-                _: &diagnostics::registry::Registry)
-                -> Option<(Input, Option<PathBuf>)> {
-        panic!("No input supplied to RustFmt");
-    }
-
-    fn build_controller(&mut self, _: &Session) -> driver::CompileController<'a> {
-        let write_mode = self.write_mode;
-
-        let mut config_option = None;
-        swap(&mut self.config, &mut config_option);
-        let config = config_option.unwrap();
-
-        let mut control = driver::CompileController::basic();
-        control.after_parse.stop = Compilation::Stop;
-        control.after_parse.callback = Box::new(move |state| {
-            let krate = state.krate.unwrap();
-            let codemap = state.session.codemap();
-            let mut file_map = fmt_ast(krate, codemap, &*config);
-            // For some reason, the codemap does not include terminating newlines
-            // so we must add one on for each file. This is sad.
-            filemap::append_newlines(&mut file_map);
-            println!("{}", fmt_lines(&mut file_map, &*config));
-
-            let result = filemap::write_all_files(&file_map, write_mode, &*config);
-
-            match result {
-                Err(msg) => println!("Error writing files: {}", msg),
-                Ok(result) => {
-                    if let WriteMode::Return(callback) = write_mode {
-                        callback(result);
-                    }
+// What to format with format_input.
+pub enum Input {
+    // The root file of a crate. Out of line modules are followed unless the
+    // config says to skip children.
+    File(PathBuf),
+    // The text of a single file.
+    Text(String),
+}
+
+#[derive(Debug)]
+pub enum Error {
+    // The input file could not be read.
+    Io(io::Error),
+    // The input could not be parsed.
+    Parse,
+    // Rustfmt crashed.
+    Internal,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Error::Io(ref e) => write!(fmt, "could not read input: {}", e),
+            Error::Parse => write!(fmt, "could not parse input"),
+            Error::Internal => write!(fmt, "internal error in rustfmt"),
+        }
+    }
+}
+
+// Formats input, without writing anything. The summary counts the files
+// and lines which formatting would change, compared to the files on disk or
+// the text given.
+pub fn format_input(input: Input,
+                    config: &Config)
+                    -> Result<(Summary, FileMap, FormatReport), Error> {
+    format_input_shared(input, Arc::new(config.clone()), Plugins::new())
+}
+
+// As format_input, calling hook to approve each rewrite.
+pub fn format_input_with_hook(input: Input,
+                              config: &Config,
+                              hook: RewriteHook)
+                              -> Result<(Summary, FileMap, FormatReport), Error> {
+    format_input_shared(input, Arc::new(config.clone()), Plugins::with_hook(hook))
+}
+
+// As format_input, with plugins' hook and passes.
+pub fn format_input_with_plugins(input: Input,
+                                 config: &Config,
+                                 plugins: Plugins)
+                                 -> Result<(Summary, FileMap, FormatReport), Error> {
+    format_input_shared(input, Arc::new(config.clone()), plugins)
+}
+
+// Formatting happens on its own thread, which shares config rather than
+// copying it.
+fn format_input_shared(input: Input,
+                       config: Arc<Config>,
+                       plugins: Plugins)
+                       -> Result<(Summary, FileMap, FormatReport), Error> {
+    let (file_map, report, original) = try!(format_only(input, config.clone(), plugins));
+
+    let mut summary = Summary::new();
+    let file_map = if config.verify_ast {
+        verify_ast(&file_map, original.as_ref().map(|s| &**s), &mut summary)
+    } else {
+        file_map
+    };
+    for (filename, text) in &file_map {
+        let lines_changed = match original {
+            Some(ref original) => filemap::lines_changed_from(original, text, &config),
+            None => filemap::lines_changed(filename, text, &config),
+        };
+        summary.add_file(lines_changed);
+        if lines_changed > 0 {
+            summary.add_diff();
+        }
+    }
+    summary.add_rewrite_failures(report.rewrite_failures().len());
+
+    Ok((summary, file_map, report))
+}
+
+// Returns true if formatting input would change nothing. Stops at the first
+// file which would change, without working out how. Input which can't be
+// formatted is not formatted.
+pub fn is_formatted(input: Input, config: &Config) -> bool {
+    let (file_map, _, original) = match format_only(input,
+                                                    Arc::new(config.clone()),
+                                                    Plugins::new()) {
+        Ok(result) => result,
+        Err(_) => return false,
+    };
+    let original = original.as_ref().map(|s| &**s);
+
+    file_map.iter().all(|(filename, text)| filemap::is_unchanged(filename, text, original, config))
+}
+
+// Formats input, also returning the original text if it was given rather than
+// read from a file.
+fn format_only(input: Input,
+               config: Arc<Config>,
+               plugins: Plugins)
+               -> Result<(FileMap, FormatReport, Option<String>), Error> {
+    let keep_bom = config.keep_bom;
+    let (result, original) = match input {
+        Input::File(path) => {
+            try!(fs::metadata(&path).map_err(Error::Io));
+            let file = path.to_string_lossy().into_owned();
+            let args = vec!["rustfmt".to_owned(), file];
+            (parallel::format_guarded(move || format_inner(args, &config, None, plugins)),
+             None)
+        }
+        Input::Text(text) => {
+            let original = text.clone();
+            let args = vec!["rustfmt".to_owned()];
+            (parallel::format_guarded(move || {
+                format_inner(args, &config, Some(text), plugins)
+            }),
+             Some(original))
+        }
+    };
+
+    let (mut file_map, report) = match result {
+        Ok(result) => result,
+        Err(ref summary) if summary.has_parsing_errors() => return Err(Error::Parse),
+        Err(_) => return Err(Error::Internal),
+    };
+    // There is no file to look at for a byte order mark.
+    if keep_bom && original.as_ref().map_or(false, |text| text.starts_with(filemap::BOM)) {
+        for (_, text) in file_map.iter_mut() {
+            filemap::restore_bom(text);
+        }
+    }
+
+    Ok((file_map, report, original))
+}
+
+// Reports warnings and writes out the result of formatting, according to
+// write_mode.
+pub fn write_result(file_map: &FileMap,
+                    report: &FormatReport,
+                    write_mode: WriteMode,
+                    config: &Config)
+                    -> Summary {
+    write_result_from(file_map, report, None, write_mode, config)
+}
+
+// As write_result, for formatted code whose original is given, rather than
+// on disk.
+fn write_result_from(file_map: &FileMap,
+                     report: &FormatReport,
+                     original: Option<&str>,
+                     write_mode: WriteMode,
+                     config: &Config)
+                     -> Summary {
+    let mut summary = Summary::new();
+    // Count changes before the files are overwritten.
+    for (filename, text) in file_map {
+        summary.add_file(filemap::lines_changed(filename, text, config));
+    }
+    summary.add_rewrite_failures(report.rewrite_failures().len());
+
+    if config.verbosity != Verbosity::Quiet && report.warning_count() > 0 {
+        if write_mode.is_machine_output() {
+            // Keep stdout clean for the formatted output.
+            let _ = write!(io::stderr(), "{}", report);
+        } else {
+            print!("{}", report);
+        }
+    }
+    if config.verbosity == Verbosity::Verbose {
+        for failure in report.rewrite_failures() {
+            let _ = writeln!(io::stderr(), "Left unformatted: {}", failure);
+        }
+    }
+    if config.check_idempotence {
+        let mut filenames: Vec<_> = file_map.keys().collect();
+        filenames.sort();
+        for filename in filenames {
+            if !check_idempotent(filename, &file_map[filename], config) {
+                summary.add_internal_error();
+            }
+        }
+    }
+    if config.failure_report.len() > 0 {
+        if let Err(msg) = filemap::write_failure_report(report, &config.failure_report) {
+            let _ = writeln!(io::stderr(), "Error writing failure report: {}", msg);
+            summary.add_operational_error();
+        }
+    }
+
+    let verified;
+    let file_map = if config.verify_ast {
+        verified = verify_ast(file_map, original, &mut summary);
+        &verified
+    } else {
+        file_map
+    };
+
+    let start = time::precise_time_ns();
+    match filemap::write_all_files(file_map, write_mode, config, &mut summary) {
+        Err(msg) => {
+            let _ = writeln!(io::stderr(), "Error writing files: {}", msg);
+            summary.add_operational_error();
+        }
+        Ok(result) => {
+            if let WriteMode::Return(callback) = write_mode {
+                callback(result);
+            }
+        }
+    }
+    if config.profile {
+        let mut profile = report.profile().clone();
+        profile.add("write", time::precise_time_ns() - start);
+        let _ = write!(io::stderr(), "Time spent, rewrites include their children:\n{}", profile);
+    }
+    if config.stats {
+        let mut stats = report.stats().clone();
+        for failure in report.rewrite_failures() {
+            stats.add_failure(&failure.reason);
+        }
+        let _ = write!(io::stderr(), "Code rewritten by kind:\n{}", stats);
+    }
+
+    summary
+}
+
+// The files in file_map whose formatted code means the same as original, or
+// the code on disk if there is no original. Any others are reported as a bug
+// in rustfmt and left out, so that they won't be written. Files with nothing
+// to compare with are kept, saying that they weren't verified.
+fn verify_ast(file_map: &FileMap, original: Option<&str>, summary: &mut Summary) -> FileMap {
+    let mut result = FileMap::new();
+    for (filename, text) in file_map {
+        let original = match original {
+            Some(original) => Some(original.to_owned()),
+            None => filemap::read_original(filename).ok(),
+        };
+        match original {
+            Some(original) => {
+                let error = match equivalence::same_ast(filename, original, text.to_string()) {
+                    Ok(true) => None,
+                    Ok(false) => Some("formatting changed the meaning of the code".to_owned()),
+                    Err(msg) => Some(msg),
+                };
+                if let Some(msg) = error {
+                    let _ = writeln!(io::stderr(),
+                                     "Not writing {}: {}, this is a bug",
+                                     filename,
+                                     msg);
+                    summary.add_internal_error();
+                    continue;
                 }
             }
-        });
+            None => {
+                let _ = writeln!(io::stderr(),
+                                 "Not verifying {}: there is no original to compare with",
+                                 filename);
+            }
+        }
+
+        let mut buffer = StringBuffer::new();
+        buffer.push_str(&text.to_string());
+        result.insert(filename.clone(), buffer);
+    }
+    result
+}
 
-        control
+// Formats the formatted text of filename again. If that changes anything,
+// rustfmt has a bug, so prints the changes to stderr and returns false.
+fn check_idempotent(filename: &str, text: &StringBuffer, config: &Config) -> bool {
+    let first = text.to_string();
+    if markdown::is_markdown(filename) {
+        let second = markdown::format_markdown(&first, config);
+        return report_idempotence(filename, &first, &second);
     }
+    let second = match format_only(Input::Text(first.clone()),
+                                   Arc::new(config.clone()),
+                                   Plugins::new()) {
+        Ok((file_map, _, _)) => file_map.values().next().map_or(String::new(), |t| t.to_string()),
+        Err(e) => {
+            let _ = writeln!(io::stderr(), "Error formatting {} a second time: {}", filename, e);
+            return false;
+        }
+    };
+    report_idempotence(filename, &first, &second)
+}
+
+// True if second, the result of formatting first again, is the same as first.
+// Otherwise reports the differences.
+fn report_idempotence(filename: &str, first: &str, second: &str) -> bool {
+    let mismatches = rustfmt_diff::make_diff(first, second);
+    if mismatches.is_empty() {
+        return true;
+    }
+    let _ = writeln!(io::stderr(),
+                     "Formatting {} is not idempotent, formatting it again changes:",
+                     filename);
+    let _ = rustfmt_diff::print_diff(io::stderr(), filename, &mismatches, false);
+    false
 }
 
 // args are the arguments passed on the command line, generally passed through
 // to the compiler.
 // write_mode determines what happens to the result of running rustfmt, see
 // WriteMode.
-// default_config is a string of toml data to be used to configure rustfmt.
-pub fn run(args: Vec<String>, write_mode: WriteMode, default_config: &str) {
-    let config = Some(Box::new(config::Config::from_toml(default_config)));
-    let mut call_ctxt = RustFmtCalls { write_mode: write_mode, config: config };
-    rustc_driver::run_compiler(&args, &mut call_ctxt);
+// config is the configuration to format with, see Config::from_toml.
+pub fn run(args: Vec<String>, write_mode: WriteMode, config: &Config) -> Summary {
+    match format(args, config) {
+        Some((file_map, report)) => write_result(&file_map, &report, write_mode, config),
+        None => {
+            let mut summary = Summary::new();
+            summary.add_parsing_error();
+            summary
+        }
+    }
+}
+
+// Formats `input` as if it were the contents of a single file. Out of line
+// modules are not followed. The result is handled according to write_mode,
+// generally WriteMode::Plain to send it to stdout.
+pub fn run_from_stdin(input: String, write_mode: WriteMode, config: &Config) -> Summary {
+    let stdin_config = config.clone();
+    let original = input.clone();
+    match parallel::format_guarded(move || format_stdin(input, &stdin_config)) {
+        Ok((file_map, report)) => {
+            write_result_from(&file_map, &report, Some(&original), write_mode, config)
+        }
+        Err(summary) => summary,
+    }
 }
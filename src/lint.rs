@@ -0,0 +1,103 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Warnings for unformatted code, in the style of the compiler's, for builds
+// which should point out formatting problems rather than fail on them. Used
+// by the rustfmt-lint driver.
+
+use std::fmt;
+
+use {Input, Error, format_input};
+use config::Config;
+use filemap;
+use rustfmt_diff::make_diff;
+
+// A run of lines which rustfmt would change. Lines are numbered from 1.
+pub struct LintWarning {
+    pub file: String,
+    pub line: usize,
+    // The first line which differs, and what rustfmt would write instead,
+    // None if it would remove the line.
+    pub original: String,
+    pub expected: Option<String>,
+}
+
+impl fmt::Display for LintWarning {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let position = format!("{}:{}:1: {}:{}",
+                               self.file,
+                               self.line,
+                               self.line,
+                               self.original.chars().count() + 1);
+        try!(write!(fmt, "{} warning: code is not formatted\n", position));
+        match self.expected {
+            Some(ref expected) => {
+                write!(fmt, "{} note: rustfmt would write `{}`", position, expected)
+            }
+            None => write!(fmt, "{} note: rustfmt would remove this line", position),
+        }
+    }
+}
+
+// Formats input and warns about each part of it which formatting would
+// change. Nothing is written.
+pub fn lint(input: Input, config: &Config) -> Result<Vec<LintWarning>, Error> {
+    let text = match input {
+        Input::Text(ref text) => Some(text.clone()),
+        Input::File(_) => None,
+    };
+    let (_, file_map, _) = try!(format_input(input, config));
+
+    let mut warnings = Vec::new();
+    filemap::for_each_file(&file_map, config, |filename, formatted| {
+        let original = match text {
+            Some(ref text) => text.clone(),
+            // The file was read to format it, so this should not fail.
+            None => filemap::read_original(filename).unwrap_or(String::new()),
+        };
+        warnings.extend(lint_text(filename, &original, formatted));
+    });
+    Ok(warnings)
+}
+
+// The warnings for the changes from original to formatted.
+pub fn lint_text(filename: &str, original: &str, formatted: &str) -> Vec<LintWarning> {
+    let original_lines: Vec<&str> = original.lines().collect();
+    make_diff(original, formatted)
+        .into_iter()
+        .map(|mismatch| {
+            let line = mismatch.original_start_line;
+            let original = match mismatch.original_lines.first() {
+                Some(first) => first.clone(),
+                // Lines are only added, point at the line they go before.
+                None => original_lines.get(line - 1).map_or(String::new(), |l| (*l).to_owned()),
+            };
+            LintWarning {
+                file: filename.to_owned(),
+                line: line,
+                original: original,
+                expected: mismatch.expected_lines.first().cloned(),
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn lint_text_test() {
+    let original = "fn main() {\n    foo(1,2);\n    bar();\n\n\n}\n";
+    let formatted = "fn main() {\n    foo(1, 2);\n    bar();\n}\n";
+    let warnings = lint_text("a.rs", original, formatted);
+    assert_eq!(warnings.len(), 2);
+    assert_eq!(warnings[0].to_string(),
+               "a.rs:2:1: 2:14 warning: code is not formatted\na.rs:2:1: 2:14 note: rustfmt would \
+                write `    foo(1, 2);`");
+    assert_eq!(warnings[1].line, 4);
+    assert_eq!(warnings[1].expected, None);
+}
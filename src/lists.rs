@@ -12,7 +12,9 @@ use std::cmp;
 
 use syntax::codemap::{self, CodeMap, BytePos};
 
-use utils::{round_up_to_power_of_two, make_indent};
+use budget::saturating_reserve;
+use config::Config;
+use utils::{borrowed_snippet, round_up_to_power_of_two, make_indent, str_width};
 use comment::{FindUncommented, rewrite_comment, find_comment_end};
 
 #[derive(Eq, PartialEq, Debug, Copy, Clone)]
@@ -36,11 +38,25 @@ pub enum SeparatorTactic {
 
 impl_enum_decodable!(SeparatorTactic, Always, Never, Vertical);
 
+// Where separators go in vertical layouts. Horizontal layouts always put them
+// after the items.
+#[derive(Eq, PartialEq, Debug, Copy, Clone)]
+pub enum SeparatorPlace {
+    // At the end of the line, after the item.
+    Back,
+    // At the start of the following line, before the next item, which stays
+    // lined up with the first.
+    Front,
+}
+
+impl_enum_decodable!(SeparatorPlace, Back, Front);
+
 // TODO having some helpful ctors for ListFormatting would be nice.
 pub struct ListFormatting<'a> {
     pub tactic: ListTactic,
     pub separator: &'a str,
     pub trailing_separator: SeparatorTactic,
+    pub separator_place: SeparatorPlace,
     pub indent: usize,
     // Available width if we layout horizontally.
     pub h_width: usize,
@@ -49,6 +65,7 @@ pub struct ListFormatting<'a> {
     // Non-expressions, e.g. items, will have a new line at the end of the list.
     // Important for comment styles.
     pub ends_with_newline: bool,
+    pub config: &'a Config,
 }
 
 pub struct ListItem {
@@ -56,6 +73,9 @@ pub struct ListItem {
     // Item should include attributes and doc comments
     pub item: String,
     pub post_comment: Option<String>,
+    // Blank lines between this item and the previous one in the source. In
+    // vertical layouts we keep (at most) one of them to preserve grouping.
+    pub blank_lines_before: usize,
 }
 
 impl ListItem {
@@ -69,34 +89,31 @@ impl ListItem {
     }
 
     pub fn from_str<S: Into<String>>(s: S) -> ListItem {
-        ListItem { pre_comment: None, item: s.into(), post_comment: None }
+        ListItem { pre_comment: None, item: s.into(), post_comment: None, blank_lines_before: 0 }
     }
 }
 
-// Format a list of commented items into a string.
-// FIXME: this has grown into a monstrosity
-// TODO: add unit tests
-pub fn write_list<'b>(items: &[ListItem], formatting: &ListFormatting<'b>) -> String {
-    if items.len() == 0 {
-        return String::new();
-    }
+// Decides how a list will be laid out, from the widths of its items alone.
+// The result is never HorizontalVertical, and only Mixed if the items don't
+// fit on one line. Callers which need to know the layout to format around the
+// list can use this rather than inspecting the output of write_list.
+pub fn definitive_tactic<'b>(items: &[ListItem], formatting: &ListFormatting<'b>) -> ListTactic {
+    let widths = item_widths(items, formatting.config.tab_width);
+    choose_tactic(items, &widths, formatting)
+}
 
+fn choose_tactic<'b>(items: &[ListItem],
+                     widths: &[usize],
+                     formatting: &ListFormatting<'b>)
+                     -> ListTactic {
     let mut tactic = formatting.tactic;
-
-    // Conservatively overestimates because of the changing separator tactic.
-    let sep_count = if formatting.trailing_separator != SeparatorTactic::Never {
-        items.len()
-    } else {
-        items.len() - 1
-    };
-    let sep_len = formatting.separator.len();
-    let total_sep_len = (sep_len + 1) * sep_count;
-    let total_width = calculate_width(items);
+    let total_width = widths.iter().fold(0, |a, w| a + w);
+    let total_sep_len = total_sep_len(items, formatting);
     let fits_single = total_width + total_sep_len <= formatting.h_width;
 
     // Check if we need to fallback from horizontal listing, if possible.
     if tactic == ListTactic::HorizontalVertical {
-        debug!("write_list: total_width: {}, total_sep_len: {}, h_width: {}",
+        debug!("choose_tactic: total_width: {}, total_sep_len: {}, h_width: {}",
                total_width, total_sep_len, formatting.h_width);
         tactic = if fits_single &&
                     !items.iter().any(ListItem::is_multiline) {
@@ -106,6 +123,13 @@ pub fn write_list<'b>(items: &[ListItem], formatting: &ListFormatting<'b>) -> St
         };
     }
 
+    // Items spanning several lines would leave the items after them on their
+    // last line, out of line with the rest. The continuation lines of the
+    // items are already indented for a vertical layout.
+    if tactic != ListTactic::Vertical && items.iter().any(|item| item.item.contains('\n')) {
+        tactic = ListTactic::Vertical;
+    }
+
     // Check if we can fit everything on a single line in mixed mode.
     // The horizontal tactic does not break after v_width columns.
     if tactic == ListTactic::Mixed && fits_single {
@@ -117,9 +141,33 @@ pub fn write_list<'b>(items: &[ListItem], formatting: &ListFormatting<'b>) -> St
         tactic = ListTactic::Vertical;
     }
 
+    tactic
+}
+
+// Format a list of commented items into a string.
+// FIXME: this has grown into a monstrosity
+pub fn write_list<'b>(items: &[ListItem], formatting: &ListFormatting<'b>) -> String {
+    if items.len() == 0 {
+        return String::new();
+    }
+
+    // Item widths are computed once, both for picking the layout and for
+    // filling lines in mixed mode.
+    let widths = item_widths(items, formatting.config.tab_width);
+    let tactic = choose_tactic(items, &widths, formatting);
+    let total_width = widths.iter().fold(0, |a, w| a + w);
+    let total_sep_len = total_sep_len(items, formatting);
+    // The separator as it follows an item, in the default place.
+    let sep_str = format!("{}{}", separator_spacing(formatting.separator), formatting.separator);
+    let sep_len = sep_str.len();
+
+    let sep_front = tactic == ListTactic::Vertical &&
+                    formatting.separator_place == SeparatorPlace::Front;
+
     // Now that we know how we will layout, we can decide for sure if there
     // will be a trailing separator.
-    let trailing_separator = needs_trailing_separator(formatting.trailing_separator, tactic);
+    let trailing_separator = !sep_front &&
+                             needs_trailing_separator(formatting.trailing_separator, tactic);
 
     // Create a buffer for the result.
     // TODO could use a StringBuffer or rope for this
@@ -141,7 +189,7 @@ pub fn write_list<'b>(items: &[ListItem], formatting: &ListFormatting<'b>) -> St
         } else {
             0
         };
-        let item_width = item.item.len() + item_sep_len;
+        let item_width = str_width(&item.item, formatting.config.tab_width) + item_sep_len;
 
         match tactic {
             ListTactic::Horizontal if !first => {
@@ -149,10 +197,23 @@ pub fn write_list<'b>(items: &[ListItem], formatting: &ListFormatting<'b>) -> St
             }
             ListTactic::Vertical if !first => {
                 result.push('\n');
-                result.push_str(indent_str);
+                if item.blank_lines_before > 0 {
+                    result.push('\n');
+                }
+                if sep_front {
+                    // The separator and the space after it go before the item.
+                    let sep_indent = saturating_reserve(saturating_reserve(formatting.indent,
+                                                                           formatting.separator),
+                                                        " ");
+                    result.push_str(&make_indent(sep_indent));
+                    result.push_str(formatting.separator);
+                    result.push(' ');
+                } else {
+                    result.push_str(indent_str);
+                }
             }
             ListTactic::Mixed => {
-                let total_width = total_item_width(item) + item_sep_len;
+                let total_width = widths[i] + item_sep_len;
 
                 if line_len > 0 && line_len + total_width > formatting.v_width {
                     result.push('\n');
@@ -178,7 +239,8 @@ pub fn write_list<'b>(items: &[ListItem], formatting: &ListFormatting<'b>) -> St
                                              // Width restriction is only
                                              // relevant in vertical mode.
                                              formatting.v_width,
-                                             formatting.indent));
+                                             formatting.indent,
+                                             formatting.config.tab_width));
 
             if tactic == ListTactic::Vertical {
                 result.push('\n');
@@ -195,26 +257,32 @@ pub fn write_list<'b>(items: &[ListItem], formatting: &ListFormatting<'b>) -> St
             let formatted_comment = rewrite_comment(item.post_comment.as_ref().unwrap(),
                                                     true,
                                                     formatting.v_width,
-                                                    0);
+                                                    0,
+                                                    formatting.config.tab_width);
 
             result.push(' ');
             result.push_str(&formatted_comment);
         }
 
-        if separate {
-            result.push_str(formatting.separator);
+        if separate && !sep_front {
+            result.push_str(&sep_str);
         }
 
         if tactic == ListTactic::Vertical && item.post_comment.is_some() {
-            // 1 = space between item and comment.
-            let width = formatting.v_width.checked_sub(item_width + 1).unwrap_or(1);
-            let offset = formatting.indent + item_width + 1;
+            // The comment goes after a space.
+            let width = formatting.v_width.checked_sub(item_width + " ".len()).unwrap_or(1);
+            let offset = formatting.indent + item_width + " ".len();
             let comment = item.post_comment.as_ref().unwrap();
             // Use block-style only for the last item or multiline comments.
             let block_style = !formatting.ends_with_newline && last ||
-                              comment.trim().contains('\n') || comment.trim().len() > width;
+                              comment.trim().contains('\n') ||
+                              str_width(comment.trim(), formatting.config.tab_width) > width;
 
-            let formatted_comment = rewrite_comment(comment, block_style, width, offset);
+            let formatted_comment = rewrite_comment(comment,
+                                                    block_style,
+                                                    width,
+                                                    offset,
+                                                    formatting.config.tab_width);
 
             result.push(' ');
             result.push_str(&formatted_comment);
@@ -248,13 +316,19 @@ pub fn itemize_list<T, I, F1, F2, F3>(codemap: &CodeMap,
 
     let mut new_it = it.peekable();
     let white_space: &[_] = &[' ', '\t'];
+    let mut prev_item_hi = None;
 
     while let Some(item) = new_it.next() {
+        let blank_lines_before = prev_item_hi.map_or(0, |hi| {
+            count_blank_lines(codemap, codemap::mk_sp(hi, get_lo(&item)))
+        });
+        prev_item_hi = Some(get_hi(&item));
+
         // Pre-comment
-        let pre_snippet = codemap.span_to_snippet(codemap::mk_sp(prev_span_end,
-                                                                get_lo(&item)))
-                                 .unwrap();
-        let pre_snippet = pre_snippet.trim();
+        // Spans from macro expansions may overlap or be out of order, in
+        // which case there is no source between items to take comments from.
+        let pre_snippet = borrowed_snippet(codemap, codemap::mk_sp(prev_span_end, get_lo(&item)));
+        let pre_snippet = pre_snippet.as_ref().map_or("", |s| s.trim());
         let pre_comment = if pre_snippet.len() > 0 {
             Some(pre_snippet.to_owned())
         } else {
@@ -264,17 +338,15 @@ pub fn itemize_list<T, I, F1, F2, F3>(codemap: &CodeMap,
         // Post-comment
         let next_start = match new_it.peek() {
             Some(ref next_item) => get_lo(next_item),
-            None => next_span_start
+            None => next_span_start,
         };
-        let post_snippet = codemap.span_to_snippet(codemap::mk_sp(get_hi(&item),
-                                                                  next_start))
-                                  .unwrap();
+        let post_snippet = borrowed_snippet(codemap, codemap::mk_sp(get_hi(&item), next_start));
+        let post_snippet: &str = post_snippet.as_ref().map_or("", |s| &**s);
 
-        let comment_end = match new_it.peek() {
-            Some(..) => {
+        let comment_end = match (new_it.peek(), post_snippet.find_uncommented(separator)) {
+            (Some(..), Some(separator_index)) => {
                 let block_open_index = post_snippet.find("/*");
                 let newline_index = post_snippet.find('\n');
-                let separator_index = post_snippet.find_uncommented(separator).unwrap();
 
                 match (block_open_index, newline_index) {
                     // Separator before comment, with the next item on same line.
@@ -292,10 +364,12 @@ pub fn itemize_list<T, I, F1, F2, F3>(codemap: &CodeMap,
                     }
                     // Potential *single* line comment.
                     (_, Some(j)) => { j + 1 }
-                    _ => post_snippet.len()
+                    _ => post_snippet.len(),
                 }
-            },
-            None => {
+            }
+            // No separator between the items, so they can't be from the source.
+            (Some(..), None) => 0,
+            (None, _) => {
                 post_snippet.find_uncommented(terminator)
                             .unwrap_or(post_snippet.len())
             }
@@ -320,13 +394,29 @@ pub fn itemize_list<T, I, F1, F2, F3>(codemap: &CodeMap,
                 Some(post_snippet.to_owned())
             } else {
                 None
-            }
+            },
+            blank_lines_before: blank_lines_before,
         });
     }
 
     result
 }
 
+// The number of lines in span consisting only of whitespace. The first and
+// last lines are shared with the items either side, so they never count.
+pub fn count_blank_lines(codemap: &CodeMap, span: codemap::Span) -> usize {
+    match borrowed_snippet(codemap, span) {
+        Some(snippet) => {
+            let lines: Vec<_> = snippet.split('\n').collect();
+            if lines.len() < 3 {
+                return 0;
+            }
+            lines[1..lines.len() - 1].iter().filter(|line| line.trim().is_empty()).count()
+        }
+        None => 0,
+    }
+}
+
 fn needs_trailing_separator(separator_tactic: SeparatorTactic, list_tactic: ListTactic) -> bool {
     match separator_tactic {
         SeparatorTactic::Always => true,
@@ -335,25 +425,143 @@ fn needs_trailing_separator(separator_tactic: SeparatorTactic, list_tactic: List
     }
 }
 
-fn calculate_width(items: &[ListItem]) -> usize {
-    items.iter().map(total_item_width).fold(0, |a, l| a + l)
+// Widths are in columns, see utils::str_width.
+fn item_widths(items: &[ListItem], tab_width: usize) -> Vec<usize> {
+    items.iter().map(|item| total_item_width(item, tab_width)).collect()
+}
+
+// Conservatively overestimates because of the changing separator tactic.
+fn total_sep_len<'b>(items: &[ListItem], formatting: &ListFormatting<'b>) -> usize {
+    let sep_count = if formatting.trailing_separator != SeparatorTactic::Never {
+        items.len()
+    } else {
+        items.len().checked_sub(1).unwrap_or(0)
+    };
+    (separator_spacing(formatting.separator).len() + formatting.separator.len() + 1) * sep_count
+}
+
+// Separators which are binary operators, like the `+` between bounds, get a
+// space either side of them. The others, e.g., `,` and `;`, only get one
+// after them.
+fn separator_spacing(separator: &str) -> &'static str {
+    if separator == "+" {
+        " "
+    } else {
+        ""
+    }
 }
 
-fn total_item_width(item: &ListItem) -> usize {
-    comment_len(&item.pre_comment) + comment_len(&item.post_comment) + item.item.len()
+fn total_item_width(item: &ListItem, tab_width: usize) -> usize {
+    comment_len(&item.pre_comment, tab_width) + comment_len(&item.post_comment, tab_width) +
+    str_width(&item.item, tab_width)
 }
 
-fn comment_len(comment: &Option<String>) -> usize {
+fn comment_len(comment: &Option<String>, tab_width: usize) -> usize {
     match comment {
         &Some(ref s) => {
-            let text_len = s.trim().len();
+            let text_len = str_width(s.trim(), tab_width);
             if text_len > 0 {
                 // We'll put " /*" before and " */" after inline comments.
                 text_len + 6
             } else {
                 text_len
             }
-        },
-        &None => 0
+        }
+        &None => 0,
     }
 }
+
+#[cfg(test)]
+fn commented_items() -> Vec<ListItem> {
+    let mut first = ListItem::from_str("a");
+    first.pre_comment = Some("/* first */".to_owned());
+    let mut second = ListItem::from_str("b");
+    second.post_comment = Some("// second".to_owned());
+    vec![first, second]
+}
+
+#[test]
+fn write_list_comments_test() {
+    let config = Config::from_toml(include_str!("default.toml"));
+    let mut fmt = ListFormatting {
+        tactic: ListTactic::Horizontal,
+        separator: ",",
+        trailing_separator: SeparatorTactic::Never,
+        separator_place: SeparatorPlace::Back,
+        indent: 4,
+        h_width: 100,
+        v_width: 100,
+        ends_with_newline: false,
+        config: &config,
+    };
+    assert_eq!(write_list(&commented_items(), &fmt), "/* first */ a, b /* second */");
+
+    // Line comments are only used where nothing follows them on the line.
+    fmt.tactic = ListTactic::Vertical;
+    assert_eq!(write_list(&commented_items(), &fmt), "// first\n    a,\n    b /* second */");
+    fmt.ends_with_newline = true;
+    assert_eq!(write_list(&commented_items(), &fmt), "// first\n    a,\n    b // second");
+}
+
+#[test]
+fn definitive_tactic_test() {
+    let config = Config::from_toml(include_str!("default.toml"));
+    let items: Vec<_> = vec!["aaa", "bbb", "ccc"].into_iter().map(ListItem::from_str).collect();
+    let mut fmt = ListFormatting {
+        tactic: ListTactic::HorizontalVertical,
+        separator: ",",
+        trailing_separator: SeparatorTactic::Never,
+        separator_place: SeparatorPlace::Back,
+        indent: 0,
+        h_width: 13,
+        v_width: 13,
+        ends_with_newline: false,
+        config: &config,
+    };
+    // "aaa, bbb, ccc"
+    assert_eq!(definitive_tactic(&items, &fmt), ListTactic::Horizontal);
+    fmt.h_width = 12;
+    assert_eq!(definitive_tactic(&items, &fmt), ListTactic::Vertical);
+    fmt.tactic = ListTactic::Mixed;
+    assert_eq!(definitive_tactic(&items, &fmt), ListTactic::Mixed);
+}
+
+#[test]
+fn separator_place_test() {
+    let config = Config::from_toml(include_str!("default.toml"));
+    let items: Vec<_> = vec!["T: Clone", "U: Eq"].into_iter().map(ListItem::from_str).collect();
+    let mut fmt = ListFormatting {
+        tactic: ListTactic::Vertical,
+        separator: ",",
+        trailing_separator: SeparatorTactic::Always,
+        separator_place: SeparatorPlace::Front,
+        indent: 10,
+        h_width: 80,
+        v_width: 80,
+        ends_with_newline: false,
+        config: &config,
+    };
+    assert_eq!(write_list(&items, &fmt), "T: Clone\n        , U: Eq");
+    fmt.tactic = ListTactic::Horizontal;
+    assert_eq!(write_list(&items, &fmt), "T: Clone, U: Eq,");
+}
+
+#[test]
+fn operator_separator_test() {
+    let config = Config::from_toml(include_str!("default.toml"));
+    let items: Vec<_> = vec!["Clone", "Eq"].into_iter().map(ListItem::from_str).collect();
+    let mut fmt = ListFormatting {
+        tactic: ListTactic::HorizontalVertical,
+        separator: "+",
+        trailing_separator: SeparatorTactic::Never,
+        separator_place: SeparatorPlace::Back,
+        indent: 4,
+        h_width: 10,
+        v_width: 10,
+        ends_with_newline: false,
+        config: &config,
+    };
+    assert_eq!(write_list(&items, &fmt), "Clone + Eq");
+    fmt.h_width = 9;
+    assert_eq!(write_list(&items, &fmt), "Clone +\n    Eq");
+}
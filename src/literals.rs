@@ -0,0 +1,212 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Handling the source text of literals. Numbers may be normalized, which is
+// opt in. The text of string literals is only ever split between escape
+// sequences, char and byte literals and byte and raw strings are never
+// changed at all, see expr::rewrite_expr.
+
+use std::cmp;
+
+use config::{Config, HexLiteralCase, LiteralSuffixStyle};
+
+const INT_SUFFIXES: &'static [&'static str] = &["usize", "isize", "u8", "u16", "u32", "u64", "i8",
+                                                "i16", "i32", "i64"];
+const FLOAT_SUFFIXES: &'static [&'static str] = &["f32", "f64"];
+
+// Rewrites the source text of an integer or float literal according to the
+// literal options of config.
+pub fn normalize_number(text: &str, config: &Config) -> String {
+    let has_prefix = text.starts_with("0x") || text.starts_with("0o") || text.starts_with("0b");
+    let (prefix, rest) = if has_prefix {
+        text.split_at(2)
+    } else {
+        ("", text)
+    };
+    let is_decimal = prefix.is_empty();
+    let (digits, separator, suffix) = split_suffix(rest, is_decimal);
+
+    let mut digits = digits.to_owned();
+    if prefix == "0x" {
+        match config.hex_literal_case {
+            HexLiteralCase::Preserve => {}
+            HexLiteralCase::Upper => digits = digits.to_uppercase(),
+            HexLiteralCase::Lower => digits = digits.to_lowercase(),
+        }
+    }
+    if is_decimal && config.lowercase_float_exponent {
+        digits = digits.replace("E", "e");
+    }
+    if is_decimal && config.group_decimal_digits && !digits.contains('_') {
+        let int_len = digits.find(|c: char| c == '.' || c == 'e' || c == 'E')
+                            .unwrap_or(digits.len());
+        if int_len > 4 {
+            digits = format!("{}{}", group_digits(&digits[..int_len]), &digits[int_len..]);
+        }
+    }
+
+    let separator = match config.literal_suffix_style {
+        _ if suffix.is_empty() => separator,
+        LiteralSuffixStyle::Preserve => separator,
+        LiteralSuffixStyle::Separated => "_",
+        LiteralSuffixStyle::Joined => "",
+    };
+    format!("{}{}{}{}", prefix, digits, separator, suffix)
+}
+
+// The text of a string literal, as written, between its quotes, with any line
+// continuations (a backslash, a newline and the whitespace after it) removed
+// since they add nothing to its value. None if snippet isn't a cooked string.
+pub fn string_lit_body(snippet: &str) -> Option<String> {
+    if snippet.len() < 2 || !snippet.starts_with('"') || !snippet.ends_with('"') {
+        return None;
+    }
+    let body = &snippet[1..snippet.len() - 1];
+
+    let mut result = String::with_capacity(body.len());
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\n') => {
+                while chars.peek().map_or(false, |c| c.is_whitespace()) {
+                    chars.next();
+                }
+            }
+            Some(escaped) => {
+                result.push(c);
+                result.push(escaped);
+            }
+            None => result.push(c),
+        }
+    }
+    Some(result)
+}
+
+// The escape sequence in s, the text of a string literal, which byte i falls
+// strictly inside of, if any, as its start and end. Splitting the text at i
+// would corrupt the literal.
+pub fn escape_around(s: &str, i: usize) -> Option<(usize, usize)> {
+    let mut pos = 0;
+    let mut chars = s.char_indices();
+    while let Some((start, c)) = chars.next() {
+        if start < pos {
+            continue;
+        }
+        if start >= i {
+            return None;
+        }
+        pos = start + c.len_utf8();
+        if c == '\\' {
+            pos = escape_end(s, start);
+            if i < pos {
+                return Some((start, pos));
+            }
+        }
+    }
+    None
+}
+
+// The end of the escape sequence starting with the backslash at start.
+fn escape_end(s: &str, start: usize) -> usize {
+    let rest = &s[start + 1..];
+    let len = match rest.chars().next() {
+        Some('x') => 3,
+        Some('u') => rest.find('}').map_or(rest.len(), |end| end + 1),
+        Some(c) => c.len_utf8(),
+        None => 0,
+    };
+    cmp::min(start + 1 + len, s.len())
+}
+
+// Splits a literal, without its radix prefix, into its digits, the
+// underscores between them and the type suffix, and the suffix. Float
+// suffixes are only looked for on decimal literals, since `f` is a hex digit.
+fn split_suffix(text: &str, is_decimal: bool) -> (&str, &str, &str) {
+    let float_suffixes: &[&str] = if is_decimal {
+        FLOAT_SUFFIXES
+    } else {
+        &[]
+    };
+    for &suffix in INT_SUFFIXES.iter().chain(float_suffixes) {
+        if !text.ends_with(suffix) {
+            continue;
+        }
+        let body = &text[..text.len() - suffix.len()];
+        let digits = body.trim_right_matches('_');
+        if !digits.is_empty() {
+            return (digits, &body[digits.len()..], suffix);
+        }
+    }
+    (text, "", "")
+}
+
+// Puts an underscore between each group of three digits, counting from the
+// right.
+fn group_digits(digits: &str) -> String {
+    let mut result = String::with_capacity(digits.len() * 4 / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            result.push('_');
+        }
+        result.push(c);
+    }
+    result
+}
+
+#[test]
+fn normalize_number_test() {
+    let mut config = Config::from_toml(include_str!("default.toml"));
+    for text in &["0xfF_u8", "1.5E3f64", "1000000", "1_000i32", "0x1f32"] {
+        assert_eq!(normalize_number(text, &config), *text);
+    }
+
+    config.hex_literal_case = HexLiteralCase::Upper;
+    config.lowercase_float_exponent = true;
+    config.literal_suffix_style = LiteralSuffixStyle::Separated;
+    config.group_decimal_digits = true;
+    assert_eq!(normalize_number("0xfF_u8", &config), "0xFF_u8");
+    assert_eq!(normalize_number("0x1f32", &config), "0x1F32");
+    assert_eq!(normalize_number("1.5E3f64", &config), "1.5e3_f64");
+    assert_eq!(normalize_number("1000000", &config), "1_000_000");
+    assert_eq!(normalize_number("12345.678e9", &config), "12_345.678e9");
+    assert_eq!(normalize_number("1234", &config), "1234");
+    assert_eq!(normalize_number("10_00i32", &config), "10_00_i32");
+    assert_eq!(normalize_number("0b1010u8", &config), "0b1010_u8");
+
+    config.hex_literal_case = HexLiteralCase::Lower;
+    config.literal_suffix_style = LiteralSuffixStyle::Joined;
+    assert_eq!(normalize_number("0xFF__u8", &config), "0xffu8");
+    assert_eq!(normalize_number("2_f32", &config), "2f32");
+}
+
+#[test]
+fn string_lit_body_test() {
+    assert_eq!(string_lit_body(r#""a\"b\\\n\u{e9}é""#), Some(r#"a\"b\\\n\u{e9}é"#.to_owned()));
+    assert_eq!(string_lit_body("\"H\\\n    e\\\n\tl\\\\\n\""), Some("Hel\\\\\n".to_owned()));
+    assert_eq!(string_lit_body("'a'"), None);
+    assert_eq!(string_lit_body("b\"a\""), None);
+}
+
+#[test]
+fn escape_around_test() {
+    let s = r"a\n\u{1F600}\\b\x41";
+    assert_eq!(escape_around(s, 1), None);
+    assert_eq!(escape_around(s, 2), Some((1, 3)));
+    assert_eq!(escape_around(s, 3), None);
+    assert_eq!(escape_around(s, 6), Some((3, 12)));
+    assert_eq!(escape_around(s, 13), Some((12, 14)));
+    assert_eq!(escape_around(s, 14), None);
+    assert_eq!(escape_around(s, 17), Some((15, 19)));
+    assert_eq!(escape_around(s, 19), None);
+}
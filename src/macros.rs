@@ -0,0 +1,155 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Formatting macro calls. The body of a macro is an arbitrary token tree, so
+// only the format!-family macros, concat! and try!, whose arguments we know to
+// be expressions, are formatted, like calls. Format strings and the string
+// literals given to concat! are never split, even if they don't fit on the
+// line. Any other macro is copied as it is.
+
+use syntax::{ast, ptr};
+use syntax::codemap::{BytePos, CodeMap, Span};
+use syntax::diagnostic::{Emitter, Handler, Level, RenderSpan, SpanHandler};
+use syntax::parse::{self, ParseSess};
+use syntax::parse::token;
+
+use expr::rewrite_call_args;
+use lists::SeparatorTactic;
+use rewrite::{Rewrite, RewriteContext, RewriteResult};
+
+const FORMAT_MACROS: &'static [&'static str] = &["format",
+                                                  "format_args",
+                                                  "print",
+                                                  "println",
+                                                  "write",
+                                                  "writeln",
+                                                  "panic",
+                                                  "unreachable"];
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum MacroStyle {
+    // The first argument is a format string.
+    Format,
+    // The arguments are pieces of one string.
+    Concat,
+    // Like any other call.
+    Call,
+}
+
+fn macro_style(mac: &ast::Mac) -> Option<MacroStyle> {
+    let ast::Mac_::MacInvocTT(ref path, _, _) = mac.node;
+    if path.global || path.segments.len() != 1 {
+        return None;
+    }
+    let name = token::get_ident(path.segments[0].identifier);
+    if FORMAT_MACROS.iter().any(|&m| m == &*name) {
+        Some(MacroStyle::Format)
+    } else if &*name == "concat" {
+        Some(MacroStyle::Concat)
+    } else if &*name == "try" {
+        Some(MacroStyle::Call)
+    } else {
+        None
+    }
+}
+
+// Whether rewrite_macro formats mac rather than copying it.
+pub fn is_call_like(mac: &ast::Mac) -> bool {
+    macro_style(mac).is_some()
+}
+
+pub fn rewrite_macro(mac: &ast::Mac,
+                     context: &RewriteContext,
+                     width: usize,
+                     offset: usize)
+                     -> RewriteResult {
+    let style = match macro_style(mac) {
+        Some(style) => style,
+        None => return context.unformatted_snippet(mac.span),
+    };
+    let ast::Mac_::MacInvocTT(ref path, ref tts, _) = mac.node;
+    let name = format!("{}!", token::get_ident(path.segments[0].identifier));
+
+    // Only calls with parens are laid out like calls, `println![..]` is left
+    // alone.
+    let snippet = try!(context.snippet(mac.span));
+    let after_name = &snippet[name.len()..];
+    let paren_pos = name.len() + after_name.len() - after_name.trim_left().len();
+    if !snippet[paren_pos..].starts_with('(') {
+        return context.unformatted_snippet(mac.span);
+    }
+    let args = match parse_args(tts) {
+        Some(args) => args,
+        None => return context.unformatted_snippet(mac.span),
+    };
+    let keep_literals = style != MacroStyle::Call;
+
+    rewrite_call_args(context,
+                      &name,
+                      &args,
+                      mac.span.lo + BytePos(paren_pos as u32 + 1),
+                      mac.span,
+                      |arg, context, width, offset| {
+                          match arg.node {
+                              ast::Expr_::ExprLit(ref lit) if keep_literals && is_str_lit(lit) => {
+                                  context.snippet(arg.span)
+                              }
+                              _ => arg.rewrite(context, width, offset),
+                          }
+                      },
+                      // Many macros, try! among them, don't accept a trailing
+                      // comma.
+                      SeparatorTactic::Never,
+                      width,
+                      offset)
+}
+
+pub fn is_str_lit(lit: &ast::Lit) -> bool {
+    match lit.node {
+        ast::Lit_::LitStr(..) => true,
+        _ => false,
+    }
+}
+
+// Swallows the errors from parsing the body of a macro. A body which doesn't
+// parse is simply left as it is, and the session's codemap, which doesn't have
+// the source the tokens came from, can't show them anyway.
+struct SilentEmitter;
+
+impl Emitter for SilentEmitter {
+    fn emit(&mut self, _: Option<(&CodeMap, Span)>, _: &str, _: Option<&str>, _: Level) {}
+
+    fn custom_emit(&mut self, _: &CodeMap, _: RenderSpan, _: &str, _: Level) {}
+}
+
+// The comma separated expressions of a macro's body, None if it is anything
+// else.
+fn parse_args(tts: &[ast::TokenTree]) -> Option<Vec<ptr::P<ast::Expr>>> {
+    let handler = Handler::with_emitter(false, Box::new(SilentEmitter));
+    let sess = ParseSess::with_span_handler(SpanHandler::new(handler, CodeMap::new()));
+    let mut parser = parse::tts_to_parser(&sess, tts.to_vec(), Vec::new());
+    let mut args = Vec::new();
+    while parser.token != token::Eof {
+        // parse_expr panics on a syntax error, the body of a macro being
+        // something other than expressions included.
+        match parser.parse_expr_nopanic() {
+            Ok(arg) => args.push(arg),
+            Err(_) => return None,
+        }
+        match parser.token {
+            token::Eof => {}
+            token::Comma => {
+                let _ = parser.bump();
+            }
+            _ => return None,
+        }
+    }
+    Some(args)
+}
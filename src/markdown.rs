@@ -0,0 +1,211 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Formatting the Rust code blocks of Markdown files, leaving the prose as it
+// is.
+
+use std::fs::File;
+use std::io::{self, Read};
+
+use config::Config;
+use file_lines::FileLines;
+use snippet;
+
+// Formats the code in each fenced block of text tagged `rust`, e.g.,
+// ```` ```rust ```` or ```` ```rust,ignore ````. Blocks which don't parse as
+// items or as statements are left as they are.
+pub fn format_markdown(text: &str, config: &Config) -> String {
+    format_code_blocks(text, config, |info| info_words(info).first() == Some(&"rust"))
+}
+
+// As format_markdown, for the text of doc comments. As in rustdoc, untagged
+// blocks are Rust too, but blocks marked `ignore` or `text`, or tagged with
+// another language, are not formatted.
+pub fn format_doc_markdown(text: &str, config: &Config) -> String {
+    format_code_blocks(text, config, |info| {
+        info_words(info).iter().all(|&word| {
+            match word {
+                "rust" | "should_panic" | "no_run" | "compile_fail" | "test_harness" => true,
+                _ => false,
+            }
+        })
+    })
+}
+
+// The words of a fence's info string, e.g., `rust` and `ignore` for
+// ```` ```rust,ignore ````.
+fn info_words(info: &str) -> Vec<&str> {
+    info.split(|c: char| c == ',' || c.is_whitespace()).filter(|word| !word.is_empty()).collect()
+}
+
+// Formats the code blocks whose info strings is_rust accepts.
+fn format_code_blocks<F>(text: &str, config: &Config, is_rust: F) -> String
+    where F: Fn(&str) -> bool
+{
+    let lines: Vec<&str> = text.split('\n').collect();
+    let mut result: Vec<String> = Vec::with_capacity(lines.len());
+
+    let mut i = 0;
+    while i < lines.len() {
+        result.push(lines[i].to_owned());
+        let fence = match Fence::open(lines[i], &is_rust) {
+            Some(fence) => fence,
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        let end = match (i + 1..lines.len()).find(|&j| fence.is_closed_by(lines[j])) {
+            Some(end) => end,
+            // An unclosed block runs to the end of the text, leave it alone.
+            None => {
+                result.extend(lines[i + 1..].iter().map(|line| (*line).to_owned()));
+                break;
+            }
+        };
+
+        let code = &lines[i + 1..end];
+        match format_block(code, &fence, config) {
+            Some(formatted) => result.extend(formatted),
+            None => result.extend(code.iter().map(|line| (*line).to_owned())),
+        }
+        result.push(lines[end].to_owned());
+        i = end + 1;
+    }
+
+    result.join("\n")
+}
+
+// True if filename names a Markdown file rather than Rust source.
+pub fn is_markdown(filename: &str) -> bool {
+    filename.ends_with(".md")
+}
+
+// True if formatting the Markdown file filename would change nothing.
+pub fn is_formatted(filename: &str, config: &Config) -> bool {
+    match read_file(filename) {
+        Ok(text) => format_markdown(&text, config) == text,
+        Err(_) => false,
+    }
+}
+
+// Formats the Markdown file filename, returning the new text of the file.
+pub fn format_file(filename: &str, config: &Config) -> io::Result<String> {
+    let text = try!(read_file(filename));
+    Ok(format_markdown(&text, config))
+}
+
+// The line opening a fenced code block.
+struct Fence {
+    // '`' or '~'.
+    ch: char,
+    len: usize,
+    // The indentation of the fence, which is also removed from the code.
+    indent: usize,
+    is_rust: bool,
+}
+
+impl Fence {
+    fn open<F>(line: &str, is_rust: F) -> Option<Fence>
+        where F: Fn(&str) -> bool
+    {
+        let trimmed = line.trim_left_matches(' ');
+        let indent = line.len() - trimmed.len();
+        let ch = match trimmed.chars().next() {
+            Some(ch) if ch == '`' || ch == '~' => ch,
+            _ => return None,
+        };
+        let len = trimmed.chars().take_while(|&c| c == ch).count();
+        if indent > 3 || len < 3 {
+            return None;
+        }
+
+        Some(Fence {
+            ch: ch,
+            len: len,
+            indent: indent,
+            is_rust: is_rust(trimmed[len..].trim()),
+        })
+    }
+
+    fn is_closed_by(&self, line: &str) -> bool {
+        let trimmed = line.trim();
+        trimmed.len() >= self.len && trimmed.chars().all(|c| c == self.ch)
+    }
+}
+
+// The formatted lines of the code in a block, None if it should be left
+// alone.
+fn format_block(code: &[&str], fence: &Fence, config: &Config) -> Option<Vec<String>> {
+    if !fence.is_rust || code.iter().all(|line| line.trim().is_empty()) {
+        return None;
+    }
+
+    let code: Vec<&str> = code.iter().map(|line| unindent(line, fence.indent)).collect();
+    let code = code.join("\n");
+    let mut config = config.clone();
+    config.max_width = config.max_width.checked_sub(fence.indent).unwrap_or(0);
+    // Line ranges refer to the file the block is in, not to the block.
+    config.file_lines = FileLines::all();
+    let formatted = match snippet::format_item(&code, &config)
+                              .or_else(|_| snippet::format_stmt(&code, &config)) {
+        Ok(formatted) => formatted,
+        Err(_) => return None,
+    };
+
+    let indent = String::from_utf8(vec![b' '; fence.indent]).unwrap();
+    Some(formatted.lines()
+                  .map(|line| {
+                      if line.is_empty() {
+                          String::new()
+                      } else {
+                          format!("{}{}", indent, line)
+                      }
+                  })
+                  .collect())
+}
+
+// Removes up to width leading spaces.
+fn unindent(line: &str, width: usize) -> &str {
+    let spaces = line.chars().take(width).take_while(|&c| c == ' ').count();
+    &line[spaces..]
+}
+
+fn read_file(filename: &str) -> io::Result<String> {
+    let mut text = String::new();
+    try!(try!(File::open(filename)).read_to_string(&mut text));
+    Ok(text)
+}
+
+#[test]
+fn format_markdown_test() {
+    let config = Config::from_toml(include_str!("default.toml"));
+    let text = "# Title\n\nSome  prose.\n\n```rust\nfn  main(){let x=1;}\n```\n\n* a list\n\n  \
+                ~~~rust,ignore\n  let y=[1,2];\n  ~~~\n\n```text\nfn  main(){}\n```\n\n```\nfn  \
+                main(){}\n```\n";
+    let expected = "# Title\n\nSome  prose.\n\n```rust\nfn main() {\n    let x = 1;\n}\n```\n\n* \
+                    a list\n\n  ~~~rust,ignore\n  let y = [1, 2];\n  ~~~\n\n```text\nfn  \
+                    main(){}\n```\n\n```\nfn  main(){}\n```\n";
+    assert_eq!(format_markdown(text, &config), expected);
+
+    // Unclosed blocks are left alone.
+    let text = "```rust\nfn  main(){}\n";
+    assert_eq!(format_markdown(text, &config), text);
+}
+
+#[test]
+fn format_doc_markdown_test() {
+    let config = Config::from_toml(include_str!("default.toml"));
+    let text = "```\nlet x=1;\n```\n```rust,no_run\nlet x=1;\n```\n```ignore\nlet x=1;\n```\n\
+                ```text\nlet x=1;\n```\n```rust,ignore\nlet x=1;\n```";
+    let expected = "```\nlet x = 1;\n```\n```rust,no_run\nlet x = 1;\n```\n```ignore\nlet \
+                    x=1;\n```\n```text\nlet x=1;\n```\n```rust,ignore\nlet x=1;\n```";
+    assert_eq!(format_doc_markdown(text, &config), expected);
+}
@@ -8,10 +8,14 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use utils::make_indent;
+use std::mem;
+
+use comment::find_comment_end;
+use items::ItemSpacing;
+use utils::{make_indent, mark_uncovered};
 use visitor::FmtVisitor;
 
-use syntax::codemap::{self, BytePos};
+use syntax::codemap::{self, BytePos, Pos};
 
 impl<'a> FmtVisitor<'a> {
     // TODO these format_missing methods are ugly. Refactor and add unit tests
@@ -42,25 +46,59 @@ impl<'a> FmtVisitor<'a> {
                self.codemap.lookup_char_pos(start),
                self.codemap.lookup_char_pos(end));
 
-        if start == end {
+        // An inverted span means we have already written past `end`, which
+        // happens around macro expansions. Writing nothing is better than
+        // duplicating text.
+        if start >= end {
             process_last_snippet(self, "", "");
             return;
         }
 
-        assert!(start < end,
-                "Request to format inverted span: {:?} to {:?}",
-                self.codemap.lookup_char_pos(start),
-                self.codemap.lookup_char_pos(end));
-
         self.last_pos = end;
         let span = codemap::mk_sp(start, end);
-        let snippet = self.snippet(span);
+        let mut snippet = self.snippet(span);
+        if !self.item_spacing.is_empty() {
+            snippet = self.space_items(snippet, start, end);
+        }
+        if self.block_depth > 0 {
+            snippet = limit_blank_lines(&snippet, self.config.max_blank_lines);
+        }
+        if self.config.coverage {
+            snippet = mark_uncovered(&snippet);
+        }
 
         self.write_snippet(&snippet,
                            true,
                            &process_last_snippet);
     }
 
+    // Puts the blank lines asked for in item_spacing in front of the items
+    // starting in snippet, the missed span from start to end.
+    fn space_items(&mut self, snippet: String, start: BytePos, end: BytePos) -> String {
+        let mut spacings = Vec::new();
+        for spacing in mem::replace(&mut self.item_spacing, Vec::new()) {
+            if spacing.lo > end {
+                self.item_spacing.push(spacing);
+            } else if spacing.lo >= start {
+                spacings.push(spacing);
+            }
+        }
+
+        let follows_brace = start.to_usize() > 0 &&
+                            self.codemap
+                                .span_to_snippet(codemap::mk_sp(start - BytePos(1), start))
+                                .map(|s| s == "{")
+                                .unwrap_or(false);
+        let mut snippet = snippet;
+        // The last item first, so the offsets of the others stay put.
+        for spacing in spacings.iter().rev() {
+            let pos = (spacing.lo - start).to_usize();
+            let prefix = space_item_lines(&snippet[..pos], spacing, follows_brace);
+            snippet = format!("{}{}", prefix, &snippet[pos..]);
+        }
+        snippet
+    }
+
     fn write_snippet<F: Fn(&mut FmtVisitor, &str, &str)>(&mut self,
                                                          snippet: &str,
                                                          last_snippet: bool,
@@ -98,3 +136,188 @@ impl<'a> FmtVisitor<'a> {
         }
     }
 }
+
+// The text before an item, with the blank lines just above it, or above the
+// comments on the lines just above it, set as spacing asks. follows_brace is
+// whether prefix comes straight after an opening brace.
+fn space_item_lines(prefix: &str, spacing: &ItemSpacing, follows_brace: bool) -> String {
+    let lines: Vec<&str> = prefix.split('\n').collect();
+    // Leave items which don't start a line alone. The first line is the end of
+    // whatever came before, so it is never blank or a comment of the item's.
+    let last = lines.len() - 1;
+    if last == 0 || !lines[last].trim().is_empty() {
+        return prefix.to_owned();
+    }
+    let mut comments_start = last;
+    while comments_start > 1 {
+        let line = lines[comments_start - 1].trim();
+        if line.starts_with("//") {
+            comments_start -= 1;
+        } else if line.ends_with("*/") {
+            // The whole of a block comment, if it starts on a line of its own.
+            match (1..comments_start).rev().find(|&i| lines[i].trim_left().starts_with("/*")) {
+                Some(start) => comments_start = start,
+                None => break,
+            }
+        } else {
+            break;
+        }
+    }
+    let mut blanks_start = comments_start;
+    while blanks_start > 1 && lines[blanks_start - 1].trim().is_empty() {
+        blanks_start -= 1;
+    }
+
+    let mut result = lines[..blanks_start].join("\n");
+    let after_brace = if result.trim().is_empty() {
+        follows_brace
+    } else {
+        result.trim_right().ends_with('{')
+    };
+    let blank_lines = match spacing.blank_lines {
+        _ if after_brace && spacing.trim_after_brace => 0,
+        Some(blank_lines) => blank_lines,
+        None => return prefix.to_owned(),
+    };
+
+    for _ in 0..blank_lines {
+        result.push('\n');
+    }
+    for line in &lines[comments_start..] {
+        result.push('\n');
+        result.push_str(line);
+    }
+    result
+}
+
+// Cuts runs of more than max blank lines in snippet down to max. The text
+// before the first newline and after the last one is on lines with code, so
+// is never blank.
+fn limit_blank_lines(snippet: &str, max: usize) -> String {
+    let in_code = newlines_in_code(snippet);
+    let lines: Vec<&str> = snippet.split('\n').collect();
+    let mut result = String::with_capacity(snippet.len());
+    let mut blank_run = 0;
+
+    for (i, line) in lines.iter().enumerate() {
+        let is_blank = i > 0 && i < lines.len() - 1 && in_code[i - 1] && line.trim().is_empty();
+        if is_blank {
+            blank_run += 1;
+            if blank_run > max {
+                continue;
+            }
+        } else {
+            blank_run = 0;
+        }
+        if i > 0 {
+            result.push('\n');
+        }
+        result.push_str(line);
+    }
+
+    result
+}
+
+// For each newline in snippet, whether it is outside comments and string
+// literals.
+fn newlines_in_code(snippet: &str) -> Vec<bool> {
+    let bytes = snippet.as_bytes();
+    let mut result = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        // The end of any comment or literal starting at i.
+        let end = match bytes[i] {
+            b'/' if bytes[i..].starts_with(b"//") => {
+                let line_len = bytes[i..].iter().position(|&b| b == b'\n');
+                line_len.map(|len| i + len).unwrap_or(bytes.len())
+            }
+            b'/' if bytes[i..].starts_with(b"/*") => {
+                find_comment_end(&snippet[i..]).map(|end| i + end).unwrap_or(bytes.len())
+            }
+            b'"' => string_end(bytes, i + 1, 0),
+            b'r' => {
+                let hashes = bytes[i + 1..].iter().take_while(|&&b| b == b'#').count();
+                if bytes.get(i + 1 + hashes) == Some(&b'"') {
+                    string_end(bytes, i + 2 + hashes, hashes)
+                } else {
+                    i + 1
+                }
+            }
+            // Char literals, rather than lifetimes, so that '"' isn't taken
+            // for the start of a string.
+            b'\'' => {
+                let char_len = if bytes.get(i + 1) == Some(&b'\\') {
+                    2
+                } else {
+                    snippet[i + 1..].chars().next().map(|c| c.len_utf8()).unwrap_or(0)
+                };
+                if bytes.get(i + 1 + char_len) == Some(&b'\'') {
+                    i + 2 + char_len
+                } else {
+                    i + 1
+                }
+            }
+            _ => i + 1,
+        };
+        for &b in &bytes[i..end] {
+            if b == b'\n' {
+                result.push(end == i + 1);
+            }
+        }
+        i = end;
+    }
+
+    result
+}
+
+// The position after the end of the string literal whose text starts at
+// start, raw if it has hashes.
+fn string_end(bytes: &[u8], start: usize, hashes: usize) -> usize {
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if hashes == 0 => i += 2,
+            b'"' if bytes[i + 1..].iter().take_while(|&&b| b == b'#').count() >= hashes => {
+                return i + 1 + hashes;
+            }
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+#[test]
+fn limit_blank_lines_test() {
+    assert_eq!("a;\n\n    b", limit_blank_lines("a;\n\n\n\n    b", 1));
+    assert_eq!("a;\n\n\n    b", limit_blank_lines("a;\n\n\n\n    b", 2));
+    assert_eq!("\n    ", limit_blank_lines("\n\n    ", 0));
+    assert_eq!("\n\n// a\n\n", limit_blank_lines("\n\n\n// a\n\n\n", 1));
+    // Blank lines in comments and strings stay.
+    assert_eq!("/*\n\n\n*/", limit_blank_lines("/*\n\n\n*/", 1));
+    assert_eq!("\"\n\n\n\"", limit_blank_lines("\"\n\n\n\"", 1));
+    assert_eq!("r#\"\"\n\n\n\"#", limit_blank_lines("r#\"\"\n\n\n\"#", 1));
+    assert_eq!("'\"'\n\n", limit_blank_lines("'\"'\n\n\n", 1));
+    assert_eq!("// \"\n\n", limit_blank_lines("// \"\n\n\n", 1));
+}
+
+#[test]
+fn space_item_lines_test() {
+    let spacing = ItemSpacing {
+        lo: BytePos(0),
+        blank_lines: Some(1),
+        trim_after_brace: true,
+    };
+    assert_eq!("}\n\n    ", space_item_lines("}\n    ", &spacing, false));
+    assert_eq!("}\n\n    ", space_item_lines("}\n\n\n    ", &spacing, false));
+    assert_eq!("}\n\n// a\n", space_item_lines("}\n// a\n", &spacing, false));
+    assert_eq!("}\n\n/* a\n * b */\n", space_item_lines("}\n/* a\n * b */\n", &spacing, false));
+    assert_eq!("}\n\n/* a */\n// b\n", space_item_lines("}\n\n\n/* a */\n// b\n", &spacing, false));
+    assert_eq!("} ", space_item_lines("} ", &spacing, false));
+    // Nothing between an opening brace and the item.
+    assert_eq!("impl Foo {\n    ", space_item_lines("impl Foo {\n\n    ", &spacing, false));
+    assert_eq!("\n    ", space_item_lines("\n\n    ", &spacing, true));
+
+    let spacing = ItemSpacing { blank_lines: None, ..spacing };
+    assert_eq!("}\n\n\n", space_item_lines("}\n\n\n", &spacing, false));
+}
@@ -14,9 +14,8 @@ use std::path::{Path, PathBuf};
 use std::collections::HashMap;
 
 use syntax::ast;
+use syntax::attr;
 use syntax::codemap;
-use syntax::parse::parser;
-
 
 /// List all the files containing modules of a crate.
 /// If a file is used twice in a crate, it appears only once.
@@ -42,9 +41,16 @@ fn list_submodules<'a>(module: &'a ast::Mod,
                 let is_internal = codemap.span_to_filename(item.span) ==
                                   codemap.span_to_filename(sub_mod.inner);
                 let dir_path = if is_internal {
-                    search_dir.join(&item.ident.to_string())
+                    // An inline module is a directory, named by its #[path]
+                    // attribute if it has one.
+                    match attr::first_attr_value_str_by_name(&item.attrs, "path") {
+                        Some(path) => search_dir.join(&*path),
+                        None => search_dir.join(&item.ident.to_string()),
+                    }
                 } else {
-                    let mod_path = module_file(item.ident, &item.attrs, search_dir, codemap);
+                    // The parser has already found the file, following any
+                    // #[path] attribute, so we needn't work it out again.
+                    let mod_path = PathBuf::from(codemap.span_to_filename(sub_mod.inner));
                     let dir_path = mod_path.parent().unwrap().to_owned();
                     result.insert(mod_path, sub_mod);
                     dir_path
@@ -54,19 +60,3 @@ fn list_submodules<'a>(module: &'a ast::Mod,
         }
     }
 }
-
-/// Find the file corresponding to an external mod
-fn module_file(id: ast::Ident,
-               attrs: &[ast::Attribute],
-               dir_path: &Path,
-               codemap: &codemap::CodeMap)
-               -> PathBuf {
-    if let Some(path) = parser::Parser::submod_path_from_attr(attrs, &dir_path) {
-        return path;
-    }
-
-    match parser::Parser::default_submod_path(id, &dir_path, codemap).result {
-        Ok(parser::ModulePathSuccess { path, .. }) => path,
-        Err(_) => panic!("Couldn't find module {}", id)
-    }
-}
@@ -0,0 +1,197 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Formatting many crates at once on a pool of threads.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+
+use strings::string_buffer::StringBuffer;
+use syntax::diagnostic::FatalError;
+
+use {WriteMode, FormatReport, FormatResult, format, write_result};
+use cache::FormatCache;
+use config::{Config, Verbosity};
+use filemap::{self, FileMap};
+use markdown;
+use summary::Summary;
+
+// StringBuffers can't be sent between threads, so the text of each file
+// travels as a String.
+type SendableResult = (HashMap<String, String>, FormatReport);
+
+// Formats the crate rooted at each file in jobs with the config given for it,
+// using up to `threads` threads. Each crate gets its own parse session.
+// Markdown files in jobs have their code blocks formatted instead. Results are
+// merged in the order of jobs and then written out together according to
+// write_mode, so the output does not depend on scheduling.
+pub fn run_files(jobs: Vec<(String, Config)>,
+                 write_mode: WriteMode,
+                 config: &Config,
+                 threads: usize)
+                 -> Summary {
+    let mut summary = Summary::new();
+    let (markdown_jobs, jobs): (Vec<_>, Vec<_>) = jobs.into_iter().partition(|&(ref file, _)| {
+        markdown::is_markdown(file)
+    });
+    let mut cache = if config.cache_file.len() > 0 {
+        Some(FormatCache::load(&config.cache_file))
+    } else {
+        None
+    };
+    // Crates the cache says are formatted are neither parsed nor formatted.
+    let mut pending = Vec::new();
+    for (file, job_config) in jobs {
+        if cache.as_ref().map_or(false, |cache| cache.is_formatted(&file, &job_config)) {
+            if config.verbosity == Verbosity::Verbose {
+                let _ = writeln!(io::stderr(), "Skipping {}: already formatted", file);
+            }
+            summary.add_file(0);
+        } else {
+            pending.push((file, job_config));
+        }
+    }
+    let jobs = pending;
+    let job_configs: Vec<_> = jobs.iter().map(|&(_, ref config)| config.clone()).collect();
+
+    let thread_count = cmp::max(1, cmp::min(threads, jobs.len()));
+    // Reversed so that popping takes the jobs in order.
+    let queue: Vec<_> = jobs.into_iter().enumerate().collect();
+    let queue = Arc::new(Mutex::new(queue.into_iter().rev().collect::<Vec<_>>()));
+    let (sender, receiver) = mpsc::channel();
+
+    for _ in 0..thread_count {
+        let queue = queue.clone();
+        let sender = sender.clone();
+        thread::spawn(move || {
+            loop {
+                let job = queue.lock().unwrap().pop();
+                let (index, (file, config)) = match job {
+                    Some(job) => job,
+                    None => break,
+                };
+                let root = file.clone();
+                let result = guarded(move || {
+                    format(vec!["rustfmt".to_owned(), root], &config)
+                });
+                sender.send((index, file, result)).unwrap();
+            }
+        });
+    }
+    drop(sender);
+
+    let mut results: Vec<_> = receiver.iter().collect();
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut file_map = FileMap::new();
+    let mut report = FormatReport::new();
+    for (index, file, result) in results {
+        match result {
+            Ok((texts, file_report)) => {
+                let names: Vec<_> = texts.keys().cloned().collect();
+                add_texts(&mut file_map, texts);
+                report.merge(file_report);
+
+                if let Some(ref mut cache) = cache {
+                    let job_config = &job_configs[index];
+                    if names.iter().all(|name| {
+                        filemap::is_unchanged(name, &file_map[name], None, job_config)
+                    }) {
+                        cache.insert(&file, job_config, &names);
+                    } else {
+                        cache.remove(&file);
+                    }
+                }
+            }
+            Err(file_summary) => {
+                // The parser has already reported where the error is.
+                let reason = if file_summary.has_parsing_errors() {
+                    "it could not be parsed"
+                } else {
+                    "of an internal error"
+                };
+                let _ = writeln!(io::stderr(), "Skipped {} because {}", file, reason);
+                summary.add(file_summary);
+                summary.add_failed_file();
+                if let Some(ref mut cache) = cache {
+                    cache.remove(&file);
+                }
+            }
+        }
+    }
+
+    for (file, job_config) in markdown_jobs {
+        match markdown::format_file(&file, &job_config) {
+            Ok(text) => {
+                let mut buffer = StringBuffer::new();
+                buffer.push_str(&text);
+                file_map.insert(file, buffer);
+            }
+            Err(e) => {
+                let _ = writeln!(io::stderr(), "Error reading {}: {}", file, e);
+                summary.add_operational_error();
+            }
+        }
+    }
+
+    summary.add(write_result(&file_map, &report, write_mode, config));
+    if let Some(cache) = cache {
+        if let Err(e) = cache.save() {
+            let _ = writeln!(io::stderr(), "Error writing cache {}: {}", config.cache_file, e);
+            summary.add_operational_error();
+        }
+    }
+    summary
+}
+
+// Runs f on its own thread, since rustc reports fatal parse errors by
+// panicking. On failure, the summary says whether the input didn't parse or
+// rustfmt itself fell over.
+pub fn format_guarded<F>(f: F) -> Result<FormatResult, Summary>
+    where F: FnOnce() -> Option<FormatResult> + Send + 'static
+{
+    guarded(f).map(|(texts, report)| {
+        let mut file_map = FileMap::new();
+        add_texts(&mut file_map, texts);
+        (file_map, report)
+    })
+}
+
+fn guarded<F>(f: F) -> Result<SendableResult, Summary>
+    where F: FnOnce() -> Option<FormatResult> + Send + 'static
+{
+    let mut summary = Summary::new();
+    match thread::spawn(move || f().map(sendable)).join() {
+        Ok(Some(result)) => return Ok(result),
+        // The driver stopped before handing us a crate.
+        Ok(None) => summary.add_parsing_error(),
+        Err(ref payload) if payload.is::<FatalError>() => summary.add_parsing_error(),
+        Err(_) => summary.add_internal_error(),
+    }
+    Err(summary)
+}
+
+fn sendable((file_map, report): FormatResult) -> SendableResult {
+    let texts = file_map.into_iter()
+                        .map(|(name, buffer)| (name, buffer.to_string()))
+                        .collect();
+    (texts, report)
+}
+
+fn add_texts(file_map: &mut FileMap, texts: HashMap<String, String>) {
+    for (name, text) in texts {
+        let mut buffer = StringBuffer::new();
+        buffer.push_str(&text);
+        file_map.insert(name, buffer);
+    }
+}
@@ -0,0 +1,80 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Where the time goes, for finding slow rewrites. Only collected if the config
+// asks for it.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    // Nanoseconds spent and number of times, for each phase.
+    phases: HashMap<String, (u64, usize)>,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile { phases: HashMap::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.phases.is_empty()
+    }
+
+    pub fn add(&mut self, phase: &str, ns: u64) {
+        let entry = self.phases.entry(phase.to_owned()).or_insert((0, 0));
+        entry.0 += ns;
+        entry.1 += 1;
+    }
+
+    pub fn merge(&mut self, other: Profile) {
+        for (phase, (ns, count)) in other.phases {
+            let entry = self.phases.entry(phase).or_insert((0, 0));
+            entry.0 += ns;
+            entry.1 += count;
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    // Slowest phase first. Times for rewrites include their children.
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let mut phases: Vec<_> = self.phases.iter().collect();
+        phases.sort_by(|a, b| {
+            match (b.1).0.cmp(&(a.1).0) {
+                Ordering::Equal => a.0.cmp(b.0),
+                ordering => ordering,
+            }
+        });
+        for (phase, &(ns, count)) in phases {
+            try!(write!(fmt,
+                        "{:>10.3}ms {:>8} {}\n",
+                        ns as f64 / 1_000_000.0,
+                        count,
+                        phase));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn profile_test() {
+    let mut profile = Profile::new();
+    profile.add("parse", 2_000_000);
+    let mut other = Profile::new();
+    other.add("rewrite ExprCall", 500_000);
+    other.add("parse", 1_000_000);
+    profile.merge(other);
+
+    assert_eq!(profile.to_string(),
+               "     3.000ms        2 parse\n     0.500ms        1 rewrite ExprCall\n");
+}
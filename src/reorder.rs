@@ -0,0 +1,320 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Moving items around within a block of items: putting the items of a trait
+// impl in the order the trait declares them, and the declarations of a module
+// at its top.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use syntax::ast;
+use syntax::attr::AttrMetaMethods;
+use syntax::codemap::{Span, mk_sp};
+use syntax::ptr;
+use syntax::visit::{self, Visitor};
+
+use utils::{make_indent, span_after};
+use visitor::{FmtVisitor, attrs_lo};
+
+// The names of the items of each trait in the crate, in the order they are
+// declared, keyed by the trait's name.
+pub type TraitOrders = Rc<HashMap<String, Vec<String>>>;
+
+// Collects the trait orders for krate. Traits which share a name with another
+// trait are left out, since we can't tell which one an impl is for.
+pub fn trait_orders(krate: &ast::Crate) -> TraitOrders {
+    let mut collector = TraitCollector {
+        orders: HashMap::new(),
+        ambiguous: HashSet::new(),
+    };
+    visit::walk_crate(&mut collector, krate);
+    for name in &collector.ambiguous {
+        collector.orders.remove(name);
+    }
+    Rc::new(collector.orders)
+}
+
+struct TraitCollector {
+    orders: HashMap<String, Vec<String>>,
+    ambiguous: HashSet<String>,
+}
+
+impl<'v> Visitor<'v> for TraitCollector {
+    fn visit_item(&mut self, item: &'v ast::Item) {
+        if let ast::Item_::ItemTrait(_, _, _, ref trait_items) = item.node {
+            let name = item.ident.to_string();
+            let item_names = trait_items.iter().map(|ti| ti.ident.to_string()).collect();
+            if self.orders.insert(name.clone(), item_names).is_some() {
+                self.ambiguous.insert(name);
+            }
+        }
+        visit::walk_item(self, item);
+    }
+
+    // Macros are left unexpanded, so there is nothing to find in them.
+    fn visit_mac(&mut self, _: &'v ast::Mac) {}
+}
+
+const OTHER_RANK: usize = 3;
+
+// Where item goes when declarations are moved to the top of a module.
+fn declaration_rank(item: &ast::Item) -> usize {
+    match item.node {
+        ast::Item_::ItemExternCrate(..) => 0,
+        ast::Item_::ItemMod(..) => 1,
+        ast::Item_::ItemUse(..) => 2,
+        _ => OTHER_RANK,
+    }
+}
+
+// An item with the comments and attributes in front of it, formatted on its
+// own so that it can be written out of order.
+struct Chunk {
+    text: String,
+    blank_lines_before: usize,
+}
+
+impl<'a> FmtVisitor<'a> {
+    // With reorder_impl_items, writes out the items of the trait impl `item`
+    // in the order the trait declares them, if that is a different order and
+    // the trait is in this crate. Returns false, having written nothing, if
+    // the impl should be formatted as usual instead.
+    pub fn push_impl_in_trait_order(&mut self, item: &ast::Item) -> bool {
+        if !self.config.reorder_impl_items {
+            return false;
+        }
+        let (trait_ref, impl_items) = match item.node {
+            ast::Item_::ItemImpl(_, _, _, Some(ref trait_ref), _, ref impl_items) => {
+                (trait_ref, impl_items)
+            }
+            _ => return false,
+        };
+        let trait_name = match trait_ref.path.segments.last() {
+            Some(segment) => segment.identifier.to_string(),
+            None => return false,
+        };
+        let trait_orders = self.trait_orders.clone();
+        let order = match trait_orders.get(&trait_name) {
+            Some(order) => order,
+            None => return false,
+        };
+
+        // Items the trait doesn't declare can only be errors, they go last.
+        let positions: Vec<usize> = impl_items.iter()
+                                              .map(|ii| {
+                                                  let name = ii.ident.to_string();
+                                                  order.iter()
+                                                       .position(|n| *n == name)
+                                                       .unwrap_or(order.len())
+                                              })
+                                              .collect();
+        let mut new_order: Vec<usize> = (0..impl_items.len()).collect();
+        new_order.sort_by(|&a, &b| positions[a].cmp(&positions[b]));
+        if new_order.iter().enumerate().all(|(i, &j)| i == j) ||
+           !impl_items.iter().all(|ii| self.in_file_lines(ii.span)) {
+            return false;
+        }
+
+        // Each item takes everything after the previous one with it, so that
+        // must start on a new line.
+        let body_lo = match span_after(item.span, "{", self.codemap) {
+            Ok(body_lo) => body_lo,
+            Err(_) => return false,
+        };
+        let mut chunk_spans = Vec::new();
+        let mut prev_hi = body_lo;
+        for ii in impl_items {
+            let lo = attrs_lo(&ii.attrs, ii.span.lo);
+            if !self.starts_new_line(mk_sp(prev_hi, lo)) {
+                return false;
+            }
+            chunk_spans.push(mk_sp(prev_hi, ii.span.hi));
+            prev_hi = ii.span.hi;
+        }
+
+        let chunks: Vec<Chunk> = impl_items.iter()
+                                           .zip(chunk_spans)
+                                           .map(|(ii, span)| {
+                                               self.format_chunk(span, |v| v.visit_impl_item(ii))
+                                           })
+                                           .collect();
+        let blank_lines = chunks[1..].iter().map(|c| c.blank_lines_before).max().unwrap_or(0);
+
+        self.format_missing(body_lo);
+        for (n, &i) in new_order.iter().enumerate() {
+            let blank_lines = if n == 0 {
+                0
+            } else {
+                blank_lines
+            };
+            self.push_chunk(&chunks[i], blank_lines);
+        }
+        self.last_pos = prev_hi;
+        true
+    }
+
+    // Visits the items of m. With reorder_declarations, its extern crates,
+    // then mods, then uses are moved to the top first. They don't move past
+    // macro items, which may define macros they use, and runs of items with
+    // the same cfg attributes move together.
+    pub fn visit_mod_items(&mut self, m: &ast::Mod) {
+        if !self.config.reorder_declarations || !self.push_mod_items_reordered(&m.items) {
+            visit::walk_mod(self, m);
+        }
+    }
+
+    // Writes out items with the declarations moved up, see visit_mod_items.
+    // Returns false, having written nothing, if they are in order already or
+    // can't be moved.
+    fn push_mod_items_reordered(&mut self, items: &[ptr::P<ast::Item>]) -> bool {
+        if items.is_empty() || !items.iter().all(|item| self.in_file_lines(item.span)) {
+            return false;
+        }
+
+        let cfgs: Vec<String> = items.iter().map(|item| self.cfg_attrs(item)).collect();
+        // (part of the module, rank, items), the first two being where the
+        // unit goes.
+        let mut units: Vec<(usize, usize, Vec<usize>)> = Vec::new();
+        let mut part = 0;
+        for (i, item) in items.iter().enumerate() {
+            if let ast::Item_::ItemMac(..) = item.node {
+                units.push((part + 1, 0, vec![i]));
+                part += 2;
+                continue;
+            }
+            let rank = declaration_rank(item);
+            if i > 0 && !cfgs[i].is_empty() && cfgs[i] == cfgs[i - 1] {
+                let unit = units.last_mut().unwrap();
+                if unit.0 == part {
+                    unit.1 = cmp::max(unit.1, rank);
+                    unit.2.push(i);
+                    continue;
+                }
+            }
+            units.push((part, rank, vec![i]));
+        }
+        units.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+        let new_order: Vec<usize> = units.iter().flat_map(|unit| unit.2.iter().cloned()).collect();
+        if new_order.iter().enumerate().all(|(i, &j)| i == j) {
+            return false;
+        }
+
+        // Anything in front of the first item stays where it is. Each of the
+        // others takes everything after the previous item with it, so must
+        // start on a new line.
+        let first_lo = attrs_lo(&items[0].attrs, items[0].span.lo);
+        if self.is_out_of_order(mk_sp(first_lo, items[0].span.hi)) {
+            return false;
+        }
+        let mut chunk_spans = Vec::new();
+        let mut prev_hi = first_lo;
+        for (i, item) in items.iter().enumerate() {
+            let lo = attrs_lo(&item.attrs, item.span.lo);
+            if i > 0 && !self.starts_new_line(mk_sp(prev_hi, lo)) {
+                return false;
+            }
+            chunk_spans.push(mk_sp(prev_hi, item.span.hi));
+            prev_hi = item.span.hi;
+        }
+
+        let chunks: Vec<Chunk> = items.iter()
+                                      .zip(chunk_spans)
+                                      .map(|(item, span)| {
+                                          self.format_chunk(span, |v| v.visit_item(item))
+                                      })
+                                      .collect();
+
+        if first_lo > self.last_pos {
+            self.format_missing_with_indent(first_lo);
+        } else {
+            self.buffer.push_str(&make_indent(self.block_indent));
+        }
+        for (n, &i) in new_order.iter().enumerate() {
+            if n == 0 {
+                self.buffer.push_str(&chunks[i].text);
+                continue;
+            }
+            let prev = new_order[n - 1];
+            let rank = declaration_rank(&items[i]);
+            // Items which were next to each other stay as they were, and
+            // declarations of a kind are kept together.
+            let blank_lines = if prev + 1 == i {
+                chunks[i].blank_lines_before
+            } else if rank < OTHER_RANK && rank == declaration_rank(&items[prev]) {
+                0
+            } else {
+                1
+            };
+            self.push_chunk(&chunks[i], blank_lines);
+        }
+        self.last_pos = prev_hi;
+        self.last_item = None;
+        true
+    }
+
+    // The cfg attributes of item, as written.
+    fn cfg_attrs(&self, item: &ast::Item) -> String {
+        item.attrs
+            .iter()
+            .filter(|attr| attr.check_name("cfg"))
+            .map(|attr| self.snippet(attr.span))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn push_chunk(&mut self, chunk: &Chunk, blank_lines: usize) {
+        self.buffer.push_str("\n");
+        for _ in 0..blank_lines {
+            self.buffer.push_str("\n");
+        }
+        self.buffer.push_str(&make_indent(self.block_indent));
+        self.buffer.push_str(&chunk.text);
+    }
+
+    // Whether there is a line break in span, with nothing but whitespace
+    // before the first one.
+    fn starts_new_line(&self, span: Span) -> bool {
+        let snippet = self.snippet(span);
+        match snippet.find('\n') {
+            Some(i) => snippet[..i].trim().is_empty(),
+            None => false,
+        }
+    }
+
+    // Formats the item in span, which starts with whatever is in front of it,
+    // using visit, as a visitor at the current position would.
+    fn format_chunk<F>(&self, span: Span, visit: F) -> Chunk
+        where F: FnOnce(&mut FmtVisitor<'a>)
+    {
+        let mut visitor = FmtVisitor::from_codemap(self.codemap, self.config);
+        visitor.block_indent = self.block_indent;
+        visitor.block_depth = self.block_depth;
+        visitor.plugins = self.plugins;
+        visitor.cache = self.cache.clone();
+        visitor.profile = self.profile.clone();
+        visitor.trait_orders = self.trait_orders.clone();
+        visitor.last_pos = span.lo;
+
+        visit(&mut visitor);
+        visitor.format_missing(span.hi);
+
+        self.failures.borrow_mut().extend(visitor.failures.into_inner());
+        self.stats.borrow_mut().merge(visitor.stats.into_inner());
+        // Indented as the chunk is written out.
+        let text = visitor.buffer.to_string();
+        let newlines = text.len() - text.trim_left_matches('\n').len();
+        Chunk {
+            text: text.trim_left().to_owned(),
+            blank_lines_before: newlines.checked_sub(1).unwrap_or(0),
+        }
+    }
+}
@@ -10,9 +10,18 @@
 
 // A generic trait to abstract the rewriting of an element (of the AST).
 
-use syntax::codemap::CodeMap;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use syntax::codemap::{BytePos, CodeMap, Pos, Span};
 
 use config::Config;
+use profile::Profile;
+use reorder::TraitOrders;
+use {Plugins, RewriteFailure};
+use utils::{mark_uncovered, reindent};
 
 pub trait Rewrite {
     /// Rewrite self into offset and width.
@@ -22,11 +31,90 @@ pub trait Rewrite {
     /// `width` is the maximum number of characters on the last line
     /// (excluding offset). The width of other lines is not limited by
     /// `width`.
-    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> Option<String>;
+    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> RewriteResult;
+}
+
+pub type RewriteResult = Result<String, RewriteError>;
+
+// Why a rewrite failed. The original source is kept when a rewrite fails.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum RewriteError {
+    // There was not enough width left to fit the rewritten code.
+    WidthExhausted,
+    // We don't know how to format this construct yet.
+    Unsupported,
+    // The codemap could not give us the source for a span.
+    NoSource,
+    // A comment is somewhere the rewritten code has no room for it.
+    Comment,
+    // The code came out of a macro expansion, so its spans don't match the
+    // source.
+    Macro,
+}
+
+impl fmt::Display for RewriteError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            RewriteError::WidthExhausted => write!(fmt, "ran out of width"),
+            RewriteError::Unsupported => write!(fmt, "unsupported construct"),
+            RewriteError::NoSource => write!(fmt, "source unavailable"),
+            RewriteError::Comment => write!(fmt, "comment in the way"),
+            RewriteError::Macro => write!(fmt, "code from a macro expansion"),
+        }
+    }
 }
 
+// Results of rewriting expressions, keyed by the expression's span, and the
+// width, offset, block indent and statement indent it was rewritten with.
+// Laying out a parent several ways often rewrites its children with the same
+// budget again.
+pub type RewriteCache = Rc<RefCell<HashMap<(BytePos, BytePos, usize, usize, usize, usize),
+                                           RewriteResult>>>;
+
 pub struct RewriteContext<'a> {
     pub codemap: &'a CodeMap,
     pub config: &'a Config,
     pub block_indent: usize,
+    // The block indent of the statement being rewritten, which block_indent
+    // moves on from inside calls and struct literals.
+    pub stmt_indent: usize,
+    // Where to record code which couldn't be formatted and was left as is.
+    pub failures: &'a RefCell<Vec<RewriteFailure>>,
+    // The hook asked to approve each rewrite and the passes run on them, if
+    // set.
+    pub plugins: Option<&'a Plugins>,
+    pub cache: &'a RewriteCache,
+    pub trait_orders: &'a TraitOrders,
+    // Time spent in each kind of rewrite, if the config asks for it.
+    pub profile: &'a Rc<RefCell<Profile>>,
+}
+
+impl<'a> RewriteContext<'a> {
+    // The original source for span.
+    pub fn snippet(&self, span: Span) -> RewriteResult {
+        self.codemap.span_to_snippet(span).map_err(|_| RewriteError::NoSource)
+    }
+
+    // The original source for span, for code we couldn't format, moved to
+    // start at column offset, see utils::reindent.
+    pub fn fallback_snippet(&self, span: Span, offset: usize) -> RewriteResult {
+        let from = self.codemap.lookup_char_pos(span.lo).col.to_usize();
+        let snippet = reindent(&try!(self.snippet(span)), from, offset);
+        if self.config.coverage {
+            Ok(mark_uncovered(&snippet))
+        } else {
+            Ok(snippet)
+        }
+    }
+
+    // The original source for span, for code we pass through without
+    // formatting it. Marked out in coverage mode.
+    pub fn unformatted_snippet(&self, span: Span) -> RewriteResult {
+        let snippet = try!(self.snippet(span));
+        if self.config.coverage {
+            Ok(mark_uncovered(&snippet))
+        } else {
+            Ok(snippet)
+        }
+    }
 }
@@ -0,0 +1,305 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Line based differences between the original and formatted source.
+
+use diff;
+use std::cmp;
+use std::io::{self, Write};
+
+const RED: &'static str = "\x1b[31m";
+const GREEN: &'static str = "\x1b[32m";
+const CYAN: &'static str = "\x1b[36m";
+const RESET: &'static str = "\x1b[0m";
+
+// The number of unchanged lines shown around each change in a patch.
+const PATCH_CONTEXT: usize = 3;
+
+// A run of lines where the original and the formatted text differ.
+#[derive(Eq, PartialEq, Debug)]
+pub struct Mismatch {
+    // The line in the original text where the run starts, numbered from 1.
+    pub original_start_line: usize,
+    pub original_lines: Vec<String>,
+    pub expected_lines: Vec<String>,
+}
+
+impl Mismatch {
+    fn new(original_start_line: usize) -> Mismatch {
+        Mismatch {
+            original_start_line: original_start_line,
+            original_lines: Vec::new(),
+            expected_lines: Vec::new(),
+        }
+    }
+}
+
+pub fn make_diff(original: &str, expected: &str) -> Vec<Mismatch> {
+    let mut result = Vec::new();
+    let mut current: Option<Mismatch> = None;
+    let mut line_number = 1;
+
+    for line in diff::lines(original, expected) {
+        match line {
+            diff::Result::Left(s) => {
+                mismatch_at(&mut current, line_number).original_lines.push(s.to_owned());
+                line_number += 1;
+            }
+            diff::Result::Right(s) => {
+                mismatch_at(&mut current, line_number).expected_lines.push(s.to_owned());
+            }
+            diff::Result::Both(..) => {
+                if let Some(mismatch) = current.take() {
+                    result.push(mismatch);
+                }
+                line_number += 1;
+            }
+        }
+    }
+
+    if let Some(mismatch) = current {
+        result.push(mismatch);
+    }
+
+    result
+}
+
+// Prints the mismatches for filename, with removed lines prefixed by `-` and
+// added lines by `+`. If color is set, uses terminal colors to mark them out.
+pub fn print_diff<T>(mut out: T,
+                     filename: &str,
+                     mismatches: &[Mismatch],
+                     color: bool)
+                     -> Result<(), io::Error>
+    where T: Write
+{
+    let paint = |code: &'static str| if color { code } else { "" };
+
+    try!(writeln!(out, "{}Diff in {}:{}", paint(CYAN), filename, paint(RESET)));
+    for mismatch in mismatches {
+        try!(writeln!(out,
+                      "{}Diff at line {}:{}",
+                      paint(CYAN),
+                      mismatch.original_start_line,
+                      paint(RESET)));
+        for line in &mismatch.original_lines {
+            try!(writeln!(out, "{}-{}{}", paint(RED), line, paint(RESET)));
+        }
+        for line in &mismatch.expected_lines {
+            try!(writeln!(out, "{}+{}{}", paint(GREEN), line, paint(RESET)));
+        }
+    }
+    Ok(())
+}
+
+// Writes the differences between original and formatted as a unified diff of
+// filename, which `git apply` or `patch -p1` can apply. Writes nothing and
+// returns false if there are no differences.
+pub fn write_patch<T>(mut out: T,
+                      filename: &str,
+                      original: &str,
+                      formatted: &str)
+                      -> Result<bool, io::Error>
+    where T: Write
+{
+    let mut lines = diff::lines(original, formatted);
+    let original_newline = original.is_empty() || original.ends_with('\n');
+    let formatted_newline = formatted.is_empty() || formatted.ends_with('\n');
+    // diff::lines can't see a change to just the newline at the end, so the
+    // last line is removed and added again.
+    if original_newline != formatted_newline {
+        if let Some(&diff::Result::Both(original_line, formatted_line)) = lines.last() {
+            lines.pop();
+            lines.push(diff::Result::Left(original_line));
+            lines.push(diff::Result::Right(formatted_line));
+        }
+    }
+    let changes: Vec<usize> = lines.iter()
+                                   .enumerate()
+                                   .filter(|&(_, line)| {
+                                       match *line {
+                                           diff::Result::Both(..) => false,
+                                           _ => true,
+                                       }
+                                   })
+                                   .map(|(i, _)| i)
+                                   .collect();
+    if changes.is_empty() {
+        return Ok(false);
+    }
+
+    // The last line of each text, which may need marking as having no newline.
+    let original_last = lines.iter().rposition(|line| {
+        match *line {
+            diff::Result::Right(..) => false,
+            _ => true,
+        }
+    });
+    let formatted_last = lines.iter().rposition(|line| {
+        match *line {
+            diff::Result::Left(..) => false,
+            _ => true,
+        }
+    });
+
+    // Absolute paths would otherwise give `a//path`.
+    let filename = filename.trim_left_matches('/');
+    try!(writeln!(out, "--- a/{}", filename));
+    try!(writeln!(out, "+++ b/{}", filename));
+
+    // The lines of each text before the next hunk.
+    let (mut original_before, mut formatted_before, mut counted) = (0, 0, 0);
+    let mut i = 0;
+    while i < changes.len() {
+        // Changes with no more than twice the context between them share a
+        // hunk.
+        let mut j = i;
+        while j + 1 < changes.len() && changes[j + 1] - changes[j] <= 2 * PATCH_CONTEXT + 1 {
+            j += 1;
+        }
+        let lo = changes[i].checked_sub(PATCH_CONTEXT).unwrap_or(0);
+        let hi = cmp::min(changes[j] + PATCH_CONTEXT + 1, lines.len());
+
+        let (original_skipped, formatted_skipped) = count_lines(&lines[counted..lo]);
+        original_before += original_skipped;
+        formatted_before += formatted_skipped;
+        let (original_count, formatted_count) = count_lines(&lines[lo..hi]);
+        try!(writeln!(out,
+                      "@@ -{} +{} @@",
+                      hunk_range(original_before, original_count),
+                      hunk_range(formatted_before, formatted_count)));
+        for (k, line) in lines.iter().enumerate().take(hi).skip(lo) {
+            let no_newline = match *line {
+                diff::Result::Left(s) => {
+                    try!(writeln!(out, "-{}", s));
+                    !original_newline && Some(k) == original_last
+                }
+                diff::Result::Right(s) => {
+                    try!(writeln!(out, "+{}", s));
+                    !formatted_newline && Some(k) == formatted_last
+                }
+                diff::Result::Both(s, _) => {
+                    try!(writeln!(out, " {}", s));
+                    !original_newline && Some(k) == original_last
+                }
+            };
+            if no_newline {
+                try!(writeln!(out, "\\ No newline at end of file"));
+            }
+        }
+
+        original_before += original_count;
+        formatted_before += formatted_count;
+        counted = hi;
+        i = j + 1;
+    }
+
+    Ok(true)
+}
+
+// The number of lines of the original and of the formatted text in lines.
+fn count_lines(lines: &[diff::Result<&str>]) -> (usize, usize) {
+    lines.iter().fold((0, 0), |(original, formatted), line| {
+        match *line {
+            diff::Result::Left(..) => (original + 1, formatted),
+            diff::Result::Right(..) => (original, formatted + 1),
+            diff::Result::Both(..) => (original + 1, formatted + 1),
+        }
+    })
+}
+
+// The `START,COUNT` of a hunk with before lines preceding it. An empty hunk
+// starts at the line before.
+fn hunk_range(before: usize, count: usize) -> String {
+    if count == 0 {
+        format!("{},0", before)
+    } else {
+        format!("{},{}", before + 1, count)
+    }
+}
+
+// Get the current mismatch, starting a new one if we are not in one.
+fn mismatch_at(current: &mut Option<Mismatch>, line_number: usize) -> &mut Mismatch {
+    if current.is_none() {
+        *current = Some(Mismatch::new(line_number));
+    }
+    current.as_mut().unwrap()
+}
+
+#[test]
+fn diff_regions() {
+    assert_eq!(Vec::<Mismatch>::new(), make_diff("a\nb\n", "a\nb\n"));
+
+    let diff = make_diff("a\nb  \nc\nd\n", "a\nb\nc\nd\ne\n");
+    assert_eq!(vec![Mismatch {
+                        original_start_line: 2,
+                        original_lines: vec!["b  ".to_owned()],
+                        expected_lines: vec!["b".to_owned()],
+                    },
+                    Mismatch {
+                        original_start_line: 5,
+                        original_lines: vec![],
+                        expected_lines: vec!["e".to_owned()],
+                    }],
+               diff);
+}
+
+#[test]
+fn print_diff_color() {
+    let mismatches = make_diff("a\nb\n", "a\nc\n");
+
+    let mut plain = Vec::new();
+    print_diff(&mut plain, "foo.rs", &mismatches, false).unwrap();
+    assert_eq!(String::from_utf8(plain).unwrap(),
+               "Diff in foo.rs:\nDiff at line 2:\n-b\n+c\n");
+
+    let mut colored = Vec::new();
+    print_diff(&mut colored, "foo.rs", &mismatches, true).unwrap();
+    let colored = String::from_utf8(colored).unwrap();
+    assert!(colored.contains("\x1b[31m-b\x1b[0m"));
+    assert!(colored.contains("\x1b[32m+c\x1b[0m"));
+}
+
+#[test]
+fn write_patch_test() {
+    let mut patch = Vec::new();
+    assert!(!write_patch(&mut patch, "foo.rs", "a\n", "a\n").unwrap());
+    assert!(patch.is_empty());
+
+    let original = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n14\n15\n16\n";
+    let formatted = "0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n15\n16\n";
+    assert!(write_patch(&mut patch, "src/foo.rs", original, formatted).unwrap());
+    assert_eq!(String::from_utf8(patch).unwrap(),
+               "--- a/src/foo.rs\n+++ b/src/foo.rs\n@@ -1,3 +1,4 @@\n+0\n 1\n 2\n 3\n\
+                @@ -11,6 +12,5 @@\n 11\n 12\n 13\n-14\n 15\n 16\n");
+
+    // Absolute paths are made relative to the root.
+    let mut patch = Vec::new();
+    assert!(write_patch(&mut patch, "/src/foo.rs", "a\n", "b\n").unwrap());
+    assert_eq!(String::from_utf8(patch).unwrap(),
+               "--- a/src/foo.rs\n+++ b/src/foo.rs\n@@ -1,1 +1,1 @@\n-a\n+b\n");
+}
+
+#[test]
+fn write_patch_no_newline_test() {
+    // Only the newline at the end changes.
+    let mut patch = Vec::new();
+    assert!(write_patch(&mut patch, "foo.rs", "a\nb", "a\nb\n").unwrap());
+    assert_eq!(String::from_utf8(patch).unwrap(),
+               "--- a/foo.rs\n+++ b/foo.rs\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of \
+                file\n+b\n");
+
+    // Neither text ends in a newline.
+    let mut patch = Vec::new();
+    assert!(write_patch(&mut patch, "foo.rs", "a\nb", "c\nb").unwrap());
+    assert_eq!(String::from_utf8(patch).unwrap(),
+               "--- a/foo.rs\n+++ b/foo.rs\n@@ -1,2 +1,2 @@\n-a\n+c\n b\n\\ No newline at \
+                end of file\n");
+}
@@ -0,0 +1,71 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// A formatting session, for long lived tools such as editors and build daemons
+// which format many requests, possibly from several threads at once.
+
+use std::sync::Arc;
+
+use {Input, Error, FormatReport, Plugins, RewriteHook, format_input_shared};
+use config::Config;
+use filemap::FileMap;
+use summary::Summary;
+
+// A config, and optionally a rewrite hook and passes, to format requests with.
+// Sessions are cheap to clone and may be shared between threads. Each request
+// is formatted on a fresh thread, so the parser's thread local state (e.g., the
+// interner) is never shared between requests.
+#[derive(Clone)]
+pub struct Session {
+    config: Arc<Config>,
+    plugins: Plugins,
+}
+
+impl Session {
+    pub fn new(config: Config) -> Session {
+        Session {
+            config: Arc::new(config),
+            plugins: Plugins::new(),
+        }
+    }
+
+    // A session which calls hook to approve each rewrite.
+    pub fn with_hook(config: Config, hook: RewriteHook) -> Session {
+        Session {
+            config: Arc::new(config),
+            plugins: Plugins::with_hook(hook),
+        }
+    }
+
+    // A session which formats with plugins, see Plugins.
+    pub fn with_plugins(config: Config, plugins: Plugins) -> Session {
+        Session {
+            config: Arc::new(config),
+            plugins: plugins,
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // As format_input.
+    pub fn format(&self, input: Input) -> Result<(Summary, FileMap, FormatReport), Error> {
+        format_input_shared(input, self.config.clone(), self.plugins.clone())
+    }
+}
+
+#[test]
+fn session_is_send_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<Config>();
+    assert_send_sync::<Session>();
+}
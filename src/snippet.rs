@@ -0,0 +1,78 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Formatting fragments of code rather than whole files. Expressions and
+// statements are wrapped in a function so that they parse, and unwrapped
+// again afterwards.
+
+use {Error, Input, format_input};
+use config::Config;
+
+const WRAPPER_START: &'static str = "fn __rustfmt_snippet() {\n";
+const WRAPPER_END: &'static str = "\n}\n";
+
+// Formats a single expression.
+pub fn format_expr(expr: &str, config: &Config) -> Result<String, Error> {
+    format_in_fn(expr, config)
+}
+
+// Formats one or more statements, as they would appear in a block.
+pub fn format_stmt(stmt: &str, config: &Config) -> Result<String, Error> {
+    format_in_fn(stmt, config)
+}
+
+// Formats one or more items, as they would appear at the top of a module.
+pub fn format_item(item: &str, config: &Config) -> Result<String, Error> {
+    let formatted = try!(format_text(item.to_owned(), config));
+    Ok(formatted.trim_right().to_owned())
+}
+
+fn format_in_fn(text: &str, config: &Config) -> Result<String, Error> {
+    // The snippet ends up indented by one level inside the wrapper, make up
+    // for the lost width.
+    let mut config = config.clone();
+    config.max_width += config.tab_spaces;
+
+    let wrapped = format!("{}{}{}", WRAPPER_START, text, WRAPPER_END);
+    let formatted = try!(format_text(wrapped, &config));
+
+    let body = formatted.trim_right();
+    if !body.starts_with(WRAPPER_START) || !body.ends_with('}') {
+        return Err(Error::Internal);
+    }
+    let body = &body[WRAPPER_START.len()..body.len() - 1];
+
+    let lines: Vec<_> = body.trim_right()
+                            .lines()
+                            .map(|line| unindent(line, config.tab_spaces))
+                            .collect();
+    Ok(lines.join("\n"))
+}
+
+fn format_text(text: String, config: &Config) -> Result<String, Error> {
+    let (_, file_map, _) = try!(format_input(Input::Text(text), config));
+    match file_map.values().next() {
+        Some(text) => Ok(text.to_string()),
+        None => Err(Error::Internal),
+    }
+}
+
+// Removes up to width leading spaces.
+fn unindent(line: &str, width: usize) -> &str {
+    let spaces = line.chars().take(width).take_while(|&c| c == ' ').count();
+    &line[spaces..]
+}
+
+#[test]
+fn unindent_test() {
+    assert_eq!(unindent("        foo", 4), "    foo");
+    assert_eq!(unindent("  foo", 4), "foo");
+    assert_eq!(unindent("", 4), "");
+}
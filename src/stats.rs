@@ -0,0 +1,89 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// How much of each kind of code formatting changed, and why rewrites failed,
+// for tracking adoption and finding what rustfmt handles badly. Only collected
+// if the config asks for it.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    // The number of nodes rewritten and the number of those which changed,
+    // for each kind of code.
+    kinds: BTreeMap<String, (usize, usize)>,
+    // The number of failed rewrites, for each reason.
+    failures: BTreeMap<String, usize>,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats {
+            kinds: BTreeMap::new(),
+            failures: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, kind: &str, changed: bool) {
+        let entry = self.kinds.entry(kind.to_owned()).or_insert((0, 0));
+        entry.0 += 1;
+        if changed {
+            entry.1 += 1;
+        }
+    }
+
+    pub fn add_failure(&mut self, reason: &str) {
+        *self.failures.entry(reason.to_owned()).or_insert(0) += 1;
+    }
+
+    pub fn merge(&mut self, other: Stats) {
+        for (kind, (count, changed)) in other.kinds {
+            let entry = self.kinds.entry(kind).or_insert((0, 0));
+            entry.0 += count;
+            entry.1 += changed;
+        }
+        for (reason, count) in other.failures {
+            *self.failures.entry(reason).or_insert(0) += count;
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    // Kinds of code and failure reasons are in alphabetical order.
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        try!(write!(fmt, "{:>8} {:>8} kind\n", "changed", "total"));
+        for (kind, &(count, changed)) in &self.kinds {
+            try!(write!(fmt, "{:>8} {:>8} {}\n", changed, count, kind));
+        }
+        if !self.failures.is_empty() {
+            try!(write!(fmt, "{:>8} failed rewrites\n", "count"));
+            for (reason, count) in &self.failures {
+                try!(write!(fmt, "{:>8} {}\n", count, reason));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn stats_test() {
+    let mut stats = Stats::new();
+    stats.add("struct", true);
+    stats.add("call", false);
+    let mut other = Stats::new();
+    other.add("struct", false);
+    other.add_failure("could not format import: ran out of width");
+    stats.merge(other);
+
+    assert_eq!(stats.to_string(),
+               " changed    total kind\n       0        1 call\n       1        2 struct\n   \
+                count failed rewrites\n       1 could not format import: ran out of width\n");
+}
@@ -10,7 +10,9 @@
 
 // Format string literals.
 
-use utils::{make_indent, next_char, prev_char, round_up_to_power_of_two};
+use literals::escape_around;
+use utils::{char_at, char_width, make_indent, next_char, prev_char, round_up_to_power_of_two,
+            str_width};
 
 use MIN_STRING;
 
@@ -22,11 +24,13 @@ pub struct StringFormat<'a> {
     pub width: usize,
     pub offset: usize,
     pub trim_end: bool,
+    pub tab_width: usize,
 }
 
+// s is the text of the string as written, escape sequences are never split.
+// Widths are in columns, see utils::str_width.
 // TODO: simplify this!
 pub fn rewrite_string<'a>(s: &str, fmt: &StringFormat<'a>) -> String {
-    // FIXME I bet this stomps unicode escapes in the source string
     // TODO if lo.col > IDEAL - 10, start a new line (need cur indent for that)
 
     let indent = make_indent(fmt.offset);
@@ -36,37 +40,38 @@ pub fn rewrite_string<'a>(s: &str, fmt: &StringFormat<'a>) -> String {
     let mut result = String::with_capacity(round_up_to_power_of_two(s.len()));
     result.push_str(fmt.opener);
 
-    let ender_length = fmt.line_end.len();
-    let max_chars = fmt.width.checked_sub(fmt.opener.len()).unwrap_or(0)
+    let ender_length = str_width(fmt.line_end, fmt.tab_width);
+    let max_chars = fmt.width.checked_sub(str_width(fmt.opener, fmt.tab_width)).unwrap_or(0)
                              .checked_sub(ender_length).unwrap_or(1);
 
     loop {
-        let mut cur_end = cur_start + max_chars;
+        let mut cur_end = end_of_width(s, cur_start, max_chars, fmt.tab_width);
 
         if cur_end >= s.len() {
             result.push_str(&s[cur_start..]);
             break;
         }
 
-        // Make sure we're on a char boundary.
-        cur_end = next_char(&s, cur_end);
-
         // Push cur_end left until we reach whitespace.
-        while !s.char_at(cur_end - 1).is_whitespace() {
+        while !char_at(&s, cur_end - 1).is_whitespace() {
             cur_end = prev_char(&s, cur_end);
 
             if cur_end - cur_start < MIN_STRING {
                 // We can't break at whitespace, fall back to splitting
                 // anywhere that doesn't break an escape sequence.
-                cur_end = next_char(&s, cur_start + max_chars);
-                while s.char_at(prev_char(&s, cur_end)) == '\\' {
-                    cur_end = prev_char(&s, cur_end);
+                cur_end = end_of_width(s, cur_start, max_chars, fmt.tab_width);
+                if let Some((lo, hi)) = escape_around(&s, cur_end) {
+                    cur_end = if lo > cur_start {
+                        lo
+                    } else {
+                        hi
+                    };
                 }
                 break;
             }
         }
         // Make sure there is no whitespace to the right of the break.
-        while cur_end < s.len() && s.char_at(cur_end).is_whitespace() {
+        while cur_end < s.len() && char_at(&s, cur_end).is_whitespace() {
             cur_end = next_char(&s, cur_end + 1);
         }
 
@@ -88,3 +93,16 @@ pub fn rewrite_string<'a>(s: &str, fmt: &StringFormat<'a>) -> String {
 
     result
 }
+
+// The end of the longest run of s from start that fits in width columns, but
+// at least one char.
+fn end_of_width(s: &str, start: usize, width: usize, tab_width: usize) -> usize {
+    let mut used = 0;
+    for (i, c) in s[start..].char_indices() {
+        used += char_width(c, tab_width);
+        if used > width && i > 0 {
+            return start + i;
+        }
+    }
+    s.len()
+}
@@ -0,0 +1,178 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// What happened during a run of rustfmt, used to pick an exit code and to
+// print statistics at the end of a run.
+
+use std::io::{self, Write};
+
+use rustc_serialize::json;
+
+#[must_use]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Summary {
+    // Reading or writing files failed, or the options were bad.
+    has_operational_errors: bool,
+    // A crate could not be parsed.
+    has_parsing_errors: bool,
+    // Rustfmt itself crashed.
+    has_internal_errors: bool,
+    // Formatting would change a file, only recorded by modes which leave the
+    // files untouched.
+    has_diff: bool,
+    files_processed: usize,
+    // Files whose formatting differs from the original, whether or not they
+    // were written.
+    files_changed: usize,
+    // For each changed region, the larger of the number of original and
+    // formatted lines.
+    lines_changed: usize,
+    rewrite_failures: usize,
+    // Crates skipped because they could not be parsed or rustfmt crashed.
+    files_failed: usize,
+}
+
+// Statistics for a whole run, as printed by --summary json.
+#[derive(RustcEncodable)]
+struct Stats {
+    files_processed: usize,
+    files_changed: usize,
+    lines_changed: usize,
+    rewrite_failures: usize,
+    files_failed: usize,
+    elapsed_ms: u64,
+}
+
+impl Summary {
+    pub fn new() -> Summary {
+        Default::default()
+    }
+
+    pub fn has_operational_errors(&self) -> bool {
+        self.has_operational_errors
+    }
+
+    pub fn has_parsing_errors(&self) -> bool {
+        self.has_parsing_errors
+    }
+
+    pub fn has_internal_errors(&self) -> bool {
+        self.has_internal_errors
+    }
+
+    pub fn has_diff(&self) -> bool {
+        self.has_diff
+    }
+
+    pub fn files_failed(&self) -> usize {
+        self.files_failed
+    }
+
+    pub fn add_operational_error(&mut self) {
+        self.has_operational_errors = true;
+    }
+
+    pub fn add_parsing_error(&mut self) {
+        self.has_parsing_errors = true;
+    }
+
+    pub fn add_internal_error(&mut self) {
+        self.has_internal_errors = true;
+    }
+
+    pub fn add_diff(&mut self) {
+        self.has_diff = true;
+    }
+
+    // Forget that formatting would change some files, for modes where that is
+    // not a failure.
+    pub fn clear_diff(&mut self) {
+        self.has_diff = false;
+    }
+
+    // Record a formatted file and how many of its lines formatting changed.
+    pub fn add_file(&mut self, lines_changed: usize) {
+        self.files_processed += 1;
+        if lines_changed > 0 {
+            self.files_changed += 1;
+            self.lines_changed += lines_changed;
+        }
+    }
+
+    pub fn add_rewrite_failures(&mut self, count: usize) {
+        self.rewrite_failures += count;
+    }
+
+    // Record a crate which was skipped, the error itself is recorded
+    // separately.
+    pub fn add_failed_file(&mut self) {
+        self.files_failed += 1;
+    }
+
+    pub fn add(&mut self, other: Summary) {
+        self.has_operational_errors |= other.has_operational_errors;
+        self.has_parsing_errors |= other.has_parsing_errors;
+        self.has_internal_errors |= other.has_internal_errors;
+        self.has_diff |= other.has_diff;
+        self.files_processed += other.files_processed;
+        self.files_changed += other.files_changed;
+        self.lines_changed += other.lines_changed;
+        self.rewrite_failures += other.rewrite_failures;
+        self.files_failed += other.files_failed;
+    }
+
+    // Print the statistics for a run which took elapsed_ms, either for people
+    // or as a JSON object.
+    pub fn write_stats<T>(&self,
+                          mut out: T,
+                          elapsed_ms: u64,
+                          as_json: bool)
+                          -> Result<(), io::Error>
+        where T: Write
+    {
+        if as_json {
+            let stats = Stats {
+                files_processed: self.files_processed,
+                files_changed: self.files_changed,
+                lines_changed: self.lines_changed,
+                rewrite_failures: self.rewrite_failures,
+                files_failed: self.files_failed,
+                elapsed_ms: elapsed_ms,
+            };
+            return writeln!(out, "{}", json::as_pretty_json(&stats));
+        }
+
+        try!(writeln!(out,
+                      "Processed {} files in {}ms: {} changed ({} lines), {} rewrites failed",
+                      self.files_processed,
+                      elapsed_ms,
+                      self.files_changed,
+                      self.lines_changed,
+                      self.rewrite_failures));
+        if self.files_failed > 0 {
+            try!(writeln!(out, "{} files could not be formatted", self.files_failed));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn add_file_test() {
+    let mut summary = Summary::new();
+    summary.add_file(0);
+    summary.add_file(3);
+    let mut other = Summary::new();
+    other.add_file(2);
+    summary.add(other);
+
+    assert_eq!(summary.files_processed, 3);
+    assert_eq!(summary.files_changed, 2);
+    assert_eq!(summary.lines_changed, 5);
+}
@@ -11,15 +11,18 @@
 use std::fmt;
 
 use syntax::ast;
+use syntax::parse::token;
 use syntax::print::pprust;
 use syntax::codemap::{self, Span, BytePos, CodeMap};
 
-use lists::{itemize_list, write_list, ListTactic, SeparatorTactic, ListFormatting};
-use rewrite::{Rewrite, RewriteContext};
-use utils::{extra_offset, span_after};
+use lists::{itemize_list, write_list, ListItem, ListTactic, SeparatorPlace, SeparatorTactic,
+            ListFormatting};
+use rewrite::{Rewrite, RewriteContext, RewriteError, RewriteResult};
+use budget::{reserve, reserve_width};
+use utils::{borrowed_snippet, extra_offset, span_after};
 
 impl Rewrite for ast::Path {
-    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> Option<String> {
+    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> RewriteResult {
         rewrite_path(context, None, self, width, offset)
     }
 }
@@ -30,7 +33,7 @@ pub fn rewrite_path(context: &RewriteContext,
                     path: &ast::Path,
                     width: usize,
                     offset: usize)
-                    -> Option<String> {
+                    -> RewriteResult {
     let skip_count = qself.map(|x| x.position).unwrap_or(0);
 
     let mut result = if path.global {
@@ -46,24 +49,23 @@ pub fn rewrite_path(context: &RewriteContext,
         result.push_str(&pprust::ty_to_string(&qself.ty));
         result.push_str(" as ");
 
-        let extra_offset = extra_offset(&result, offset);
-        // 3 = ">::".len()
-        let budget = try_opt!(width.checked_sub(extra_offset)) - 3;
+        let extra_offset = extra_offset(&result, offset, context.config.tab_width);
+        let budget = try!(reserve_width(width, extra_offset).and_then(|w| reserve(w, ">::")));
 
-        result = try_opt!(rewrite_path_segments(result,
-                                                path.segments.iter().take(skip_count),
-                                                span_lo,
-                                                path.span.hi,
-                                                context,
-                                                budget,
-                                                offset + extra_offset));
+        result = try!(rewrite_path_segments(result,
+                                            path.segments.iter().take(skip_count),
+                                            span_lo,
+                                            path.span.hi,
+                                            context,
+                                            budget,
+                                            offset + extra_offset));
 
         result.push_str(">::");
         span_lo = qself.ty.span.hi + BytePos(1);
     }
 
-    let extra_offset = extra_offset(&result, offset);
-    let budget = try_opt!(width.checked_sub(extra_offset));
+    let extra_offset = extra_offset(&result, offset, context.config.tab_width);
+    let budget = try!(reserve_width(width, extra_offset));
     rewrite_path_segments(result,
                           path.segments.iter().skip(skip_count),
                           span_lo,
@@ -80,21 +82,21 @@ fn rewrite_path_segments<'a, I>(mut buffer: String,
                                 context: &RewriteContext,
                                 width: usize,
                                 offset: usize)
-                                -> Option<String>
+                                -> RewriteResult
     where I: Iterator<Item = &'a ast::PathSegment>
 {
     let mut first = true;
 
     for segment in iter {
-        let extra_offset = extra_offset(&buffer, offset);
-        let remaining_width = try_opt!(width.checked_sub(extra_offset));
+        let extra_offset = extra_offset(&buffer, offset, context.config.tab_width);
+        let remaining_width = try!(reserve_width(width, extra_offset));
         let new_offset = offset + extra_offset;
-        let segment_string = try_opt!(rewrite_segment(segment,
-                                                      &mut span_lo,
-                                                      span_hi,
-                                                      context,
-                                                      remaining_width,
-                                                      new_offset));
+        let segment_string = try!(rewrite_segment(segment,
+                                                  &mut span_lo,
+                                                  span_hi,
+                                                  context,
+                                                  remaining_width,
+                                                  new_offset));
 
         if first {
             first = false;
@@ -105,7 +107,7 @@ fn rewrite_path_segments<'a, I>(mut buffer: String,
         buffer.push_str(&segment_string);
     }
 
-    Some(buffer)
+    Ok(buffer)
 }
 
 enum SegmentParam<'a> {
@@ -140,6 +142,36 @@ impl<'a> fmt::Display for SegmentParam<'a> {
     }
 }
 
+impl<'a> Rewrite for SegmentParam<'a> {
+    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> RewriteResult {
+        match *self {
+            SegmentParam::LifeTime(..) => Ok(self.to_string()),
+            SegmentParam::Type(ref ty) => rewrite_ty(ty, context, width, offset),
+            SegmentParam::Binding(ref binding) => {
+                let prefix = format!("{} = ", binding.ident);
+                let budget = try!(reserve(width, &prefix));
+                let ty_str = try!(rewrite_ty(&binding.ty, context, budget, offset + prefix.len()));
+                Ok(prefix + &ty_str)
+            }
+        }
+    }
+}
+
+// Paths may have parameter lists of their own, which are fitted into the
+// width. Other types are printed as the compiler would.
+fn rewrite_ty(ty: &ast::Ty,
+              context: &RewriteContext,
+              width: usize,
+              offset: usize)
+              -> RewriteResult {
+    match ty.node {
+        ast::Ty_::TyPath(ref qself, ref path) => {
+            rewrite_path(context, qself.as_ref(), path, width, offset)
+        }
+        _ => Ok(pprust::ty_to_string(ty)),
+    }
+}
+
 // This is a dirty hack to determine if we're in an expression or not. Generic
 // parameters are passed differently in expressions and items. We'd declare
 // a struct with Foo<A, B>, but call its functions with Foo::<A, B>::f().
@@ -149,21 +181,22 @@ impl<'a> fmt::Display for SegmentParam<'a> {
 fn get_path_separator(codemap: &CodeMap,
                       path_start: BytePos,
                       segment_start: BytePos)
-                      -> &'static str {
+                      -> Result<&'static str, RewriteError> {
     let span = codemap::mk_sp(path_start, segment_start);
-    let snippet = codemap.span_to_snippet(span).unwrap();
+    let snippet = try!(borrowed_snippet(codemap, span).ok_or(RewriteError::NoSource));
 
     for c in snippet.chars().rev() {
         if c == ':' {
-            return "::"
+            return Ok("::");
         } else if c.is_whitespace() || c == '<' {
             continue;
         } else {
-            return "";
+            return Ok("");
         }
     }
 
-    unreachable!();
+    // Only a macro expansion leaves nothing but `<` and whitespace here.
+    Err(RewriteError::Macro)
 }
 
 // Formats a path segment. There are some hacks involved to correctly determine
@@ -182,9 +215,9 @@ fn rewrite_segment(segment: &ast::PathSegment,
                    context: &RewriteContext,
                    width: usize,
                    offset: usize)
-                   -> Option<String> {
-    let ident_len = segment.identifier.to_string().len();
-    let width = try_opt!(width.checked_sub(ident_len));
+                   -> RewriteResult {
+    let ident_len = token::get_ident(segment.identifier).len();
+    let width = try!(reserve_width(width, ident_len));
     let offset = offset + ident_len;
 
     let params = match segment.parameters {
@@ -200,8 +233,22 @@ fn rewrite_segment(segment: &ast::PathSegment,
                                            .collect::<Vec<_>>();
 
             let next_span_lo = param_list.last().unwrap().get_span().hi + BytePos(1);
-            let list_lo = span_after(codemap::mk_sp(*span_lo, span_hi), "<", context.codemap);
-            let separator = get_path_separator(context.codemap, *span_lo, list_lo);
+            // Parameters from macro expansions need not lie inside the path.
+            if next_span_lo <= *span_lo || next_span_lo > span_hi {
+                return Err(RewriteError::Macro);
+            }
+            let list_lo = try!(span_after(codemap::mk_sp(*span_lo, span_hi),
+                                          "<",
+                                          context.codemap));
+            let separator = try!(get_path_separator(context.codemap, *span_lo, list_lo));
+
+            // All items fit on the first line after `<` and before `>`, or
+            // they get a line each, ending with `,` or `>`, at the indent of
+            // the first. Both leave the same width for each item, so nested
+            // lists are rewritten with it.
+            let extra_offset = "<".len() + separator.len();
+            let list_width = try!(reserve_width(width, extra_offset).and_then(|w| reserve(w, ">")));
+            let list_offset = offset + extra_offset;
 
             let items = itemize_list(context.codemap,
                                      Vec::new(),
@@ -210,23 +257,23 @@ fn rewrite_segment(segment: &ast::PathSegment,
                                      ">",
                                      |param| param.get_span().lo,
                                      |param| param.get_span().hi,
-                                     ToString::to_string,
+                                     |param| {
+                                         param.rewrite(context, list_width, list_offset)
+                                              .unwrap_or_else(|_| param.to_string())
+                                     },
                                      list_lo,
                                      span_hi);
 
-            // 1 for <
-            let extra_offset = 1 + separator.len();
-            // 1 for >
-            let list_width = try_opt!(width.checked_sub(extra_offset + 1));
-
             let fmt = ListFormatting {
                 tactic: ListTactic::HorizontalVertical,
                 separator: ",",
-                trailing_separator: SeparatorTactic::Never,
-                indent: offset + extra_offset,
+                trailing_separator: context.config.trailing_comma,
+                separator_place: SeparatorPlace::Back,
+                indent: list_offset,
                 h_width: list_width,
                 v_width: list_width,
                 ends_with_newline: false,
+                config: context.config,
             };
 
             // update pos
@@ -237,10 +284,12 @@ fn rewrite_segment(segment: &ast::PathSegment,
         ast::PathParameters::ParenthesizedParameters(ref data) => {
             let output = match data.output {
                 Some(ref ty) => format!(" -> {}", pprust::ty_to_string(&*ty)),
-                None => String::new()
+                None => String::new(),
             };
 
-            let list_lo = span_after(codemap::mk_sp(*span_lo, span_hi), "(", context.codemap);
+            let list_lo = try!(span_after(codemap::mk_sp(*span_lo, span_hi),
+                                          "(",
+                                          context.codemap));
             let items = itemize_list(context.codemap,
                                      Vec::new(),
                                      data.inputs.iter(),
@@ -252,18 +301,18 @@ fn rewrite_segment(segment: &ast::PathSegment,
                                      list_lo,
                                      span_hi);
 
-            // 2 for ()
-            let budget = try_opt!(width.checked_sub(output.len() + 2));
+            let budget = try!(reserve(width, &output).and_then(|w| reserve(w, "()")));
 
             let fmt = ListFormatting {
                 tactic: ListTactic::HorizontalVertical,
                 separator: ",",
-                trailing_separator: SeparatorTactic::Never,
-                // 1 for (
-                indent: offset + 1,
+                trailing_separator: context.config.trailing_comma,
+                separator_place: SeparatorPlace::Back,
+                indent: offset + "(".len(),
                 h_width: budget,
                 v_width: budget,
                 ends_with_newline: false,
+                config: context.config,
             };
 
             // update pos
@@ -271,46 +320,45 @@ fn rewrite_segment(segment: &ast::PathSegment,
 
             format!("({}){}", write_list(&items, &fmt), output)
         }
-        _ => String::new()
+        _ => String::new(),
     };
 
-    Some(format!("{}{}", segment.identifier, params))
+    Ok(format!("{}{}", segment.identifier, params))
 }
 
 impl Rewrite for ast::WherePredicate {
-    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> Option<String> {
+    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> RewriteResult {
         // TODO dead spans?
         // TODO assumes we'll always fit on one line...
-        Some(match self {
+        Ok(match self {
             &ast::WherePredicate::BoundPredicate(ast::WhereBoundPredicate{ref bound_lifetimes,
                                                                           ref bounded_ty,
                                                                           ref bounds,
                                                                           ..}) => {
                 if bound_lifetimes.len() > 0 {
-                    let lifetime_str = bound_lifetimes.iter().map(|lt| {
-                                           lt.rewrite(context, width, offset).unwrap()
-                                       }).collect::<Vec<_>>().join(", ");
+                    let lifetime_strs = try!(bound_lifetimes.iter()
+                                                            .map(|lt| lt.rewrite(context,
+                                                                                 width,
+                                                                                 offset))
+                                                            .collect::<Result<Vec<_>, _>>());
+                    let lifetime_str = lifetime_strs.join(", ");
                     let type_str = pprust::ty_to_string(bounded_ty);
-                    // 8 = "for<> : ".len()
-                    let used_width = lifetime_str.len() + type_str.len() + 8;
-                    let bounds_str = bounds.iter().map(|ty_bound| {
-                                         ty_bound.rewrite(context,
-                                                          width - used_width,
-                                                          offset + used_width)
-                                                 .unwrap()
-                                     }).collect::<Vec<_>>().join(" + ");
+                    let used_width = lifetime_str.len() + type_str.len() + "for<> : ".len();
+                    let bounds_width = try!(reserve_width(width, used_width));
+                    let bounds_str = try!(rewrite_bounds(context,
+                                                         bounds,
+                                                         bounds_width,
+                                                         offset + used_width));
 
                     format!("for<{}> {}: {}", lifetime_str, type_str, bounds_str)
                 } else {
                     let type_str = pprust::ty_to_string(bounded_ty);
-                    // 2 = ": ".len()
-                    let used_width = type_str.len() + 2;
-                    let bounds_str = bounds.iter().map(|ty_bound| {
-                                         ty_bound.rewrite(context,
-                                                          width - used_width,
-                                                          offset + used_width)
-                                                 .unwrap()
-                                     }).collect::<Vec<_>>().join(" + ");
+                    let used_width = type_str.len() + ": ".len();
+                    let bounds_width = try!(reserve_width(width, used_width));
+                    let bounds_str = try!(rewrite_bounds(context,
+                                                         bounds,
+                                                         bounds_width,
+                                                         offset + used_width));
 
                     format!("{}: {}", type_str, bounds_str)
                 }
@@ -325,11 +373,10 @@ impl Rewrite for ast::WherePredicate {
             }
             &ast::WherePredicate::EqPredicate(ast::WhereEqPredicate{ref path, ref ty, ..}) => {
                 let ty_str = pprust::ty_to_string(ty);
-                // 3 = " = ".len()
-                let used_width = 3 + ty_str.len();
-                let path_str = try_opt!(path.rewrite(context,
-                                                     width - used_width,
-                                                     offset + used_width));
+                let used_width = " = ".len() + ty_str.len();
+                let path_str = try!(path.rewrite(context,
+                                                 try!(reserve_width(width, used_width)),
+                                                 offset + used_width));
                 format!("{} = {}", path_str, ty_str)
             }
         })
@@ -337,11 +384,11 @@ impl Rewrite for ast::WherePredicate {
 }
 
 impl Rewrite for ast::LifetimeDef {
-    fn rewrite(&self, _: &RewriteContext, _: usize, _: usize) -> Option<String> {
+    fn rewrite(&self, _: &RewriteContext, _: usize, _: usize) -> RewriteResult {
         if self.bounds.len() == 0 {
-            Some(pprust::lifetime_to_string(&self.lifetime))
+            Ok(pprust::lifetime_to_string(&self.lifetime))
         } else {
-            Some(format!("{}: {}",
+            Ok(format!("{}: {}",
                          pprust::lifetime_to_string(&self.lifetime),
                          self.bounds.iter().map(pprust::lifetime_to_string)
                                     .collect::<Vec<_>>().join(" + ")))
@@ -349,17 +396,44 @@ impl Rewrite for ast::LifetimeDef {
     }
 }
 
+// Joins bounds with ` + `, a line each if they don't fit on one, giving up
+// rather than panicking if one of them doesn't fit.
+fn rewrite_bounds(context: &RewriteContext,
+                  bounds: &[ast::TyParamBound],
+                  width: usize,
+                  offset: usize)
+                  -> RewriteResult {
+    let items = try!(bounds.iter()
+                           .map(|ty_bound| ty_bound.rewrite(context, width, offset))
+                           .map(|bound_str| bound_str.map(ListItem::from_str))
+                           .collect::<Result<Vec<_>, _>>());
+
+    let fmt = ListFormatting {
+        tactic: ListTactic::HorizontalVertical,
+        separator: "+",
+        trailing_separator: SeparatorTactic::Never,
+        separator_place: SeparatorPlace::Back,
+        indent: offset,
+        h_width: width,
+        v_width: width,
+        ends_with_newline: false,
+        config: context.config,
+    };
+    Ok(write_list(&items, &fmt))
+}
+
 impl Rewrite for ast::TyParamBound {
-    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> Option<String> {
+    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> RewriteResult {
         match *self {
             ast::TyParamBound::TraitTyParamBound(ref tref, ast::TraitBoundModifier::None) => {
                 tref.rewrite(context, width, offset)
             }
             ast::TyParamBound::TraitTyParamBound(ref tref, ast::TraitBoundModifier::Maybe) => {
-                Some(format!("?{}", try_opt!(tref.rewrite(context, width - 1, offset + 1))))
+                let tref_width = try!(reserve(width, "?"));
+                Ok(format!("?{}", try!(tref.rewrite(context, tref_width, offset + 1))))
             }
             ast::TyParamBound::RegionTyParamBound(ref l) => {
-                Some(pprust::lifetime_to_string(l))
+                Ok(pprust::lifetime_to_string(l))
             }
         }
     }
@@ -367,15 +441,13 @@ impl Rewrite for ast::TyParamBound {
 
 // FIXME: this assumes everything will fit on one line
 impl Rewrite for ast::TyParam {
-    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> Option<String> {
+    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> RewriteResult {
         let mut result = String::with_capacity(128);
         result.push_str(&self.ident.to_string());
         if self.bounds.len() > 0 {
             result.push_str(": ");
 
-            let bounds = self.bounds.iter().map(|ty_bound| {
-                ty_bound.rewrite(context, width, offset).unwrap()
-            }).collect::<Vec<_>>().join(" + ");
+            let bounds = try!(rewrite_bounds(context, &self.bounds, width, offset));
 
             result.push_str(&bounds);
         }
@@ -384,25 +456,26 @@ impl Rewrite for ast::TyParam {
             result.push_str(&pprust::ty_to_string(&def));
         }
 
-        Some(result)
+        Ok(result)
     }
 }
 
 // FIXME: this assumes everything will fit on one line
 impl Rewrite for ast::PolyTraitRef {
-    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> Option<String> {
+    fn rewrite(&self, context: &RewriteContext, width: usize, offset: usize) -> RewriteResult {
         if self.bound_lifetimes.len() > 0 {
-            let lifetime_str = self.bound_lifetimes.iter().map(|lt| {
-                lt.rewrite(context, width, offset).unwrap()
-            }).collect::<Vec<_>>().join(", ");
-            // 6 is "for<> ".len()
-            let extra_offset = lifetime_str.len() + 6;
-            let max_path_width = try_opt!(width.checked_sub(extra_offset));
-            let path_str = try_opt!(self.trait_ref.path.rewrite(context,
-                                                                max_path_width,
-                                                                offset + extra_offset));
-
-            Some(format!("for<{}> {}", lifetime_str, path_str))
+            let lifetime_strs = try!(self.bound_lifetimes
+                                         .iter()
+                                         .map(|lt| lt.rewrite(context, width, offset))
+                                         .collect::<Result<Vec<_>, _>>());
+            let lifetime_str = lifetime_strs.join(", ");
+            let extra_offset = lifetime_str.len() + "for<> ".len();
+            let max_path_width = try!(reserve_width(width, extra_offset));
+            let path_str = try!(self.trait_ref.path.rewrite(context,
+                                                            max_path_width,
+                                                            offset + extra_offset));
+
+            Ok(format!("for<{}> {}", lifetime_str, path_str))
         } else {
             self.trait_ref.path.rewrite(context, width, offset)
         }
@@ -8,28 +8,291 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::ops::Deref;
+use std::rc::Rc;
+
 use syntax::ast::{self, Visibility, Attribute, MetaItem, MetaItem_};
-use syntax::codemap::{CodeMap, Span, BytePos};
+use syntax::codemap::{CodeMap, Span, BytePos, Pos};
 
 use comment::FindUncommented;
+use rewrite::RewriteError;
+use libc;
+use unicode_width::UnicodeWidthChar;
 
 use SKIP_ANNOTATION;
 
-// Computes the length of a string's last line, minus offset.
+#[cfg(unix)]
+pub fn stdout_isatty() -> bool {
+    unsafe { libc::isatty(libc::STDOUT_FILENO) != 0 }
+}
+
+// FIXME detect the Windows console.
+#[cfg(not(unix))]
+pub fn stdout_isatty() -> bool {
+    false
+}
+
+// The length of the byte order mark at the start of text, if there is one.
+pub fn bom_len(text: &str) -> usize {
+    if text.starts_with("\u{feff}") {
+        "\u{feff}".len()
+    } else {
+        0
+    }
+}
+
+// The length of the `#!` interpreter line opening text, excluding the newline,
+// or zero if there is none. `#![` starts an inner attribute, not a shebang.
+pub fn shebang_len(text: &str) -> usize {
+    if text.starts_with("#!") && !text[2..].trim_left().starts_with("[") {
+        text.find('\n').unwrap_or(text.len())
+    } else {
+        0
+    }
+}
+
+// The number of columns c takes up on screen, which is what max_width
+// limits: two for wide characters, e.g., CJK ideographs, and tab_width for a
+// tab.
 #[inline]
-pub fn extra_offset(text: &str, offset: usize) -> usize {
+pub fn char_width(c: char, tab_width: usize) -> usize {
+    if c == '\t' {
+        tab_width
+    } else {
+        c.width().unwrap_or(0)
+    }
+}
+
+pub fn str_width(s: &str, tab_width: usize) -> usize {
+    s.chars().map(|c| char_width(c, tab_width)).fold(0, |a, b| a + b)
+}
+
+// What kind of code runs past column max_width on line, so that warnings
+// can say what needs fixing by hand or skipping. A rough guess from the text,
+// since by the time we check lines the AST is gone.
+pub fn overflow_kind(line: &str, max_width: usize, tab_width: usize) -> &'static str {
+    let mut width = 0;
+    let mut in_string = false;
+    let mut last = ' ';
+    for c in line.chars() {
+        width += char_width(c, tab_width);
+        if width > max_width {
+            break;
+        }
+        if in_string {
+            if c == '"' && last != '\\' {
+                in_string = false;
+            }
+            // An escaped backslash doesn't escape what follows it.
+            last = if last == '\\' { ' ' } else { c };
+            continue;
+        }
+        if last == '/' && (c == '/' || c == '*') {
+            return "comment";
+        }
+        if c == '"' {
+            in_string = true;
+        }
+        last = c;
+    }
+    if in_string {
+        return "string literal";
+    }
+
+    let line = line.trim_left();
+    if line.starts_with("//") || line.starts_with("/*") || line.starts_with("*") {
+        "comment"
+    } else if line.starts_with("#") {
+        "attribute"
+    } else if line.starts_with("use ") || line.starts_with("pub use ") {
+        "import"
+    } else if line.starts_with("fn ") || line.starts_with("pub fn ") ||
+              line.starts_with("unsafe fn ") || line.starts_with("pub unsafe fn ") {
+        "function signature"
+    } else {
+        "expression"
+    }
+}
+
+// Moves the lines of snippet after the first by the difference between the
+// column it started at in the source, from, and the one it starts at now, to,
+// so that code we couldn't format still lines up with its new surroundings.
+// Lines without enough indentation to take away are left alone, as is a
+// snippet with a line break inside a string literal, which moving would
+// change.
+pub fn reindent(snippet: &str, from: usize, to: usize) -> String {
+    if from == to || !snippet.contains('\n') || newline_in_string(snippet) {
+        return snippet.to_owned();
+    }
+
+    let mut result = String::with_capacity(snippet.len());
+    for (i, line) in snippet.split('\n').enumerate() {
+        if i == 0 {
+            result.push_str(line);
+            continue;
+        }
+        result.push('\n');
+        if line.trim().is_empty() {
+            result.push_str(line);
+        } else if to > from {
+            result.push_str(&make_indent(to - from));
+            result.push_str(line);
+        } else if line.chars().take(from - to).all(|c| c == ' ') && line.len() >= from - to {
+            result.push_str(&line[from - to..]);
+        } else {
+            result.push_str(line);
+        }
+    }
+    result
+}
+
+// Whether s has a line break inside a string literal, raw ones included.
+// Comments are skipped, so that quotes in them don't count.
+fn newline_in_string(s: &str) -> bool {
+    let chars: Vec<char> = s.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+        if c == '/' && next == Some('/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            // Block comments nest.
+            let mut depth = 1;
+            i += 2;
+            while i < chars.len() && depth > 0 {
+                if chars[i] == '/' && chars.get(i + 1) == Some(&'*') {
+                    depth += 1;
+                    i += 1;
+                } else if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+                    depth -= 1;
+                    i += 1;
+                }
+                i += 1;
+            }
+            continue;
+        } else if c == '"' && (i == 0 || chars[i - 1] != '\'') {
+            // Not the char literal '"'.
+            i += 1;
+            while i < chars.len() && chars[i] != '"' {
+                if chars[i] == '\\' {
+                    i += 1;
+                }
+                if chars.get(i) == Some(&'\n') {
+                    return true;
+                }
+                i += 1;
+            }
+        } else if c == 'r' && starts_raw_string(&chars, i) {
+            let hashes = chars[i + 1..].iter().take_while(|&&c| c == '#').count();
+            i += hashes + 2;
+            while i < chars.len() {
+                if chars[i] == '\n' {
+                    return true;
+                }
+                if chars[i] == '"' &&
+                   chars[i + 1..].iter().take_while(|&&c| c == '#').count() >= hashes {
+                    i += hashes;
+                    break;
+                }
+                i += 1;
+            }
+        }
+        i += 1;
+    }
+    false
+}
+
+// Whether the r at chars[i] starts a raw string, `r"`, `r#"` and so on, or a
+// raw byte string, rather than being part of an identifier.
+fn starts_raw_string(chars: &[char], i: usize) -> bool {
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+    let prefix_ok = match i {
+        0 => true,
+        1 => chars[0] == 'b' || !is_ident_char(chars[0]),
+        _ => {
+            !is_ident_char(chars[i - 1]) ||
+            (chars[i - 1] == 'b' && !is_ident_char(chars[i - 2]))
+        }
+    };
+    let hashes = chars[i + 1..].iter().take_while(|&&c| c == '#').count();
+    prefix_ok && chars.get(i + 1 + hashes) == Some(&'"')
+}
+
+// Replaces everything but whitespace with X, keeping the layout of s.
+pub fn mark_uncovered(s: &str) -> String {
+    s.chars().map(|c| if c.is_whitespace() { c } else { 'X' }).collect()
+}
+
+// Computes the width of a string's last line, minus offset.
+#[inline]
+pub fn extra_offset(text: &str, offset: usize, tab_width: usize) -> usize {
     match text.rfind('\n') {
-        // 1 for newline character
-        Some(idx) => text.len() - idx - 1 - offset,
-        None => text.len()
+        Some(idx) => {
+            str_width(&text[idx + "\n".len()..], tab_width).checked_sub(offset).unwrap_or(0)
+        }
+        None => str_width(text, tab_width),
+    }
+}
+
+// The source for a span, shared with the codemap's copy of the file rather
+// than copied out of it like span_to_snippet does.
+pub struct BorrowedSnippet {
+    src: Rc<String>,
+    lo: usize,
+    hi: usize,
+}
+
+impl Deref for BorrowedSnippet {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.src[self.lo..self.hi]
+    }
+}
+
+// None if span is not within a single file whose source we have.
+pub fn borrowed_snippet(codemap: &CodeMap, span: Span) -> Option<BorrowedSnippet> {
+    let lo = codemap.lookup_byte_offset(span.lo);
+    let hi = codemap.lookup_byte_offset(span.hi);
+    if lo.fm.start_pos != hi.fm.start_pos || hi.pos < lo.pos {
+        return None;
     }
+
+    lo.fm.src.as_ref().map(|src| {
+        BorrowedSnippet {
+            src: src.clone(),
+            lo: lo.pos.to_usize(),
+            hi: hi.pos.to_usize(),
+        }
+    })
 }
 
+// The position just past the first uncommented needle in original. Spans
+// from macro expansions need not contain it.
 #[inline]
-pub fn span_after(original: Span, needle: &str, codemap: &CodeMap) -> BytePos {
-    let snippet = codemap.span_to_snippet(original).unwrap();
+pub fn span_after(original: Span,
+                  needle: &str,
+                  codemap: &CodeMap)
+                  -> Result<BytePos, RewriteError> {
+    let snippet = try!(borrowed_snippet(codemap, original).ok_or(RewriteError::NoSource));
+    let index = try!(snippet.find_uncommented(needle).ok_or(RewriteError::Macro));
 
-    original.lo + BytePos(snippet.find_uncommented(needle).unwrap() as u32 + 1)
+    Ok(original.lo + BytePos(index as u32 + 1))
+}
+
+// The char starting at byte offset i, which must be on a char boundary.
+#[inline]
+pub fn char_at(s: &str, i: usize) -> char {
+    s[i..].chars().next().unwrap()
+}
+
+#[inline]
+fn is_char_boundary(s: &str, i: usize) -> bool {
+    // Continuation bytes of a multi-byte char look like 0b10xxxxxx.
+    i == s.len() || s.as_bytes()[i] & 0xC0 != 0x80
 }
 
 #[inline]
@@ -39,7 +302,7 @@ pub fn prev_char(s: &str, mut i: usize) -> usize {
     }
 
     i -= 1;
-    while !s.is_char_boundary(i) {
+    while !is_char_boundary(s, i) {
         i -= 1;
     }
     i
@@ -51,7 +314,7 @@ pub fn next_char(s: &str, mut i: usize) -> usize {
         return s.len();
     }
 
-    while !s.is_char_boundary(i) {
+    while !is_char_boundary(s, i) {
         i += 1;
     }
     i
@@ -70,7 +333,7 @@ pub fn make_indent(width: usize) -> String {
 pub fn format_visibility(vis: Visibility) -> &'static str {
     match vis {
         Visibility::Public => "pub ",
-        Visibility::Inherited => ""
+        Visibility::Inherited => "",
     }
 }
 
@@ -78,13 +341,20 @@ pub fn format_visibility(vis: Visibility) -> &'static str {
 pub fn format_mutability(mutability: ast::Mutability) -> &'static str {
     match mutability {
         ast::Mutability::MutMutable => "mut ",
-        ast::Mutability::MutImmutable => ""
+        ast::Mutability::MutImmutable => "",
     }
 }
 
 fn is_skip(meta_item: &MetaItem) -> bool {
     match meta_item.node {
         MetaItem_::MetaWord(ref s) => *s == SKIP_ANNOTATION,
+        // We can't evaluate the predicate, so `#[cfg_attr(pred, rustfmt_skip)]`
+        // skips the item under any configuration. `#[cfg_attr(rustfmt,
+        // rustfmt_skip)]` is the way to skip code without the compiler seeing
+        // an unknown attribute.
+        MetaItem_::MetaList(ref s, ref items) if *s == "cfg_attr" => {
+            items.iter().skip(1).any(|item| is_skip(item))
+        }
         _ => false,
     }
 }
@@ -129,7 +399,8 @@ pub fn round_up_to_power_of_two(mut x: usize) -> usize {
     x.wrapping_add(1)
 }
 
-// Macro for deriving implementations of Decodable for enums
+// Macro for deriving implementations of Decodable for enums, and listing their
+// variants
 #[macro_export]
 macro_rules! impl_enum_decodable {
     ( $e:ident, $( $x:ident ),* ) => {
@@ -144,6 +415,14 @@ macro_rules! impl_enum_decodable {
                 }
             }
         }
+
+        impl $e {
+            // The names of the variants, as they are written in a config.
+            pub fn variants() -> &'static [&'static str] {
+                const VARIANTS: &'static [&'static str] = &[$( stringify!($x) ),*];
+                VARIANTS
+            }
+        }
     };
 }
 
@@ -163,3 +442,93 @@ fn power_rounding() {
     assert_eq!(64, round_up_to_power_of_two(33));
     assert_eq!(256, round_up_to_power_of_two(256));
 }
+
+#[test]
+fn shebang_len_test() {
+    assert_eq!(shebang_len("#!/usr/bin/env run-cargo-script\nfn main() {}"), 31);
+    assert_eq!(shebang_len("#!/bin/sh"), 9);
+    assert_eq!(shebang_len("#![feature(rustc_private)]\n"), 0);
+    assert_eq!(shebang_len("#!  [allow(dead_code)]\n"), 0);
+    assert_eq!(shebang_len("fn main() {}\n"), 0);
+}
+
+#[test]
+fn bom_len_test() {
+    assert_eq!(bom_len("\u{feff}fn main() {}"), 3);
+    assert_eq!(bom_len("fn main() {}"), 0);
+}
+
+#[test]
+fn extra_offset_test() {
+    assert_eq!(extra_offset("foo(", 4, 4), 4);
+    assert_eq!(extra_offset("foo(\n        名前(", 4, 4), 9);
+}
+
+#[test]
+fn str_width_test() {
+    assert_eq!(str_width("foo(a, b)", 4), 9);
+    assert_eq!(str_width("\t// x", 4), 8);
+    assert_eq!(str_width("\t// x", 8), 12);
+    assert_eq!(str_width("let 名前 = 1;", 4), 13);
+    assert_eq!(str_width("e\u{301}", 4), 1);
+}
+
+#[test]
+fn is_skip_test() {
+    use syntax::attr;
+    use syntax::parse::token::InternedString;
+
+    let word = |s| attr::mk_word_item(InternedString::new(s));
+    let cfg_attr = |items| attr::mk_list_item(InternedString::new("cfg_attr"), items);
+
+    assert!(is_skip(&word("rustfmt_skip")));
+    assert!(!is_skip(&word("inline")));
+    assert!(is_skip(&cfg_attr(vec![word("rustfmt"), word("rustfmt_skip")])));
+    assert!(is_skip(&cfg_attr(vec![word("test"), word("inline"), word("rustfmt_skip")])));
+    assert!(!is_skip(&cfg_attr(vec![word("rustfmt"), word("inline")])));
+    // The predicate itself doesn't count.
+    assert!(!is_skip(&cfg_attr(vec![word("rustfmt_skip"), word("inline")])));
+}
+
+#[test]
+fn overflow_kind_test() {
+    assert_eq!(overflow_kind("    let x = foo(aaaa, bbbb);", 20, 4), "expression");
+    assert_eq!(overflow_kind("    let x = \"aaaa bbbb cccc\";", 20, 4), "string literal");
+    assert_eq!(overflow_kind("    let x = \"a\\\\\"; // bbbb cccc", 30, 4), "comment");
+    assert_eq!(overflow_kind("    // aaaa bbbb cccc dddd", 20, 4), "comment");
+    assert_eq!(overflow_kind("use aaaa::bbbb::{cccc, dddd};", 20, 4), "import");
+    assert_eq!(overflow_kind("pub fn aaaa(bbbb: u32, cccc: u32) {", 20, 4), "function signature");
+    assert_eq!(overflow_kind("#[derive(Debug, Clone, Copy)]", 20, 4), "attribute");
+}
+
+#[test]
+fn reindent_test() {
+    let snippet = "foo(a,\n    b,\n\n    c)";
+    assert_eq!(reindent(snippet, 4, 8), "foo(a,\n        b,\n\n        c)");
+    assert_eq!(reindent(snippet, 4, 0), "foo(a,\nb,\n\nc)");
+    // Not enough indentation to take away.
+    assert_eq!(reindent("foo(a,\n  b)", 4, 0), "foo(a,\n  b)");
+    assert_eq!(reindent("foo(\"a\n   b\")", 4, 8), "foo(\"a\n   b\")");
+    assert_eq!(reindent("foo('\"',\n    b)", 0, 4), "foo('\"',\n        b)");
+    // Raw strings are left alone too, and quotes in comments don't count.
+    let raw = "foo(r#\"a \" b\n   c\"#)";
+    assert_eq!(reindent(raw, 4, 8), raw);
+    assert_eq!(reindent("foo(br\"a\n   b\")", 4, 8), "foo(br\"a\n   b\")");
+    assert_eq!(reindent("foo(bar, // \"\n    b)", 0, 4), "foo(bar, // \"\n        b)");
+    assert_eq!(reindent("foo(/* \" */ a,\n    b)", 0, 4), "foo(/* \" */ a,\n        b)");
+    assert_eq!(reindent("foo(r,\n    b)", 0, 4), "foo(r,\n        b)");
+}
+
+#[test]
+fn mark_uncovered_test() {
+    assert_eq!(mark_uncovered("foo!(a, b);\n    x"), "XXXXXXX XXX\n    X");
+}
+
+#[test]
+fn borrowed_snippet_test() {
+    let codemap = CodeMap::new();
+    let filemap = codemap.new_filemap("a.rs".to_owned(), "fn main() {}".to_owned());
+    let span = ::syntax::codemap::mk_sp(filemap.start_pos + BytePos(3),
+                                        filemap.start_pos + BytePos(7));
+    assert_eq!(&*borrowed_snippet(&codemap, span).unwrap(), "main");
+}
@@ -8,15 +8,31 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::HashMap;
+use std::rc::Rc;
+use time;
+
 use syntax::ast;
-use syntax::codemap::{self, CodeMap, Span, BytePos};
+use syntax::codemap::{self, CodeMap, Span, BytePos, Pos};
 use syntax::visit;
 
 use strings::string_buffer::StringBuffer;
 
 use utils;
+use markdown;
+use budget::{saturating_reserve, saturating_reserve_width};
 use config::Config;
-use rewrite::{Rewrite, RewriteContext};
+use rewrite::{Rewrite, RewriteCache, RewriteContext, RewriteResult};
+use file_lines::LineRange;
+use profile::Profile;
+use stats::Stats;
+use expr;
+use items::{ItemKind, ItemSpacing};
+use macros;
+use reorder::TraitOrders;
+use {Plugins, RewriteFailure, RewriteEvent};
 
 pub struct FmtVisitor<'a> {
     pub codemap: &'a CodeMap,
@@ -24,7 +40,28 @@ pub struct FmtVisitor<'a> {
     pub last_pos: BytePos,
     // TODO RAII util for indenting
     pub block_indent: usize,
+    // How many blocks deep we are, zero between items at the top level.
+    pub block_depth: usize,
+    // The kind of the last item at this level and whether it was on one line,
+    // None if something else came after it.
+    pub last_item: Option<(ItemKind, bool)>,
+    // Blank lines to put in front of items the missed spans haven't reached
+    // yet, see items::ItemSpacing.
+    pub item_spacing: Vec<ItemSpacing>,
     pub config: &'a Config,
+    // Code we gave up on and left as it was.
+    pub failures: RefCell<Vec<RewriteFailure>>,
+    // The hook asked to approve each rewrite and the passes run on them, if
+    // set.
+    pub plugins: Option<&'a Plugins>,
+    // Shared with the visitors for nested blocks.
+    pub cache: RewriteCache,
+    // Also shared, see reorder::trait_orders.
+    pub trait_orders: TraitOrders,
+    // Also shared with nested visitors.
+    pub profile: Rc<RefCell<Profile>>,
+    // Not shared, since a nested block can be rewritten more than once.
+    pub stats: RefCell<Stats>,
 }
 
 impl<'a, 'v> visit::Visitor<'v> for FmtVisitor<'a> {
@@ -32,35 +69,19 @@ impl<'a, 'v> visit::Visitor<'v> for FmtVisitor<'a> {
         debug!("visit_expr: {:?} {:?}",
                self.codemap.lookup_char_pos(ex.span.lo),
                self.codemap.lookup_char_pos(ex.span.hi));
+        if self.is_out_of_order(ex.span) {
+            return;
+        }
         self.format_missing(ex.span.lo);
 
         let offset = self.buffer.cur_offset();
-        let rewrite = ex.rewrite(&self.get_context(), self.config.max_width - offset, offset);
-
-        if let Some(new_str) = rewrite {
-            self.buffer.push_str(&new_str);
-            self.last_pos = ex.span.hi;
-        }
+        let width = saturating_reserve_width(self.config.max_width, offset);
+        let rewrite = ex.rewrite(&self.get_context(), width, offset);
+        self.push_rewrite(expr::expr_kind(ex), ex.span, rewrite, offset);
     }
 
     fn visit_stmt(&mut self, stmt: &'v ast::Stmt) {
-        // If the stmt is actually an item, then we'll handle any missing spans
-        // there. This is important because of annotations.
-        // Although it might make more sense for the statement span to include
-        // any annotations on the item.
-        let skip_missing = match stmt.node {
-            ast::Stmt_::StmtDecl(ref decl, _) => {
-                match decl.node {
-                    ast::Decl_::DeclItem(_) => true,
-                    _ => false,
-                }
-            }
-            _ => false,
-        };
-        if !skip_missing {
-            self.format_missing_with_indent(stmt.span.lo);
-        }
-        visit::walk_stmt(self, stmt);
+        self.profiled("statement", |visitor| visitor.format_stmt(stmt))
     }
 
     fn visit_block(&mut self, b: &'v ast::Block) {
@@ -71,10 +92,9 @@ impl<'a, 'v> visit::Visitor<'v> for FmtVisitor<'a> {
         self.buffer.push_str("{");
         self.last_pos = self.last_pos + BytePos(1);
         self.block_indent += self.config.tab_spaces;
+        self.block_depth += 1;
 
-        for stmt in &b.stmts {
-            self.visit_stmt(&stmt)
-        }
+        self.visit_stmts(&b.stmts);
 
         match b.expr {
             Some(ref e) => {
@@ -84,9 +104,14 @@ impl<'a, 'v> visit::Visitor<'v> for FmtVisitor<'a> {
             None => {}
         }
 
+        // Comments after the last statement have nothing following them to
+        // be lined up with, so indent them with the block's contents.
+        self.push_indented_lines(b.span.hi - BytePos(1), is_comment_line);
+
         self.block_indent -= self.config.tab_spaces;
         // TODO we should compress any newlines here to just one
         self.format_missing_with_indent(b.span.hi - BytePos(1));
+        self.block_depth -= 1;
         self.buffer.push_str("}");
         self.last_pos = b.span.hi;
     }
@@ -103,6 +128,7 @@ impl<'a, 'v> visit::Visitor<'v> for FmtVisitor<'a> {
         self.last_pos = s.lo;
 
         let indent = self.block_indent;
+        let signature_span = codemap::mk_sp(s.lo, b.span.lo);
         match fk {
             visit::FkItemFn(ident,
                             ref generics,
@@ -110,30 +136,32 @@ impl<'a, 'v> visit::Visitor<'v> for FmtVisitor<'a> {
                             ref constness,
                             ref abi,
                             vis) => {
-                let new_fn = self.rewrite_fn(indent,
-                                             ident,
-                                             fd,
-                                             None,
-                                             generics,
-                                             unsafety,
-                                             constness,
-                                             abi,
-                                             vis,
-                                             codemap::mk_sp(s.lo, b.span.lo));
-                self.buffer.push_str(&new_fn);
+                self.push_item_rewrite("fn signature", signature_span, |visitor| {
+                    visitor.rewrite_fn(indent,
+                                       ident,
+                                       fd,
+                                       None,
+                                       generics,
+                                       unsafety,
+                                       constness,
+                                       abi,
+                                       vis,
+                                       signature_span)
+                });
             }
             visit::FkMethod(ident, ref sig, vis) => {
-                let new_fn = self.rewrite_fn(indent,
-                                             ident,
-                                             fd,
-                                             Some(&sig.explicit_self),
-                                             &sig.generics,
-                                             &sig.unsafety,
-                                             &sig.constness,
-                                             &sig.abi,
-                                             vis.unwrap_or(ast::Visibility::Inherited),
-                                             codemap::mk_sp(s.lo, b.span.lo));
-                self.buffer.push_str(&new_fn);
+                self.push_item_rewrite("fn signature", signature_span, |visitor| {
+                    visitor.rewrite_fn(indent,
+                                       ident,
+                                       fd,
+                                       Some(&sig.explicit_self),
+                                       &sig.generics,
+                                       &sig.unsafety,
+                                       &sig.constness,
+                                       &sig.abi,
+                                       vis.unwrap_or(ast::Visibility::Inherited),
+                                       signature_span)
+                });
             }
             visit::FkFnBlock(..) => {}
         }
@@ -143,6 +171,121 @@ impl<'a, 'v> visit::Visitor<'v> for FmtVisitor<'a> {
     }
 
     fn visit_item(&mut self, item: &'v ast::Item) {
+        let lo = attrs_lo(&item.attrs, item.span.lo);
+        let spacing = self.space_item(lo, item.span, ItemKind::of_item(item));
+        self.format_item(item);
+        self.last_item = Some(spacing);
+    }
+
+    fn visit_trait_item(&mut self, ti: &'v ast::TraitItem) {
+        let kind = match ti.node {
+            ast::TraitItem_::MethodTraitItem(..) => ItemKind::Fn,
+            _ => ItemKind::Other,
+        };
+        let spacing = self.space_item(attrs_lo(&ti.attrs, ti.span.lo), ti.span, kind);
+        self.format_trait_item(ti);
+        self.last_item = Some(spacing);
+    }
+
+    fn visit_impl_item(&mut self, ii: &'v ast::ImplItem) {
+        let kind = match ii.node {
+            ast::ImplItem_::MethodImplItem(..) => ItemKind::Fn,
+            _ => ItemKind::Other,
+        };
+        let spacing = self.space_item(attrs_lo(&ii.attrs, ii.span.lo), ii.span, kind);
+        self.format_impl_item(ii);
+        self.last_item = Some(spacing);
+    }
+
+    fn visit_mac(&mut self, mac: &'v ast::Mac) {
+        visit::walk_mac(self, mac)
+    }
+}
+
+impl<'a> FmtVisitor<'a> {
+    pub fn from_codemap<'b>(codemap: &'b CodeMap, config: &'b Config) -> FmtVisitor<'b> {
+        FmtVisitor {
+            codemap: codemap,
+            buffer: StringBuffer::new(),
+            last_pos: BytePos(0),
+            block_indent: 0,
+            block_depth: 0,
+            last_item: None,
+            item_spacing: Vec::new(),
+            config: config,
+            failures: RefCell::new(Vec::new()),
+            plugins: None,
+            cache: Rc::new(RefCell::new(HashMap::new())),
+            trait_orders: Rc::new(HashMap::new()),
+            profile: Rc::new(RefCell::new(Profile::new())),
+            stats: RefCell::new(Stats::new()),
+        }
+    }
+
+    fn format_stmt(&mut self, stmt: &ast::Stmt) {
+        // If the stmt is actually an item, then we'll handle any missing spans
+        // there. This is important because of annotations.
+        // Although it might make more sense for the statement span to include
+        // any annotations on the item.
+        let skip_missing = match stmt.node {
+            ast::Stmt_::StmtDecl(ref decl, _) => {
+                match decl.node {
+                    ast::Decl_::DeclItem(_) => true,
+                    _ => false,
+                }
+            }
+            _ => false,
+        };
+        if !skip_missing {
+            self.last_item = None;
+        }
+        if self.is_out_of_order(stmt.span) {
+            return;
+        }
+        if !self.in_file_lines(stmt.span) {
+            self.push_verbatim(stmt.span);
+            return;
+        }
+        if !skip_missing {
+            self.format_missing_with_indent(stmt.span.lo);
+        }
+        if let ast::Stmt_::StmtMac(ref mac, ..) = stmt.node {
+            if !macros::is_call_like(mac) {
+                self.push_verbatim(stmt.span);
+                return;
+            }
+            // Any semicolon is copied as missing code, as for expressions.
+            let offset = self.buffer.cur_offset();
+            let width = saturating_reserve(saturating_reserve_width(self.config.max_width, offset),
+                                           ";");
+            let rewrite = macros::rewrite_macro(mac, &self.get_context(), width, offset);
+            self.push_rewrite("macro call", mac.span, rewrite, offset);
+            return;
+        }
+        if let ast::Stmt_::StmtSemi(ref ex, _) = stmt.node {
+            if self.config.remove_redundant_semicolons && expr::is_unit_block_like(ex) {
+                self.visit_expr(ex);
+                // The semicolon is the rest of the statement, skip it.
+                let semicolon = self.snippet(codemap::mk_sp(ex.span.hi, stmt.span.hi));
+                if self.last_pos == ex.span.hi && semicolon.trim() == ";" {
+                    self.last_pos = stmt.span.hi;
+                }
+                return;
+            }
+        }
+        visit::walk_stmt(self, stmt);
+    }
+
+    fn format_item(&mut self, item: &ast::Item) {
+        let full_span = codemap::mk_sp(attrs_lo(&item.attrs, item.span.lo), item.span.hi);
+        if self.is_out_of_order(full_span) {
+            return;
+        }
+        if !self.in_file_lines(full_span) {
+            self.push_verbatim(item.span);
+            return;
+        }
+
         // Don't look at attributes for modules.
         // We want to avoid looking at attributes in another file, which the AST
         // doesn't distinguish. FIXME This is overly conservative and means we miss
@@ -158,12 +301,14 @@ impl<'a, 'v> visit::Visitor<'v> for FmtVisitor<'a> {
 
         match item.node {
             ast::Item_::ItemUse(ref vp) => {
-                self.format_import(item.vis, vp, item.span);
+                self.format_import(item.vis, vp, item.span, !item.attrs.is_empty());
             }
             ast::Item_::ItemImpl(..) |
             ast::Item_::ItemTrait(..) => {
                 self.block_indent += self.config.tab_spaces;
-                visit::walk_item(self, item);
+                if !self.push_impl_in_trait_order(item) {
+                    visit::walk_item(self, item);
+                }
                 self.block_indent -= self.config.tab_spaces;
             }
             ast::Item_::ItemExternCrate(_) => {
@@ -193,13 +338,22 @@ impl<'a, 'v> visit::Visitor<'v> for FmtVisitor<'a> {
                 self.format_missing_with_indent(item.span.lo);
                 self.format_mod(module, item.span, item.ident);
             }
+            ast::Item_::ItemMac(..) => {
+                self.format_missing_with_indent(item.span.lo);
+                self.push_item_mac(item.span);
+            }
             _ => {
                 visit::walk_item(self, item);
             }
         }
     }
 
-    fn visit_trait_item(&mut self, ti: &'v ast::TraitItem) {
+    fn format_trait_item(&mut self, ti: &ast::TraitItem) {
+        if !self.in_file_lines(ti.span) {
+            self.push_verbatim(ti.span);
+            return;
+        }
+
         if self.visit_attrs(&ti.attrs) {
             return;
         }
@@ -208,40 +362,25 @@ impl<'a, 'v> visit::Visitor<'v> for FmtVisitor<'a> {
             self.format_missing_with_indent(ti.span.lo);
 
             let indent = self.block_indent;
-            let new_fn = self.rewrite_required_fn(indent,
-                                                  ti.ident,
-                                                  sig,
-                                                  ti.span);
-
-            self.buffer.push_str(&new_fn);
-            self.last_pos = ti.span.hi;
+            self.push_item_rewrite("fn signature", ti.span, |visitor| {
+                visitor.rewrite_required_fn(indent, ti.ident, sig, ti.span)
+            });
         }
         // TODO format trait types
 
         visit::walk_trait_item(self, ti)
     }
 
-    fn visit_impl_item(&mut self, ii: &'v ast::ImplItem) {
-        if self.visit_attrs(&ii.attrs) {
+    fn format_impl_item(&mut self, ii: &ast::ImplItem) {
+        if !self.in_file_lines(ii.span) {
+            self.push_verbatim(ii.span);
             return;
         }
-        visit::walk_impl_item(self, ii)
-    }
 
-    fn visit_mac(&mut self, mac: &'v ast::Mac) {
-        visit::walk_mac(self, mac)
-    }
-}
-
-impl<'a> FmtVisitor<'a> {
-    pub fn from_codemap<'b>(codemap: &'b CodeMap, config: &'b Config) -> FmtVisitor<'b> {
-        FmtVisitor {
-            codemap: codemap,
-            buffer: StringBuffer::new(),
-            last_pos: BytePos(0),
-            block_indent: 0,
-            config: config,
+        if self.visit_attrs(&ii.attrs) {
+            return;
         }
+        visit::walk_impl_item(self, ii)
     }
 
     pub fn snippet(&self, span: Span) -> String {
@@ -256,6 +395,192 @@ impl<'a> FmtVisitor<'a> {
         }
     }
 
+    // Returns true if span overlaps the lines we have been asked to format.
+    pub fn in_file_lines(&self, span: Span) -> bool {
+        if self.config.file_lines.is_all() {
+            return true;
+        }
+
+        let lo = self.codemap.lookup_char_pos(span.lo);
+        let hi = self.codemap.lookup_char_pos(span.hi);
+        let range = LineRange { lo: lo.line, hi: hi.line };
+        self.config.file_lines.intersects(&lo.file.name, range)
+    }
+
+    // Asks the hook, if any, whether to replace the code in span with
+    // proposed.
+    pub fn accept_rewrite(&self, kind: &str, span: Span, proposed: &str) -> bool {
+        match self.plugins.and_then(|plugins| plugins.hook.as_ref()) {
+            Some(hook) => self.with_event(kind, span, proposed, |event| hook(event)),
+            None => true,
+        }
+    }
+
+    // Runs the passes for kind, if any, on rustfmt's rewrite of the code in
+    // span.
+    pub fn run_passes(&self, kind: &str, span: Span, rewrite: String) -> String {
+        let plugins = match self.plugins {
+            Some(plugins) => plugins,
+            None => return rewrite,
+        };
+
+        let mut result = rewrite;
+        for &(ref pass_kind, ref pass) in &plugins.passes {
+            if pass_kind != kind {
+                continue;
+            }
+            let config = self.config;
+            if let Some(new_result) = self.with_event(kind, span, &result, |e| pass(e, config)) {
+                result = new_result;
+            }
+        }
+        result
+    }
+
+    fn with_event<F, T>(&self, kind: &str, span: Span, proposed: &str, f: F) -> T
+        where F: FnOnce(&RewriteEvent) -> T
+    {
+        let lo = self.codemap.lookup_char_pos(span.lo);
+        let hi = self.codemap.lookup_char_pos(span.hi);
+        let original = self.snippet(span);
+        f(&RewriteEvent {
+            kind: kind,
+            file: &lo.file.name,
+            lo_line: lo.line,
+            lo_col: lo.col.0 + 1,
+            hi_line: hi.line,
+            hi_col: hi.col.0 + 1,
+            original: &original,
+            proposed: proposed,
+        })
+    }
+
+    // With config.stats, counts a rewrite of the code in span as kind, noting
+    // whether it changed anything.
+    pub fn count_rewrite(&self, kind: &str, span: Span, rewrite: &str) {
+        if self.config.stats {
+            let changed = self.snippet(span).trim() != rewrite.trim();
+            self.stats.borrow_mut().add(kind, changed);
+        }
+    }
+
+    // Notes that the code in span is being left as it was.
+    pub fn record_failure(&self, span: Span, reason: &str) {
+        let lo = self.codemap.lookup_char_pos(span.lo);
+        let hi = self.codemap.lookup_char_pos(span.hi);
+        self.failures.borrow_mut().push(RewriteFailure {
+            file: lo.file.name.clone(),
+            lo_line: lo.line,
+            lo_col: lo.col.0 + 1,
+            hi_line: hi.line,
+            hi_col: hi.col.0 + 1,
+            reason: reason.to_owned(),
+        });
+    }
+
+    // Writes out the rewrite of the code of kind in span, which starts at
+    // column offset, or the original code if the rewrite failed.
+    pub fn push_rewrite(&mut self, kind: &str, span: Span, rewrite: RewriteResult, offset: usize) {
+        match rewrite {
+            Ok(new_str) => {
+                let new_str = self.run_passes(kind, span, new_str);
+                // If the hook vetoes the rewrite, the original is copied
+                // through as missing code.
+                if self.accept_rewrite(kind, span, &new_str) {
+                    self.count_rewrite(kind, span, &new_str);
+                    self.buffer.push_str(&new_str);
+                    self.last_pos = span.hi;
+                }
+            }
+            Err(e) => {
+                self.record_failure(span, &format!("could not format expression: {}", e));
+                self.push_fallback(span, offset);
+            }
+        }
+    }
+
+    // Rewrites the item code of kind in span with rewrite and writes out the
+    // result, or the original code if the rewrite failed or the hook vetoed
+    // it.
+    pub fn push_item_rewrite<F>(&mut self, kind: &str, span: Span, rewrite: F)
+        where F: FnOnce(&mut FmtVisitor<'a>) -> RewriteResult
+    {
+        match self.profiled(kind, rewrite) {
+            Ok(new_str) => {
+                let new_str = self.run_passes(kind, span, new_str);
+                if self.accept_rewrite(kind, span, &new_str) {
+                    self.count_rewrite(kind, span, &new_str);
+                    self.buffer.push_str(&new_str);
+                    self.last_pos = span.hi;
+                } else {
+                    self.push_verbatim(span);
+                }
+            }
+            Err(e) => {
+                self.record_failure(span, &format!("could not format {}: {}", kind, e));
+                self.push_verbatim(span);
+            }
+        }
+    }
+
+    // Runs f, which rewrites code of kind, adding the time it takes to the
+    // profile if the config asks for one.
+    pub fn profiled<T, F>(&mut self, kind: &str, f: F) -> T
+        where F: FnOnce(&mut FmtVisitor<'a>) -> T
+    {
+        if !self.config.profile {
+            return f(self);
+        }
+        let start = time::precise_time_ns();
+        let result = f(self);
+        let phase = format!("rewrite {}", kind);
+        self.profile.borrow_mut().add(&phase, time::precise_time_ns() - start);
+        result
+    }
+
+    // Copies the source for span, which we couldn't format, into the buffer at
+    // column offset, see utils::reindent.
+    pub fn push_fallback(&mut self, span: Span, offset: usize) {
+        let from = self.codemap.lookup_char_pos(span.lo).col.to_usize();
+        let mut snippet = utils::reindent(&self.snippet(span), from, offset);
+        if self.config.coverage {
+            snippet = utils::mark_uncovered(&snippet);
+        }
+        self.buffer.push_str(&snippet);
+        self.last_pos = span.hi;
+    }
+
+    // The body of a macro need not be valid Rust, so we leave it exactly as
+    // written, comments and whitespace included. Only the semicolon which
+    // macros without braces need is normalized, to come straight after them.
+    fn push_item_mac(&mut self, span: Span) {
+        let snippet = self.snippet(span);
+        if snippet.ends_with('}') {
+            self.push_verbatim(span);
+            return;
+        }
+
+        let mut mac = format!("{};", snippet.trim_right_matches(';').trim_right());
+        if self.config.coverage {
+            mac = utils::mark_uncovered(&mac);
+        }
+        self.buffer.push_str(&mac);
+        self.last_pos = span.hi;
+    }
+
+    // Copies the source up to the end of span into the buffer unchanged.
+    pub fn push_verbatim(&mut self, span: Span) {
+        if self.last_pos >= span.hi {
+            return;
+        }
+        let mut snippet = self.snippet(codemap::mk_sp(self.last_pos, span.hi));
+        if self.config.coverage {
+            snippet = utils::mark_uncovered(&snippet);
+        }
+        self.buffer.push_str(&snippet);
+        self.last_pos = span.hi;
+    }
+
     // Returns true if we should skip the following item.
     pub fn visit_attrs(&mut self, attrs: &[ast::Attribute]) -> bool {
         if attrs.len() == 0 {
@@ -278,12 +603,17 @@ impl<'a> FmtVisitor<'a> {
 
     pub fn rewrite_attrs(&self, attrs: &[ast::Attribute], indent: usize) -> String {
         let mut result = String::new();
+        let rewrites = self.rewrite_doc_comments(attrs, indent);
         let indent = utils::make_indent(indent);
 
         for (i, a) in attrs.iter().enumerate() {
-            let a_str = self.snippet(a.span);
+            let a_str = match rewrites[i] {
+                Some(ref a_str) => a_str,
+                None => continue,
+            };
 
             if i > 0 {
+                result.push('\n');
                 let comment = self.snippet(codemap::mk_sp(attrs[i-1].span.hi, a.span.lo));
                 // This particular horror show is to preserve line breaks in between doc
                 // comments. An alternative would be to force such line breaks to start
@@ -300,13 +630,78 @@ impl<'a> FmtVisitor<'a> {
                 result.push_str(&indent);
             }
 
-            result.push_str(&a_str);
+            result.push_str(a_str);
+        }
 
-            if i < attrs.len() - 1 {
-                result.push('\n');
-            }
+        result
+    }
+
+    // The text of each attribute. With format_code_in_doc_comments, the code
+    // examples in each run of `///` comments are formatted, and the first
+    // comment of a run whose text changes stands for the whole run, the others
+    // are then None.
+    fn rewrite_doc_comments(&self, attrs: &[ast::Attribute], indent: usize) -> Vec<Option<String>> {
+        let mut result: Vec<_> = attrs.iter().map(|a| Some(self.snippet(a.span))).collect();
+        if !self.config.format_code_in_doc_comments {
+            return result;
         }
 
+        let is_doc = |s: &Option<String>| {
+            s.as_ref().map_or(false, |s| s.starts_with("///") && !s.starts_with("////"))
+        };
+        let mut i = 0;
+        while i < attrs.len() {
+            if !is_doc(&result[i]) {
+                i += 1;
+                continue;
+            }
+            // A run ends at a blank line or a comment between attributes.
+            let mut end = i + 1;
+            while end < attrs.len() && is_doc(&result[end]) {
+                let between = self.snippet(codemap::mk_sp(attrs[end - 1].span.hi,
+                                                          attrs[end].span.lo));
+                if !between.trim().is_empty() || between.matches('\n').count() > 1 {
+                    break;
+                }
+                end += 1;
+            }
+
+            let text = {
+                let lines: Vec<&str> = result[i..end]
+                                           .iter()
+                                           .map(|s| {
+                                               let s = &s.as_ref().unwrap()[3..];
+                                               if s.starts_with(' ') {
+                                                   &s[1..]
+                                               } else {
+                                                   s
+                                               }
+                                           })
+                                           .collect();
+                lines.join("\n")
+            };
+            let mut config = self.config.clone();
+            config.max_width = config.max_width.checked_sub(indent + 4).unwrap_or(0);
+            let formatted = markdown::format_doc_markdown(&text, &config);
+
+            if formatted != text {
+                let line_indent = format!("\n{}", utils::make_indent(indent));
+                let lines: Vec<String> = formatted.lines()
+                                                  .map(|line| {
+                                                      if line.is_empty() {
+                                                          "///".to_owned()
+                                                      } else {
+                                                          format!("/// {}", line)
+                                                      }
+                                                  })
+                                                  .collect();
+                result[i] = Some(lines.join(&line_indent));
+                for rewrite in &mut result[i + 1..end] {
+                    *rewrite = None;
+                }
+            }
+            i = end;
+        }
         result
     }
 
@@ -322,7 +717,8 @@ impl<'a> FmtVisitor<'a> {
         if is_internal {
             debug!("FmtVisitor::format_mod: internal mod");
             self.block_indent += self.config.tab_spaces;
-            visit::walk_mod(self, m);
+            self.format_mod_header(m);
+            self.visit_mod_items(m);
             debug!("... last_pos after: {:?}", self.last_pos);
             self.block_indent -= self.config.tab_spaces;
         }
@@ -332,48 +728,180 @@ impl<'a> FmtVisitor<'a> {
         let filemap = self.codemap.get_filemap(filename);
         self.last_pos = filemap.start_pos;
         self.block_indent = 0;
-        visit::walk_mod(self, m);
+
+        // A byte order mark is put back, if wanted, once the file is formatted.
+        let bom_len = filemap.src.as_ref().map_or(0, |src| utils::bom_len(src));
+        self.last_pos = self.last_pos + BytePos(bom_len as u32);
+
+        // The shebang is not part of the AST, so we copy it across first to
+        // be sure it stays on the first line.
+        let shebang_len = filemap.src.as_ref().map_or(0, |src| utils::shebang_len(&src[bom_len..]));
+        if shebang_len > 0 {
+            let shebang_end = self.last_pos + BytePos(shebang_len as u32);
+            let shebang = self.snippet(codemap::mk_sp(self.last_pos, shebang_end));
+            self.buffer.push_str(&shebang);
+            self.last_pos = shebang_end;
+        }
+
+        self.format_mod_header(m);
+        self.visit_mod_items(m);
+        self.push_indented_lines(filemap.end_pos, is_comment_line);
         self.format_missing(filemap.end_pos);
     }
 
-    fn format_import(&mut self, vis: ast::Visibility, vp: &ast::ViewPath, span: Span) {
+    // Inner doc comments and attributes (`//!`, `#![..]`) before the first
+    // item belong to the module rather than to that item. We emit them here,
+    // indented to the module's contents, so the first item's missed span does
+    // not carry them along. Doc comments are markdown, so they are never
+    // wrapped.
+    fn format_mod_header(&mut self, m: &ast::Mod) {
+        let header_end = match m.items.first() {
+            // Inner attributes of an external mod live in another file, so
+            // only look at attributes in front of the item.
+            Some(item) => item.attrs
+                              .iter()
+                              .map(|attr| attr.span.lo)
+                              .filter(|&lo| lo >= self.last_pos && lo < item.span.lo)
+                              .fold(item.span.lo, cmp::min),
+            None => return,
+        };
+        if header_end <= self.last_pos {
+            return;
+        }
+
+        self.push_indented_lines(header_end, is_mod_header_line);
+    }
+
+    // Copies the missed span up to `end` through to the end of the last line
+    // matching `reindent`, indenting the matching lines to the current block
+    // indent. The first line continues whatever came before it, so it is left
+    // where it is.
+    fn push_indented_lines<F: Fn(&str) -> bool>(&mut self, end: BytePos, reindent: F) {
+        if self.config.coverage || end <= self.last_pos {
+            return;
+        }
+
+        let snippet = self.snippet(codemap::mk_sp(self.last_pos, end));
+        let mut last = 0;
+        let mut pos = 0;
+        for line in snippet.split('\n') {
+            if reindent(line.trim()) {
+                last = pos + line.len();
+            }
+            pos += line.len() + 1;
+        }
+        if last == 0 {
+            return;
+        }
+
+        let indent = utils::make_indent(self.block_indent);
+        let lines = snippet[..last].split('\n').enumerate().map(|(i, line)| {
+            let trimmed = line.trim();
+            if i > 0 && reindent(trimmed) {
+                format!("{}{}", indent, trimmed)
+            } else {
+                line.trim_right().to_owned()
+            }
+        });
+        self.buffer.push_str(&lines.collect::<Vec<_>>().join("\n"));
+        self.last_pos = self.last_pos + BytePos(last as u32);
+    }
+
+    fn format_import(&mut self,
+                     vis: ast::Visibility,
+                     vp: &ast::ViewPath,
+                     span: Span,
+                     has_attrs: bool) {
         let vis = utils::format_visibility(vis);
         let offset = self.block_indent + vis.len() + "use ".len();
-        let context = RewriteContext {
-            codemap: self.codemap,
-            config: self.config,
-            block_indent: self.block_indent,
-        };
-        // 1 = ";"
-        match vp.rewrite(&context, self.config.max_width - offset - 1, offset) {
-            Some(ref s) if s.len() == 0 => {
+        let width = saturating_reserve(saturating_reserve_width(self.config.max_width, offset),
+                                       ";");
+        let rewrite = self.profiled("import",
+                                    |visitor| vp.rewrite(&visitor.get_context(), width, offset));
+        // An empty rewrite means the import is removed.
+        let rewrite = rewrite.map(|s| {
+            if s.len() == 0 {
+                s
+            } else {
+                self.run_passes("import", span, format!("{}use {};", vis, s))
+            }
+        });
+        match rewrite {
+            Ok(ref s) if !self.accept_rewrite("import", span, s) => {
+                self.format_missing_with_indent(span.lo);
+                self.format_missing(span.hi);
+            }
+            // Removing an import which carries attributes (e.g., `#[cfg(..)]`)
+            // would leave them dangling on the following item.
+            Ok(ref s) if s.len() == 0 && has_attrs => {
+                self.format_missing_with_indent(span.lo);
+                self.format_missing(span.hi);
+            }
+            Ok(ref s) if s.len() == 0 => {
                 // Format up to last newline
                 let prev_span = codemap::mk_sp(self.last_pos, span.lo);
                 let span_end = match self.snippet(prev_span).rfind('\n') {
                     Some(offset) => self.last_pos + BytePos(offset as u32),
-                    None => span.lo
+                    None => span.lo,
                 };
                 self.format_missing(span_end);
                 self.last_pos = span.hi;
             }
-            Some(ref s) => {
-                let s = format!("{}use {};", vis, s);
+            Ok(ref s) => {
                 self.format_missing_with_indent(span.lo);
-                self.buffer.push_str(&s);
+                self.count_rewrite("import", span, s);
+                self.buffer.push_str(s);
                 self.last_pos = span.hi;
             }
-            None => {
+            Err(e) => {
+                self.record_failure(span, &format!("could not format import: {}", e));
                 self.format_missing_with_indent(span.lo);
                 self.format_missing(span.hi);
             }
         }
     }
 
+    // Spans from macro expansions can run backwards or overlap text we have
+    // already written. Such nodes are left alone: whatever of their source has
+    // not been written yet is copied by the next missed span.
+    pub fn is_out_of_order(&self, span: Span) -> bool {
+        let out_of_order = span.hi < span.lo || span.lo < self.last_pos;
+        if out_of_order {
+            debug!("skipping out of order span: {:?} to {:?}, last_pos: {:?}",
+                   self.codemap.lookup_char_pos(span.lo),
+                   self.codemap.lookup_char_pos(span.hi),
+                   self.codemap.lookup_char_pos(self.last_pos));
+        }
+        out_of_order
+    }
+
     pub fn get_context(&self) -> RewriteContext {
         RewriteContext {
             codemap: self.codemap,
             config: self.config,
             block_indent: self.block_indent,
+            stmt_indent: self.block_indent,
+            failures: &self.failures,
+            plugins: self.plugins,
+            cache: &self.cache,
+            trait_orders: &self.trait_orders,
+            profile: &self.profile,
         }
     }
 }
+
+// Single line inner doc comments and attributes. Multi-line ones are left to
+// the missed span handling.
+fn is_mod_header_line(line: &str) -> bool {
+    line.starts_with("//!") || (line.starts_with("#![") && line.ends_with("]"))
+}
+
+fn is_comment_line(line: &str) -> bool {
+    line.starts_with("//") || (line.starts_with("/*") && line.ends_with("*/"))
+}
+
+// The start of the first of attrs in front of an item starting at lo. The
+// inner attributes of an external mod are in another file.
+pub fn attrs_lo(attrs: &[ast::Attribute], lo: BytePos) -> BytePos {
+    attrs.iter().map(|attr| attr.span.lo).filter(|&attr_lo| attr_lo < lo).fold(lo, cmp::min)
+}
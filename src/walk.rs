@@ -0,0 +1,135 @@
+// Copyright 2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Finding Rust source files in a directory tree.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+use config::{Config, Verbosity};
+use gitignore::{self, GitIgnore};
+
+/// Recursively list all `.rs` files under `root`, skipping anything matched by
+/// the `ignore` config option and, if `default_ignores` is set, hidden files,
+/// `target` directories and .gitignore'd files. Symlinked directories are not
+/// followed. The result is sorted so that runs are deterministic.
+pub fn list_rust_files(root: &Path, config: &Config) -> Result<Vec<PathBuf>, io::Error> {
+    let mut result = Vec::new();
+    let mut gitignores = Vec::new();
+    try!(walk_dir(root, config, &mut gitignores, &mut result));
+    result.sort();
+    Ok(result)
+}
+
+// gitignores holds the .gitignore files of the directories we are inside.
+fn walk_dir(dir: &Path,
+            config: &Config,
+            gitignores: &mut Vec<GitIgnore>,
+            result: &mut Vec<PathBuf>)
+            -> Result<(), io::Error> {
+    let gitignore = if config.default_ignores {
+        GitIgnore::from_dir(dir)
+    } else {
+        None
+    };
+    let pushed_gitignore = gitignore.is_some();
+    if let Some(gitignore) = gitignore {
+        gitignores.push(gitignore);
+    }
+
+    for entry in try!(fs::read_dir(dir)) {
+        let path = try!(entry).path();
+        let metadata = try!(fs::symlink_metadata(&path));
+        let is_dir = metadata.is_dir();
+        let is_target = is_dir && path.file_name() == Some(OsStr::new("target"));
+
+        let skip_reason = if is_ignored(&path, config) {
+            Some("ignored")
+        } else if config.default_ignores && is_hidden(&path) {
+            Some("hidden")
+        } else if config.default_ignores && is_target {
+            Some("target directory")
+        } else if config.default_ignores && is_gitignored(&path, is_dir, gitignores) {
+            Some("matched by .gitignore")
+        } else {
+            None
+        };
+        if let Some(reason) = skip_reason {
+            if config.verbosity == Verbosity::Verbose {
+                let _ = writeln!(io::stderr(), "Skipping {}: {}", path.display(), reason);
+            }
+            continue;
+        }
+
+        if is_dir {
+            try!(walk_dir(&path, config, gitignores, result));
+        } else if is_rust_file(&path) {
+            result.push(path);
+        } else if config.verbosity == Verbosity::Verbose {
+            let _ = writeln!(io::stderr(), "Skipping {}: not a Rust file", path.display());
+        }
+    }
+
+    if pushed_gitignore {
+        gitignores.pop();
+    }
+    Ok(())
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name().and_then(|name| name.to_str()).map_or(false, |name| name.starts_with("."))
+}
+
+// The innermost .gitignore with an opinion decides.
+fn is_gitignored(path: &Path, is_dir: bool, gitignores: &[GitIgnore]) -> bool {
+    for gitignore in gitignores.iter().rev() {
+        match gitignore.matches(path, is_dir) {
+            gitignore::Match::Ignore => return true,
+            gitignore::Match::Include => return false,
+            gitignore::Match::None => {}
+        }
+    }
+    false
+}
+
+fn is_rust_file(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()) == Some("rs")
+}
+
+pub fn is_ignored(path: &Path, config: &Config) -> bool {
+    let path = without_cur_dir(path);
+    config.ignore.iter().any(|pattern| {
+        let pattern = without_cur_dir(Path::new(pattern));
+        path.starts_with(&pattern) || path.file_name() == Some(pattern.as_os_str())
+    })
+}
+
+// path without any `.` components, so that `./src` starts with `src`.
+fn without_cur_dir(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .map(|component| component.as_os_str())
+        .collect()
+}
+
+#[test]
+fn ignored_paths() {
+    let mut config = Config::from_toml(include_str!("default.toml"));
+    config.ignore = vec!["target".to_owned(), "src/gen".to_owned()];
+
+    assert!(is_ignored(Path::new("target"), &config));
+    assert!(is_ignored(Path::new("foo/target"), &config));
+    assert!(is_ignored(Path::new("src/gen/a.rs"), &config));
+    assert!(is_ignored(Path::new("./src/gen/a.rs"), &config));
+    assert!(!is_ignored(Path::new("src/generated.rs"), &config));
+    assert!(!is_ignored(Path::new("src/lib.rs"), &config));
+}
@@ -0,0 +1,23 @@
+// rustfmt-config: align_bindings.toml
+
+fn main() {
+    let a = 1;
+    let abcde: u32 = 2;
+    let mut xy = a+abcde;
+
+    const MAX: usize = 10;
+    const MIN_VALUE: usize = 0;
+    let after_consts = 5;
+
+    // A blank line or a comment ends a group.
+    let single = 3;
+    // comment
+    let other = 4;
+
+    // So does a line which would run past max_width.
+    let short = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+    let the_longest_name_in_this_group_which_pushes_values_out = 1;
+    let not_first = 2;
+    let uninit;
+    let x = 1;
+}
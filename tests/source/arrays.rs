@@ -0,0 +1,5 @@
+fn main() {
+    let a = [1,2,  3];
+    let primes = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113];
+    let names = [first_long_name(), second_long_name(), third_long_name(), fourth_long_name(), fifth_long_name()];
+}
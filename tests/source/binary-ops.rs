@@ -0,0 +1,12 @@
+// Spacing around binary and assignment operators
+
+fn main() {
+    let a = b+c*d;
+    let x = x==y && z!=w;
+    let shifted = g<<2>>1;
+    let cmp = a<b || c>=d;
+    a+=1;
+    b = c<d;
+    flags|=MASK;
+    let v: Vec<Vec<u8>> = Vec::new();
+}
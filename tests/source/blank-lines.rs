@@ -0,0 +1,25 @@
+// Runs of blank lines inside blocks are cut down to max_blank_lines.
+
+
+fn main() {
+    let a = 1;
+
+
+
+    let b = 2;
+
+
+    // A comment.
+
+
+    let c = "multi
+
+
+line";
+    /* Blank lines in comments
+
+
+
+       are left alone. */
+    let d = 4;
+}
@@ -0,0 +1,7 @@
+// Box expressions and patterns
+
+fn main() {
+    let x = box   5;
+
+    if let box   Some(z) = w {}
+}
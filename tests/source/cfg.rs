@@ -0,0 +1,32 @@
+// Code under `cfg` is formatted but never dropped, whichever configuration
+// rustfmt happens to run under.
+
+#[cfg(windows)]
+use std::os::windows::{};
+use std::io;
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn   unix_only()   {
+    let   x = 1;
+}
+
+#[cfg(feature = "nightly")]
+#[allow(dead_code)]
+struct   Gated   {
+    a:   u32,
+}
+
+#[cfg_attr(feature = "nightly", rustfmt_skip)]
+fn   keep_me   ( ) { }
+
+#[cfg_attr(rustfmt, rustfmt_skip)]
+const   MATRIX : [u8; 4] = [1, 0,
+                            0, 1];
+
+fn main() {
+    #[cfg(debug_assertions)]
+    fn   helper() {
+        check( );
+    }
+    linux_only!(  a ,b );
+}
@@ -0,0 +1,20 @@
+// rustfmt-config: closure_block_indent.toml
+// Closure bodies indented one level in from the statement they are in.
+
+fn main() {
+    foo(|x| {
+            bar(|y| {
+                    baz(y);
+                });
+        });
+
+    let f = |x|    {
+                        x + 1
+                    };
+
+    run(1,2, |a, b| {
+        a + b
+    });
+
+    spawn(move ||   tick(1,2));
+}
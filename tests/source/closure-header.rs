@@ -0,0 +1,11 @@
+// Closure headers. The bodies are kept as written.
+
+fn main() {
+    let a = | x ,y | x + y;
+
+    let b = move||  {
+        foo()
+    };
+
+    let c = |aaaaaaaaaaaaaaaaaaaa: u32, bbbbbbbbbbbbbbbbbbbbbbbbb: u32, ccccccccccccccccccccc: u32| -> u32 { 0 };
+}
@@ -0,0 +1,16 @@
+// rustfmt-config: condition_double_indent.toml
+// Wrapped conditions are indented one level deeper than the body.
+
+fn main() {
+    if aaaaaaaaaaaaaaaaaaaaaaaaaaaaaa && bbbbbbbbbbbbbbbbbbbbbbbbbbbbbb && cccccccccccccccccccccccccccccc {
+        foo();
+    }
+
+    while xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx || yyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyy {
+        bar();
+    }
+
+    match  mmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmm + nnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnn {
+        _ => {}
+    }
+}
@@ -0,0 +1,26 @@
+// rustfmt-config: doc_comment_code.toml
+
+/// Adds one.
+///
+/// ```
+/// let x=add_one(1);
+/// let y=x+1;
+/// ```
+///
+/// ```text
+/// let  x=1;
+/// ```
+///
+/// ```rust,ignore
+/// let  x=1;
+/// ```
+fn add_one(x: u32) -> u32 {
+    x + 1
+}
+
+mod foo {
+    /// ```
+    /// fn nested(){bar(1,2)}
+    /// ```
+    fn bar(a: u32, b: u32) {}
+}
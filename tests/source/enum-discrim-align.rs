@@ -0,0 +1,14 @@
+// rustfmt-config: enum_discrim_align.toml
+// The discriminants of variants with names of up to 12 columns line up within
+// each group of variants.
+
+enum Color {
+    Red = 1,
+    Green = 2,
+    Blue   = 0x10,
+
+    Transparent = 0,
+    A = 1,
+    VeryLongVariantName = 2,
+    C,
+}
@@ -0,0 +1,10 @@
+// rustfmt-config: file_lines.toml
+// Only lines 6 to 8 are formatted.
+
+fn  foo( a:i32 )   {}
+
+fn   bar( b:i32,   c:   i32 ) -> i32 {
+    b  +  c
+}
+
+fn baz(  ) {}
@@ -0,0 +1,28 @@
+// rustfmt-config: item_spacing.toml
+// Blank lines around fns, impls and mods.
+
+mod a;
+mod b;
+fn foo() {
+    bar();
+}
+
+
+
+fn baz() {
+    qux();
+}
+struct Foo;
+impl Foo {
+
+    fn new() -> Foo {
+        Foo
+    }
+    // Comments stay with the method below them.
+    fn get(&self) -> u32 {
+        0
+    }
+    /* So do block comments,
+     * however many lines they take. */
+    fn set(&mut self) {}
+}
@@ -0,0 +1,10 @@
+struct Foo {
+    a: u32,
+    b: u32,
+
+    // Second group.
+    c: u32,
+
+
+    d: u32,
+}
@@ -0,0 +1,13 @@
+// rustfmt-config: literals.toml
+// Numeric literals are normalized, other literals are left alone.
+
+fn main() {
+    let a = 0xdeadBEEFu32;
+    let b = 1.5E10f64;
+    let c = 1000000;
+    let d = 1_0000_i64;
+    let e = 123456.789;
+    let f = 0b1010_1010u8;
+    let g = 'E';
+    let h = "0xff";
+}
@@ -22,4 +22,12 @@ let x = loop { do_forever(); };
     {
         x.update();
     }
+
+    'outer :loop {
+        'inner: for x in xs {
+            if x { break   'outer; }
+            continue  'inner;
+        }
+        break;
+    }
 }
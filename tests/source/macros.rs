@@ -0,0 +1,26 @@
+macro_rules! my_macro {
+    ( $x:expr ) => (   $x   );
+}
+
+my_items! {
+    fn  weird ( ) ->    ! { panic!() }
+    struct   Foo; // not formatted
+}
+
+fn main() {
+    let   x   =  1;
+    foo!( a   =>   b ,  /* comment */ c );
+    bar![ 1,2,
+          3 ];
+    let y = baz!(  @ weird tokens ;; );
+    quux!{
+        x   y   z
+    }
+    println!( "{} {}" ,  x,y );
+    let s = format!("a very long format string which takes up most of the line: {} {}", aaaaaaaa, bbbbbbbbbb);
+    panic!("this format string is far too long to fit on one line, but rustfmt never splits it: {}", x);
+    println![ "{}" , x ];
+    let z = try!( foo(1,2) );
+    try!(write!(out, "{}{}{}{}", aaaaaaaaaaaaaaaaaaaa, bbbbbbbbbbbbbbbbbbbbbbbbbbbbb, cccccccccccccccccccccccccccccc, dddddddddddddd));
+    println!( "{}" ,  => x );
+}
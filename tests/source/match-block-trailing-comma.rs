@@ -0,0 +1,10 @@
+// rustfmt-config: match_block_trailing_comma.toml
+
+fn main() {
+    match x {
+        Some(y) => {
+            y
+        }
+        None => 0
+    }
+}
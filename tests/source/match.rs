@@ -0,0 +1,25 @@
+// Only the scrutinee of a match and the commas after its arms are formatted.
+
+fn main() {
+    match  mmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmm + nnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnn {
+        _ => {}
+    }
+
+    match  (x,y) {
+        (1, _) =>  {}
+        _ => {}
+    }
+
+    match x {
+        Some(y) => y,
+        None => {
+            0
+        },
+        // Comments stay put.
+        Ok(z) => z /* before the comma */,
+        Ok(_) => {
+            1
+        } /* before the comma */,
+        Err(e) => e // after
+    }
+}
@@ -0,0 +1,11 @@
+//! Crate level docs.
+//!    Indented markdown is kept as it is.
+#![allow(dead_code)]
+
+mod foo {
+        //! Docs for foo, which are
+      //! badly indented.
+   #![allow(unused_imports)]
+
+    use  a::b ;
+}
@@ -11,3 +11,8 @@ mod mod3a;
 mod mymod2;
 
 mod submod2;
+
+#[path="mymod1"]
+mod mymod3 {
+    mod mod3b;
+}
@@ -0,0 +1,2 @@
+// Found through the path attribute on the enclosing inline mod
+fn b(   x:u32 ) {       }
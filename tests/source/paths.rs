@@ -20,3 +20,7 @@ fn main() {
 }
 
 fn op(foo: Bar, key : &[u8], upd : Fn(Option<&memcache::Item> , Baz  ) -> Result) -> MapResult {}
+
+fn nested() {
+    let x = SomeLongTypeName::<AnotherLongTypeName<FirstParameter,SecondParameter>, YetAnotherTypeName<u32>>::new();
+}
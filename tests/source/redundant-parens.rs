@@ -0,0 +1,21 @@
+// rustfmt-config: redundant_parens.toml
+
+fn main() {
+    if (x) {
+        return (a + b);
+    }
+    while ((ready)) {
+        wait();
+    }
+    match (value) {
+        _ => {}
+    }
+    let y = ((a + b)) * c;
+    let z = (a + b) * c;
+    if (Foo { a: 1 } == foo) {
+        return;
+    }
+    if (/* comment */ x) {
+        return (y /* kept */);
+    }
+}
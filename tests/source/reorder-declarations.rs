@@ -0,0 +1,28 @@
+// rustfmt-config: reorder_declarations.toml
+// Declarations go to the top of each module.
+
+use std::fmt;
+
+fn main() {}
+
+mod foo {
+    fn bar() {}
+
+    use std::io;
+    extern crate baz;
+}
+
+#[cfg(test)]
+mod tests {}
+#[cfg(test)]
+use tests::helper;
+
+extern crate qux;
+
+// Nothing moves past a macro.
+macro_rules! m {
+    () => {}
+}
+
+use after::m;
+mod after {}
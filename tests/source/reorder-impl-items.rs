@@ -0,0 +1,35 @@
+// rustfmt-config: reorder_impl_items.toml
+// Trait impl items follow the order of the trait.
+
+trait Shape {
+    type Unit;
+
+    fn area(&self) -> f64;
+
+    fn name(&self) -> String;
+}
+
+struct Square;
+
+impl Shape for Square {
+    // The name.
+    fn name(&self) -> String {
+        "square".to_owned()
+    }
+
+    #[inline]
+    fn area(&self) -> f64 {
+        1.0
+    }
+
+    type Unit = f64;
+}
+
+// Traits from elsewhere are left alone.
+impl Clone for Square {
+    fn clone_from(&mut self, _: &Square) {}
+
+    fn clone(&self) -> Square {
+        Square
+    }
+}
@@ -0,0 +1,27 @@
+// rustfmt-config: redundant_semicolons.toml
+
+foo!(a, b) ;
+bar! { c }
+
+fn main() {
+    for i in 0..10 {
+        print(i);
+    };
+    if x {
+        y();
+    } else {
+        z();
+    };
+    // These can have values, so keep their semicolons.
+    if x {
+        1
+    } else {
+        2
+    };
+    match x {
+        _ => 5,
+    };
+    loop {
+        break;
+    };
+}
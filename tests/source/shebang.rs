@@ -0,0 +1,6 @@
+#!/usr/bin/env run-cargo-script
+//! A cargo script, the interpreter line must stay first.
+
+fn   main()   {
+    let x=1;
+}
@@ -0,0 +1,11 @@
+// Slice patterns and @ bindings
+
+fn main() {
+    if let [ first , .. ,last ] = xs {}
+
+    if let [head, tail ..] = xs {}
+
+    while let ref  x@[_, ..] = xs {}
+
+    if let [aaaaaaaaaaaaaaaaaaaaaaaaaaaaaa, bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb, ccccccccccccccccccccccccccc..] = xs {}
+}
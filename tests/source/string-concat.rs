@@ -0,0 +1,7 @@
+// String concatenations have one operand a line when they don't fit on one.
+
+fn main() {
+    let short = "a".to_owned()+"b"+  "c";
+    let long = "Lorem ipsum dolor sit amet, consectetur adipiscing elit".to_owned() + ", sed do eiusmod tempor incididunt" + " ut labore et dolore magna aliqua.";
+    let s = concat!("Lorem ipsum dolor sit amet, consectetur adipiscing elit", ", sed do eiusmod tempor incididunt", 42);
+}
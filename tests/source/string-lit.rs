@@ -17,5 +17,14 @@ fn main() -> &'static str {
 
     let really_long_variable_name = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
 
+    let raw = r#"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA "quoted" AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA"#;
+    let raw_lines = r##"first line
+                        "# second line, with \n left as written"##;
+    let bytes = b"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\n";
+
+    // Escapes are neither split nor re-escaped.
+    let escapes = "BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB\u{1F600}éBBBBBBBBBB";
+    let chars = ('é', '\u{e9}', b'\x7f', b"\xff\0");
+
     "stuff"
 }
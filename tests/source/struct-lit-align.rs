@@ -0,0 +1,13 @@
+// rustfmt-config: struct_lit_align.toml
+
+fn main() {
+    let x = Foo {
+        a: 1,
+        bb: 2,
+        ccc: 3,
+
+        really_long_field_name: 4,
+        dddddddddddddddddddddddd: some_function_with_a_long_name(),
+        e: 5,
+    };
+}
@@ -0,0 +1,10 @@
+// rustfmt-config: struct_lit_width.toml
+// Struct literals whose fields take up more than 16 columns go one field a
+// line, even where they would fit on one.
+
+fn main() {
+    let a = Point {x: 1, y: 2};
+    let b = Rect { top_left: a, bottom_right: a };
+    let c = Point { x: 1,
+                    y: 2 };
+}
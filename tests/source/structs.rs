@@ -68,3 +68,5 @@ struct Tuple(
     A, //Comment
     B
 );
+
+struct Short(#[cfg(test)] u32, u64);
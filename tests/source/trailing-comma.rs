@@ -0,0 +1,7 @@
+// rustfmt-config: trailing_comma.toml
+
+fn main() {
+    foo(1,2);
+    let names = [first_long_name(), second_long_name(), third_long_name(), fourth_long_name(), fifth_long_name()];
+    println!("{} {} {} {}", first_long_name(), second_long_name(), third_long_name(), fourth_long_name());
+}
@@ -0,0 +1,11 @@
+fn main() {
+    let x = 1; // after x
+        // trailing comment
+  /* block */
+}
+
+fn foo() {
+    bar()
+      // after the expression
+}
+  // end of file
@@ -14,11 +14,15 @@ extern crate rustfmt;
 extern crate diff;
 extern crate regex;
 
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::env;
 use std::fs;
-use std::io::{self, Read, BufRead, BufReader};
+use std::io::{self, Read, Write, BufRead, BufReader};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use rustfmt::*;
+use rustfmt::config::Config;
 
 fn get_path_string(dir_entry: io::Result<fs::DirEntry>) -> String {
     let path = dir_entry.ok().expect("Couldn't get DirEntry.").path();
@@ -66,6 +70,260 @@ fn idempotence_tests() {
     assert!(fails == 0, "{} idempotent tests failed", fails);
 }
 
+#[test]
+fn format_input_text() {
+    let config = default_config();
+    let input = Input::Text("fn main() {\n    foo(1,2);\n}\n".to_owned());
+    let (summary, file_map, _) = format_input(input, &config).unwrap();
+
+    assert!(summary.has_diff());
+    let text = file_map.values().next().unwrap().to_string();
+    assert_eq!(text, "fn main() {\n    foo(1, 2);\n}\n");
+
+    let bad_input = Input::Text("fn main() {".to_owned());
+    match format_input(bad_input, &config) {
+        Err(Error::Parse) => {}
+        _ => panic!("expected a parse error"),
+    }
+}
+
+#[test]
+fn verify_text() {
+    let mut config = default_config();
+    config.verify_ast = true;
+    let input = Input::Text("fn main() {\n    foo(1,2);\n}\n".to_owned());
+    let (summary, file_map, _) = format_input(input, &config).unwrap();
+
+    assert!(!summary.has_internal_errors());
+    let text = file_map.values().next().unwrap().to_string();
+    assert_eq!(text, "fn main() {\n    foo(1, 2);\n}\n");
+}
+
+#[test]
+fn profile_phases() {
+    let mut config = default_config();
+    config.profile = true;
+    let input = Input::Text("use a::b;\nstruct S;\nfn main() {\n    let x = 1;\n}\n".to_owned());
+    let (_, _, report) = format_input(input, &config).unwrap();
+
+    let profile = report.profile().to_string();
+    let phases = ["rewrite import", "rewrite struct", "rewrite fn signature", "rewrite statement"];
+    for phase in &phases {
+        assert!(profile.contains(phase), "no {} in:\n{}", phase, profile);
+    }
+}
+
+#[test]
+fn is_formatted_text() {
+    let config = default_config();
+
+    assert!(is_formatted(Input::Text("fn main() {\n    foo(1, 2);\n}\n".to_owned()), &config));
+    assert!(!is_formatted(Input::Text("fn main() {\n    foo(1,2);\n}\n".to_owned()), &config));
+    assert!(!is_formatted(Input::Text("fn main() {".to_owned()), &config));
+}
+
+#[test]
+fn byte_order_mark() {
+    let mut config = default_config();
+    let input = "\u{feff}fn main() {\n    foo(1,2);\n}\n";
+
+    let (_, file_map, _) = format_input(Input::Text(input.to_owned()), &config).unwrap();
+    let text = file_map.values().next().unwrap().to_string();
+    assert_eq!(text, "\u{feff}fn main() {\n    foo(1, 2);\n}\n");
+    assert!(is_formatted(Input::Text(text), &config));
+
+    config.keep_bom = false;
+    let (_, file_map, _) = format_input(Input::Text(input.to_owned()), &config).unwrap();
+    let text = file_map.values().next().unwrap().to_string();
+    assert_eq!(text, "fn main() {\n    foo(1, 2);\n}\n");
+}
+
+#[test]
+fn struct_lit_field_comment() {
+    let config = default_config();
+
+    assert_eq!(snippet::format_expr("Foo { x:x, z: /* z */ z }", &config).unwrap(),
+               "Foo { x: x, z: /* z */ z }");
+}
+
+// Macro arguments which don't parse are left as they are, without the parse
+// error reaching the user.
+#[test]
+fn unparseable_macro_args() {
+    let config = default_config();
+
+    assert_eq!(snippet::format_stmt("println!( \"{}\" ,  => x );", &config).unwrap(),
+               "println!( \"{}\" ,  => x );");
+    assert_eq!(snippet::format_stmt("try!( a b );", &config).unwrap(), "try!( a b );");
+}
+
+// A variant which can't fit is left as it was, rather than panicking.
+#[test]
+fn enum_variant_too_wide() {
+    let mut config = default_config();
+    config.max_width = 30;
+    config.ideal_width = 30;
+
+    let input = "enum E {\n    AVeryVeryLongVariantName = 1234567890,\n}";
+    assert_eq!(snippet::format_item(input, &config).unwrap(), input);
+}
+
+#[test]
+fn format_snippets() {
+    let config = default_config();
+
+    assert_eq!(snippet::format_expr("foo(1,2)", &config).unwrap(), "foo(1, 2)");
+    assert_eq!(snippet::format_stmt("foo(1,2);\nbar( );", &config).unwrap(),
+               "foo(1, 2);\nbar();");
+    assert_eq!(snippet::format_item("use foo::{b,a};", &config).unwrap(), "use foo::{b, a};");
+}
+
+#[test]
+fn rewrite_hook_veto() {
+    let config = default_config();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let hook_seen = seen.clone();
+    let hook: RewriteHook = Arc::new(move |event: &RewriteEvent| {
+        hook_seen.lock().unwrap().push((event.original.to_owned(), event.proposed.to_owned()));
+        false
+    });
+
+    let text = "fn main() {\n    foo(1,2);\n}\n";
+    let input = Input::Text(text.to_owned());
+    let (summary, file_map, _) = format_input_with_hook(input, &config, hook).unwrap();
+
+    assert!(!summary.has_diff());
+    assert_eq!(file_map.values().next().unwrap().to_string(), text);
+    assert!(seen.lock().unwrap().contains(&("foo(1,2)".to_owned(), "foo(1, 2)".to_owned())));
+}
+
+// Item rewrites can be vetoed too.
+#[test]
+fn rewrite_hook_veto_items() {
+    let config = default_config();
+    let hook: RewriteHook = Arc::new(|event: &RewriteEvent| event.kind != "struct");
+
+    let input = Input::Text("struct  Foo {a:u32}\nfn  main( ) {\n}\n".to_owned());
+    let (_, file_map, _) = format_input_with_hook(input, &config, hook).unwrap();
+    let text = file_map.values().next().unwrap().to_string();
+
+    assert!(text.starts_with("struct  Foo {a:u32}\n"));
+    assert!(text.contains("\nfn main() {"));
+}
+
+#[test]
+fn rewrite_pass() {
+    let config = default_config();
+    let mut plugins = Plugins::new();
+    plugins.add_pass("call",
+                     Arc::new(|event: &RewriteEvent, _: &Config| {
+                         Some(event.proposed.replace("foo", "bar"))
+                     }));
+
+    let input = Input::Text("fn main() {\n    foo(1,2);\n    baz(foo);\n}\n".to_owned());
+    let (_, file_map, _) = format_input_with_plugins(input, &config, plugins).unwrap();
+
+    assert_eq!(file_map.values().next().unwrap().to_string(),
+               "fn main() {\n    bar(1, 2);\n    baz(bar);\n}\n");
+}
+
+#[test]
+fn concurrent_session() {
+    fn source(i: usize, sep: &str) -> String {
+        format!("fn f{}() {{\n    foo(1,{}{});\n}}\n", i, sep, i)
+    }
+
+    let session = session::Session::new(default_config());
+    let threads: Vec<_> = (0..4)
+                              .map(|i| {
+                                  let session = session.clone();
+                                  thread::spawn(move || {
+                                      let input = Input::Text(source(i, ""));
+                                      let (_, file_map, _) = session.format(input).unwrap();
+                                      file_map.values().next().unwrap().to_string()
+                                  })
+                              })
+                              .collect();
+
+    for (i, thread) in threads.into_iter().enumerate() {
+        assert_eq!(thread.join().unwrap(), source(i, " "));
+    }
+}
+
+#[test]
+fn format_edited_ranges() {
+    let config = default_config();
+    let text = "fn a() {\n    foo(1,2);\n}\n\nfn b() {\n    bar(1,2);\n}\n";
+    let start = text.find("bar").unwrap();
+    let edits = edits::format_ranges(text, &[(start, start + 3)], &config).unwrap();
+
+    assert_eq!(edits,
+               vec![edits::TextEdit {
+                        lo: start - 4,
+                        hi: start + 10,
+                        replacement: "    bar(1, 2);\n".to_owned(),
+                    }]);
+}
+
+#[test]
+fn parse_error_in_one_crate() {
+    let config = default_config();
+    let dir = env::temp_dir().join("rustfmt_parse_error_in_one_crate");
+    fs::create_dir_all(&dir).unwrap();
+    let good = dir.join("good.rs");
+    let bad = dir.join("bad.rs");
+    fs::File::create(&good).unwrap().write_all(b"fn main() {}\n").unwrap();
+    fs::File::create(&bad).unwrap().write_all(b"fn main() {\n").unwrap();
+
+    let jobs = vec![(bad.to_str().unwrap().to_owned(), config.clone()),
+                    (good.to_str().unwrap().to_owned(), config.clone())];
+    let summary = parallel::run_files(jobs, WriteMode::List, &config, 2);
+
+    assert!(summary.has_parsing_errors());
+    assert!(!summary.has_internal_errors());
+    assert_eq!(summary.files_failed(), 1);
+}
+
+// Markdown files are written out together with the Rust files, so machine
+// readable output is a single document.
+#[test]
+fn markdown_and_rust_written_together() {
+    let config = default_config();
+    let dir = env::temp_dir().join("rustfmt_markdown_and_rust_written_together");
+    fs::create_dir_all(&dir).unwrap();
+    let rust = dir.join("lib.rs");
+    let doc = dir.join("README.md");
+    fs::File::create(&rust).unwrap().write_all(b"fn  main() {}\n").unwrap();
+    fs::File::create(&doc).unwrap().write_all(b"```rust\nfn  main(){}\n```\n").unwrap();
+
+    let jobs = vec![(doc.to_str().unwrap().to_owned(), config.clone()),
+                    (rust.to_str().unwrap().to_owned(), config.clone())];
+    let summary = parallel::run_files(jobs, WriteMode::Return(&record_written), &config, 2);
+
+    assert!(!summary.has_operational_errors());
+    WRITTEN.with(|written| {
+        let written = written.borrow();
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0][doc.to_str().unwrap()], "```rust\nfn main() {}\n```\n");
+        assert_eq!(written[0][rust.to_str().unwrap()], "fn main() {}\n");
+    });
+}
+
+// Results are written on the thread that called run_files.
+thread_local! {
+    static WRITTEN: RefCell<Vec<HashMap<String, String>>> = RefCell::new(Vec::new())
+}
+
+fn record_written(texts: HashMap<String, String>) {
+    WRITTEN.with(|written| written.borrow_mut().push(texts));
+}
+
+fn default_config() -> Config {
+    let mut def_config = String::new();
+    fs::File::open("default.toml").unwrap().read_to_string(&mut def_config).unwrap();
+    Config::from_toml(&def_config)
+}
+
 // For each file, run rustfmt and collect the output.
 // Returns the number of files checked and the number of failures.
 fn check_files<I>(files: I) -> (u32, u32)
@@ -96,14 +354,14 @@ fn print_mismatches(result: HashMap<String, String>) {
 static HANDLE_RESULT: &'static Fn(HashMap<String, String>) = &handle_result;
 
 pub fn idempotent_check(filename: String) -> Result<(), HashMap<String, String>> {
-    let config = get_config(&filename);
+    let config = Config::from_toml(&get_config(&filename));
     let args = vec!["rustfmt".to_owned(), filename];
     // this thread is not used for concurrency, but rather to workaround the issue that the passed
     // function handle needs to have static lifetime. Instead of using a global RefCell, we use
     // panic to return a result in case of failure. This has the advantage of smoothing the road to
     // multithreaded rustfmt
     thread::catch_panic(move || {
-        run(args, WriteMode::Return(HANDLE_RESULT), &config);
+        let _ = run(args, WriteMode::Return(HANDLE_RESULT), &config);
     }).map_err(|any|
         *any.downcast().ok().expect("Downcast failed.")
     )